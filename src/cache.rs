@@ -0,0 +1,517 @@
+//! The cache module manages the persistent on-disk cache of remote git repositories
+//! and of the options parsed from Nix files.
+//!
+//! Repositories are stored as bare clones keyed by their URL and are fetched
+//! incrementally on subsequent runs. Options are then read directly from the
+//! object database at the fetched revision.
+//!
+//! Parsed options are stored per documented directory, keyed by a hash of the
+//! contents of each file, so unchanged files aren't parsed again.
+
+use crate::error::NixDocError;
+use crate::OptionDoc;
+use gix::{progress::Discard, remote::fetch::Shallow, remote::Direction};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Name of the directory holding cached repositories inside the cache directory.
+const REPOS_DIR: &str = "repos";
+
+/// Name of the directory holding cached parse results inside the cache directory.
+const PARSE_DIR: &str = "parse";
+
+/// Determines the directory used for caching, defaulting to the platform cache
+/// directory (`$XDG_CACHE_HOME/nix-options-doc` on Linux).
+///
+/// # Arguments
+/// - `custom`: A user-provided cache directory that takes precedence over the default.
+///
+/// # Returns
+/// The path to the cache directory or an error if no cache directory could be determined.
+pub fn cache_dir(custom: Option<&Path>) -> Result<PathBuf, NixDocError> {
+    match custom {
+        Some(dir) => Ok(dir.to_path_buf()),
+        None => dirs::cache_dir()
+            .map(|dir| dir.join(env!("CARGO_PKG_NAME")))
+            .ok_or_else(|| {
+                NixDocError::InvalidPath("Unable to determine the user cache directory".to_string())
+            }),
+    }
+}
+
+/// Computes a short, readable and collision-resistant file name for a URL or path.
+fn cache_name(location: &str) -> String {
+    let slug: String = location
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .rsplit(['/', ':'])
+        .take(2)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect::<Vec<_>>()
+        .join("-")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+
+    format!("{}-{}", slug, hash_hex(location.as_bytes(), 12))
+}
+
+/// Hashes data into a hexadecimal string of the given length.
+fn hash_hex(data: &[u8], len: usize) -> String {
+    gix::objs::compute_hash(gix::hash::Kind::Sha1, gix::objs::Kind::Blob, data)
+        .to_hex_with_len(len)
+        .to_string()
+}
+
+/// Computes the path of the cached repository for the given URL.
+///
+/// # Arguments
+/// - `cache_dir`: The cache directory.
+/// - `url`: The URL of the remote repository.
+///
+/// # Returns
+/// A path made of a readable slug of the URL followed by a short hash to avoid collisions.
+pub fn repo_cache_path(cache_dir: &Path, url: &str) -> PathBuf {
+    cache_dir.join(REPOS_DIR).join(cache_name(url))
+}
+
+/// Fetches a remote repository into the cache, reusing previously fetched objects.
+///
+/// # Arguments
+/// - `cache_dir`: The cache directory.
+/// - `url`: The URL of the remote repository.
+/// - `branch`: The branch, tag or commit id to fetch, or the remote `HEAD` if None.
+/// - `depth`: The commit depth to fetch.
+/// - `offline`: If true, skips fetching and uses the cached revision as-is.
+///
+/// # Returns
+/// A tuple containing the path to the cached bare repository and the id of the fetched commit.
+pub fn fetch_repo(
+    cache_dir: &Path,
+    url: &str,
+    branch: Option<&str>,
+    depth: u32,
+    offline: bool,
+) -> Result<(PathBuf, String), NixDocError> {
+    let repo_path = repo_cache_path(cache_dir, url);
+    let source_ref = branch.unwrap_or("HEAD");
+    let tracking_ref = format!("refs/remotes/origin/{}", source_ref);
+
+    let created = !repo_path.exists();
+    let repo = if !created {
+        log::debug!("Using cached repository: {}", repo_path.display());
+        gix::open(&repo_path).map_err(NixDocError::git_error)?
+    } else if offline {
+        return Err(NixDocError::GitOperation(format!(
+            "Repository is not cached and --offline was specified: {}",
+            url
+        )));
+    } else {
+        log::debug!("Creating cached repository: {}", repo_path.display());
+        std::fs::create_dir_all(&repo_path)?;
+        let repo = gix::init_bare(&repo_path).map_err(NixDocError::git_error)?;
+
+        // Record the URL as `origin` so relative submodule URLs can be resolved
+        let config_path = repo_path.join("config");
+        let mut config = gix::config::File::from_path_no_includes(
+            config_path.clone(),
+            gix::config::Source::Local,
+        )
+        .map_err(NixDocError::git_error)?;
+        repo.remote_at(url)
+            .map_err(NixDocError::git_error)?
+            .save_as_to("origin", &mut config)
+            .map_err(NixDocError::git_error)?;
+        std::fs::write(&config_path, config.to_bstring())?;
+
+        gix::open(&repo_path).map_err(NixDocError::git_error)?
+    };
+
+    if offline {
+        log::info!("Offline mode, using cached revision of {}", url);
+    } else {
+        log::info!("Fetching {} ({})", url, source_ref);
+
+        let shallow = Shallow::DepthAtRemote(
+            std::num::NonZeroU32::new(depth)
+                .unwrap_or_else(|| std::num::NonZeroU32::new(1).unwrap()),
+        );
+
+        let fetched = repo
+            .remote_at(url)
+            .map_err(NixDocError::git_error)?
+            .with_refspecs(
+                Some(format!("+{}:{}", source_ref, tracking_ref).as_str()),
+                Direction::Fetch,
+            )
+            .map_err(NixDocError::git_error)?
+            .connect(Direction::Fetch)
+            .map_err(|e| NixDocError::GitClone(url.to_string(), e.to_string()))
+            .and_then(|connection| {
+                connection
+                    .prepare_fetch(Discard, Default::default())
+                    .map_err(|e| NixDocError::GitClone(url.to_string(), e.to_string()))
+            })
+            .and_then(|fetch| {
+                fetch
+                    .with_shallow(shallow)
+                    .receive(Discard, &gix::interrupt::IS_INTERRUPTED)
+                    .map_err(|e| NixDocError::GitClone(url.to_string(), e.to_string()))
+            });
+
+        // Don't leave an empty repository behind if the initial fetch failed
+        if let Err(e) = fetched {
+            if created {
+                std::fs::remove_dir_all(&repo_path)?;
+            }
+            return Err(e);
+        }
+    }
+
+    let commit = repo
+        .find_reference(tracking_ref.as_str())
+        .map_err(|_| {
+            NixDocError::GitOperation(format!(
+                "Revision `{}` of {} is not cached",
+                source_ref, url
+            ))
+        })?
+        .peel_to_id_in_place()
+        .map_err(NixDocError::git_error)?;
+
+    log::debug!("Using cached revision {} of {}", commit, url);
+    Ok((repo_path, commit.to_string()))
+}
+
+/// Fetches a specific commit of a remote repository into the cache, unless it is
+/// already available there.
+///
+/// # Arguments
+/// - `cache_dir`: The cache directory.
+/// - `url`: The URL of the remote repository.
+/// - `commit`: The id of the commit to fetch.
+/// - `depth`: The commit depth to fetch.
+/// - `offline`: If true, fails instead of fetching commits that aren't cached.
+///
+/// # Returns
+/// The path to the cached bare repository containing the commit.
+pub fn fetch_commit(
+    cache_dir: &Path,
+    url: &str,
+    commit: gix::ObjectId,
+    depth: u32,
+    offline: bool,
+) -> Result<PathBuf, NixDocError> {
+    let repo_path = repo_cache_path(cache_dir, url);
+    if let Ok(repo) = gix::open(&repo_path) {
+        if repo.has_object(commit) {
+            log::debug!("Commit {} of {} is cached", commit, url);
+            return Ok(repo_path);
+        }
+    }
+
+    let (repo_path, _) = fetch_repo(cache_dir, url, Some(&commit.to_string()), depth, offline)?;
+    Ok(repo_path)
+}
+
+/// Removes all cached repositories and parse results.
+///
+/// # Arguments
+/// - `cache_dir`: The cache directory.
+///
+/// # Returns
+/// The number of removed repositories or an IO error.
+pub fn clean(cache_dir: &Path) -> Result<usize, NixDocError> {
+    let parse_dir = cache_dir.join(PARSE_DIR);
+    if parse_dir.exists() {
+        std::fs::remove_dir_all(&parse_dir)?;
+    }
+
+    let repos_dir = cache_dir.join(REPOS_DIR);
+    if !repos_dir.exists() {
+        return Ok(0);
+    }
+
+    let count = std::fs::read_dir(&repos_dir)?.count();
+    std::fs::remove_dir_all(&repos_dir)?;
+    Ok(count)
+}
+
+/// The contents of a parse cache file.
+#[derive(Default, Serialize, Deserialize)]
+struct ParseCacheFile {
+    /// The version of the tool that wrote the file
+    version: String,
+
+    /// The options of each file, keyed by the hash of its contents
+    entries: HashMap<String, ParseCacheEntry>,
+}
+
+/// The cached options of a file.
+#[derive(Clone, Serialize, Deserialize)]
+struct ParseCacheEntry {
+    /// The path of the file (or module) the options were parsed from
+    source: String,
+
+    /// The hash of the contents of the file
+    content_hash: String,
+
+    /// The options of the file
+    options: Vec<OptionDoc>,
+}
+
+/// Reads the entries of a parse cache file, or none if it doesn't exist, is unreadable
+/// or was written by another version.
+fn read_entries(path: &Path) -> HashMap<String, ParseCacheEntry> {
+    std::fs::read(path)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<ParseCacheFile>(&data).ok())
+        .filter(|file| file.version == env!("CARGO_PKG_VERSION"))
+        .map(|file| file.entries)
+        .unwrap_or_default()
+}
+
+/// Persistent cache of the options parsed from the files of a directory.
+///
+/// Entries are keyed by a hash of the tool version, the file's path and contents and the
+/// variable replacements, so changed files are parsed again. Saving merges the entries used
+/// in a run with those on disk, dropping the entries of older contents of the files used in
+/// the run while keeping those of files only used by other runs (e.g. of other subdirectories).
+pub struct ParseCache {
+    /// The file the cache is stored in, or None if it is only kept in memory
+    path: Option<PathBuf>,
+
+    /// The entries loaded from the file
+    previous: HashMap<String, ParseCacheEntry>,
+
+    /// The entries used in this run
+    current: Mutex<HashMap<String, ParseCacheEntry>>,
+
+    /// The keys of the previous entries of files known not to have changed since, by file
+    unchanged: HashMap<String, String>,
+
+    /// The number of files whose options were found in the cache
+    hits: AtomicUsize,
+
+    /// The number of files that had to be parsed
+    misses: AtomicUsize,
+}
+
+impl ParseCache {
+    /// Opens the parse cache of a directory, starting empty if it doesn't exist, is
+    /// unreadable or was written by another version.
+    ///
+    /// # Arguments
+    /// - `cache_dir`: The cache directory.
+    /// - `dir`: The documented directory.
+    ///
+    /// # Returns
+    /// The parse cache of the directory.
+    pub fn open(cache_dir: &Path, dir: &Path) -> Self {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let path = cache_dir
+            .join(PARSE_DIR)
+            .join(format!("{}.json", cache_name(&dir.to_string_lossy())));
+
+        let previous = read_entries(&path);
+        log::debug!(
+            "Loaded {} cached files from {}",
+            previous.len(),
+            path.display()
+        );
+
+        Self {
+            path: Some(path),
+            previous,
+            current: Mutex::new(HashMap::new()),
+            unchanged: HashMap::new(),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Creates an empty parse cache that is never written to disk, used to reuse the
+    /// options of unchanged files between runs of the same process.
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            previous: HashMap::new(),
+            current: Mutex::new(HashMap::new()),
+            unchanged: HashMap::new(),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Starts a new run, making the entries used in this run available to the next one
+    /// and resetting the statistics.
+    ///
+    /// # Arguments
+    /// - `changed`: The files changed since this run (relative to the documented directory),
+    ///   if known. The options of the other files are then reused without reading them.
+    pub fn next_run(&mut self, changed: Option<&[String]>) {
+        if let Ok(current) = self.current.get_mut() {
+            self.previous = std::mem::take(current);
+        }
+
+        self.unchanged = match changed {
+            Some(changed) => self
+                .previous
+                .iter()
+                .filter(|(_, entry)| !changed.contains(&entry.source))
+                .map(|(key, entry)| (entry.source.clone(), key.clone()))
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        *self.hits.get_mut() = 0;
+        *self.misses.get_mut() = 0;
+    }
+
+    /// Returns the options of a file from the previous run without reading it, if the file
+    /// is known not to have changed since.
+    ///
+    /// # Arguments
+    /// - `source`: The path of the file relative to the documented directory.
+    ///
+    /// # Returns
+    /// The options of the file, or None if it must be read and looked up by its contents.
+    pub fn get_unchanged(&self, source: &str) -> Option<Vec<OptionDoc>> {
+        let key = self.unchanged.get(source)?;
+        let entry = self.previous.get(key)?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+
+        if let Ok(mut current) = self.current.lock() {
+            current.insert(key.clone(), entry.clone());
+        }
+        Some(entry.options.clone())
+    }
+
+    /// Returns the cached options of a file, or parses it and caches the result.
+    ///
+    /// # Arguments
+    /// - `source`: The path of the file (or module) relative to the documented directory.
+    /// - `content`: The contents of the file.
+    /// - `replacements`: The variable replacements used for parsing.
+    /// - `parse`: Extracts the options of the file.
+    ///
+    /// # Returns
+    /// The options of the file.
+    pub fn get_or_parse<F>(
+        &self,
+        source: &str,
+        content: &str,
+        replacements: &HashMap<String, String>,
+        parse: F,
+    ) -> Vec<OptionDoc>
+    where
+        F: FnOnce() -> Vec<OptionDoc>,
+    {
+        let mut replacements: Vec<_> = replacements
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        replacements.sort();
+
+        let mut data = env!("CARGO_PKG_VERSION").to_string();
+        for part in [source, content]
+            .into_iter()
+            .chain(replacements.iter().map(String::as_str))
+        {
+            data.push('\0');
+            data.push_str(part);
+        }
+        let key = hash_hex(data.as_bytes(), 40);
+
+        let options = match self.previous.get(&key) {
+            Some(entry) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                entry.options.clone()
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                parse()
+            }
+        };
+
+        if let Ok(mut current) = self.current.lock() {
+            current.insert(
+                key,
+                ParseCacheEntry {
+                    source: source.to_string(),
+                    content_hash: hash_hex(content.as_bytes(), 40),
+                    options: options.clone(),
+                },
+            );
+        }
+        options
+    }
+
+    /// Writes the entries used in this run to the cache file, unless the cache is only
+    /// kept in memory.
+    ///
+    /// The file is replaced atomically, so concurrent runs never leave a truncated file
+    /// behind; the last run to save wins for the files both of them parsed.
+    ///
+    /// # Arguments
+    /// - `prune`: Whether this run collected the options of the whole directory, so that
+    ///   the entries it didn't use belong to deleted, renamed or changed files and are
+    ///   dropped. Otherwise the entries are merged into the file.
+    ///
+    /// # Returns
+    /// An error if the cache file couldn't be written.
+    pub fn save(&self, prune: bool) -> Result<(), NixDocError> {
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        let current = match self.current.lock() {
+            Ok(current) => current.clone(),
+            Err(_) => return Ok(()),
+        };
+
+        let entries = if prune {
+            current
+        } else {
+            // Other runs may have saved since this one started, so merge with the file as it is now
+            let content_hashes: HashMap<_, _> = current
+                .values()
+                .map(|entry| (entry.source.as_str(), entry.content_hash.as_str()))
+                .collect();
+            let mut entries = read_entries(path);
+            entries.retain(|_, entry| {
+                content_hashes
+                    .get(entry.source.as_str())
+                    .is_none_or(|hash| *hash == entry.content_hash)
+            });
+            entries.extend(current);
+            entries
+        };
+
+        let file = ParseCacheFile {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            entries,
+        };
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(dir)?;
+        let mut temp_file = tempfile::NamedTempFile::new_in(dir)?;
+        temp_file
+            .write_all(&serde_json::to_vec(&file).map_err(NixDocError::serialization_error)?)?;
+        temp_file.persist(path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Returns the number of files found in the cache and of files parsed in this run.
+    pub fn stats(&self) -> (usize, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
@@ -0,0 +1,75 @@
+//! The check module compares generated documentation with the files it is written to.
+//!
+//! Differences are reported as a unified diff, so that CI logs show what changed
+//! without having to regenerate the documentation locally.
+
+use anstyle::{AnsiColor, Style};
+use std::path::Path;
+
+/// Exit status of a check finding out of date documentation, distinct from the status
+/// of errors (1) and invalid arguments (2).
+pub const EXIT_OUT_OF_DATE: i32 = 3;
+
+/// Number of unchanged lines shown around each change.
+const CONTEXT_LINES: usize = 3;
+
+/// Style of file headers and hunk headers.
+const HEADER: Style = AnsiColor::Cyan.on_default();
+
+/// Style of removed lines.
+const REMOVED: Style = AnsiColor::Red.on_default();
+
+/// Style of added lines.
+const ADDED: Style = AnsiColor::Green.on_default();
+
+/// Computes the unified diff turning the current content of a file into the generated one.
+///
+/// # Arguments
+/// - `current`: The current content of the file, empty if it doesn't exist.
+/// - `generated`: The generated content.
+/// - `path`: The path of the file, shown in the headers of the diff.
+///
+/// # Returns
+/// The unified diff, empty if the contents are equal.
+pub fn unified_diff(current: &str, generated: &str, path: &str) -> String {
+    // Like git, prefix relative paths so the diff can be applied with `patch -p1`
+    let (old, new) = if Path::new(path).is_relative() {
+        (format!("a/{}", path), format!("b/{}", path))
+    } else {
+        (path.to_string(), path.to_string())
+    };
+
+    similar::TextDiff::from_lines(current, generated)
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(&old, &new)
+        .to_string()
+}
+
+/// Colors the lines of a unified diff, for terminals.
+///
+/// # Arguments
+/// - `diff`: The unified diff.
+///
+/// # Returns
+/// The diff with removed lines in red, added lines in green and headers in cyan.
+pub fn colorize(diff: &str) -> String {
+    diff.split_inclusive('\n')
+        .map(|line| {
+            let style =
+                if line.starts_with("---") || line.starts_with("+++") || line.starts_with("@@") {
+                    HEADER
+                } else if line.starts_with('-') {
+                    REMOVED
+                } else if line.starts_with('+') {
+                    ADDED
+                } else {
+                    return line.to_string();
+                };
+            let (content, newline) = line
+                .strip_suffix('\n')
+                .map_or((line, ""), |content| (content, "\n"));
+            format!("{style}{}{style:#}{}", content, newline)
+        })
+        .collect()
+}
@@ -20,9 +20,6 @@ pub enum NixDocError {
     #[error("Parsing error in file {0}: {1}")]
     Parse(String, String),
 
-    #[error("No repository work directory found")]
-    NoWorkDir,
-
     #[error("Not a valid local path or git repository: {0}")]
     InvalidPath(String),
 
@@ -41,6 +38,36 @@ pub enum NixDocError {
     #[error("Serialization error: {0}")]
     Serialization(String),
 
+    #[error("Watching for changes failed: {0}")]
+    Watch(String),
+
+    #[error("Unable to inject documentation: {0}")]
+    Inject(String),
+
+    #[error("Unable to check documentation: {0}")]
+    Check(String),
+
+    #[error("Documentation is out of date: {0}")]
+    OutOfDate(String),
+
+    #[error("Invalid group mapping: {0}")]
+    GroupMapping(String),
+
+    #[error("Unable to write the table of contents: {0}")]
+    TableOfContents(String),
+
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(String),
+
+    #[error("Invalid query: {0}")]
+    Query(String),
+
+    #[error("Option not found: {0}")]
+    OptionNotFound(String),
+
+    #[error("Server error: {0}")]
+    Server(String),
+
     #[error("UTF-8 conversion error: {0}")]
     Utf8(#[from] FromUtf8Error),
 }
@@ -91,6 +118,39 @@ impl NixDocError {
     pub fn serialization_error<E: std::fmt::Display>(err: E) -> Self {
         Self::with_message(err, NixDocError::Serialization)
     }
+
+    /// Creates a file watching error with the given error message.
+    ///
+    /// # Arguments
+    /// - `err`: Any error that implements Display.
+    ///
+    /// # Returns
+    /// A NixDocError::Watch variant with the formatted error message.
+    pub fn watch_error<E: std::fmt::Display>(err: E) -> Self {
+        Self::with_message(err, NixDocError::Watch)
+    }
+
+    /// Creates a group mapping error with the given error message.
+    ///
+    /// # Arguments
+    /// - `err`: Any error that implements Display.
+    ///
+    /// # Returns
+    /// A NixDocError::GroupMapping variant with the formatted error message.
+    pub fn group_mapping_error<E: std::fmt::Display>(err: E) -> Self {
+        Self::with_message(err, NixDocError::GroupMapping)
+    }
+
+    /// Creates a preview server error with the given error message.
+    ///
+    /// # Arguments
+    /// - `err`: Any error that implements Display.
+    ///
+    /// # Returns
+    /// A NixDocError::Server variant with the formatted error message.
+    pub fn server_error<E: std::fmt::Display>(err: E) -> Self {
+        Self::with_message(err, NixDocError::Server)
+    }
 }
 
 // Box<dyn Error> conversion
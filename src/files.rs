@@ -0,0 +1,282 @@
+//! The files module selects the Nix files to document.
+//!
+//! Files are matched by their path relative to the documented directory against the
+//! excluded directories, gitignore-style `--include` and `--exclude` patterns, and the
+//! `.nix-options-docignore` (and optionally `.gitignore`) files found along the way.
+//! Hidden files and directories are skipped unless requested otherwise.
+
+use crate::error::NixDocError;
+use crate::resolve_exclude_paths;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the files listing gitignore-style patterns of files that aren't documented.
+pub const IGNORE_FILE: &str = ".nix-options-docignore";
+
+/// Name of git's ignore files, honored when enabled.
+const GITIGNORE_FILE: &str = ".gitignore";
+
+/// Reads an ignore file given its path relative to the documented directory.
+type ReadIgnoreFile<'a> = Box<dyn Fn(&Path) -> Option<String> + 'a>;
+
+/// Controls which files under the documented directory are processed.
+#[derive(Clone, Debug, Default)]
+pub struct FileFilter {
+    /// Directories to exclude, absolute or relative to the documented directory
+    pub exclude_dirs: Vec<String>,
+
+    /// Gitignore-style patterns of the files to process, all files if empty
+    pub include: Vec<String>,
+
+    /// Gitignore-style patterns of the files to skip, where `!` re-includes files
+    pub exclude: Vec<String>,
+
+    /// Skip the files ignored by `.gitignore` files
+    pub gitignore: bool,
+
+    /// Process hidden files and directories
+    pub hidden: bool,
+}
+
+impl FileFilter {
+    /// Prepares matching the files of a directory on disk.
+    ///
+    /// # Arguments
+    /// - `dir`: The documented directory that paths are relative to.
+    ///
+    /// # Returns
+    /// The matcher, or an error if a pattern is invalid.
+    pub fn matcher(&self, dir: &Path) -> Result<FileMatcher<'static>, NixDocError> {
+        let root = dir.to_path_buf();
+        self.matcher_with(dir, move |path| {
+            std::fs::read_to_string(root.join(path)).ok()
+        })
+    }
+
+    /// Prepares matching the files of a directory, reading ignore files with a custom reader.
+    ///
+    /// # Arguments
+    /// - `dir`: The documented directory that paths are relative to.
+    /// - `read`: Reads an ignore file given its path relative to `dir`, or returns None if
+    ///   there is no such file.
+    ///
+    /// # Returns
+    /// The matcher, or an error if a pattern is invalid.
+    pub fn matcher_with<'a, F>(&self, dir: &Path, read: F) -> Result<FileMatcher<'a>, NixDocError>
+    where
+        F: Fn(&Path) -> Option<String> + 'a,
+    {
+        Ok(FileMatcher {
+            dir: dir.to_path_buf(),
+            exclude_paths: resolve_exclude_paths(dir, &self.exclude_dirs),
+            include: if self.include.is_empty() {
+                None
+            } else {
+                Some(build_patterns(&self.include)?)
+            },
+            exclude: build_patterns(&self.exclude)?,
+            gitignore: self.gitignore,
+            hidden: self.hidden,
+            read: Box::new(read),
+            ignore_files: RefCell::new(HashMap::new()),
+        })
+    }
+}
+
+/// Matches paths relative to a directory against a [`FileFilter`].
+pub struct FileMatcher<'a> {
+    /// The documented directory
+    dir: PathBuf,
+
+    /// Absolute paths of the excluded directories
+    exclude_paths: Vec<PathBuf>,
+
+    /// Patterns of the files to process, if restricted
+    include: Option<Gitignore>,
+
+    /// Patterns of the files to skip
+    exclude: Gitignore,
+
+    /// Whether `.gitignore` files are honored
+    gitignore: bool,
+
+    /// Whether hidden files and directories are processed
+    hidden: bool,
+
+    /// Reads ignore files by their path relative to the documented directory
+    read: ReadIgnoreFile<'a>,
+
+    /// The ignore files of each directory read so far, in order of precedence
+    ignore_files: RefCell<HashMap<PathBuf, Vec<Gitignore>>>,
+}
+
+impl FileMatcher<'_> {
+    /// Determines if a file should be processed.
+    ///
+    /// # Arguments
+    /// - `relative_path`: The path of the file relative to the documented directory.
+    /// - `is_file`: Whether the path refers to a regular file.
+    ///
+    /// # Returns
+    /// True if the path is a selected .nix file, false if it should be skipped.
+    pub fn is_selected(&self, relative_path: &Path, is_file: bool) -> bool {
+        if !is_file || relative_path.extension().is_none_or(|ext| ext != "nix") {
+            return false;
+        }
+        if self.is_excluded(relative_path, false) {
+            return false;
+        }
+
+        match self.include {
+            Some(ref include) if relative_path.is_relative() => include
+                .matched_path_or_any_parents(relative_path, false)
+                .is_ignore(),
+            _ => true,
+        }
+    }
+
+    /// Determines if a file or directory is excluded, either itself or through one of
+    /// its parent directories. Directories that are excluded don't need to be traversed.
+    ///
+    /// # Arguments
+    /// - `relative_path`: The path relative to the documented directory.
+    /// - `is_dir`: Whether the path refers to a directory.
+    ///
+    /// # Returns
+    /// True if the path is excluded, false otherwise.
+    pub fn is_excluded(&self, relative_path: &Path, is_dir: bool) -> bool {
+        let file_path = self.dir.join(relative_path);
+        if self
+            .exclude_paths
+            .iter()
+            .any(|excl| file_path.starts_with(excl))
+        {
+            log::debug!("Skipping excluded path: {}", file_path.display());
+            return true;
+        }
+
+        // Paths outside of the directory can only be excluded by directory
+        if !relative_path.is_relative() {
+            return false;
+        }
+
+        let mut path = PathBuf::new();
+        let mut components = relative_path.components().peekable();
+        while let Some(component) = components.next() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+            path.push(name);
+
+            let is_last = components.peek().is_none();
+            let name = name.to_string_lossy();
+            if name == ".git" || (!self.hidden && name.starts_with('.')) {
+                return true;
+            }
+            if self.is_ignored(&path, !is_last || is_dir) {
+                log::debug!("Skipping ignored path: {}", path.display());
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Determines if a path refers to an ignore file that affects which files are processed.
+    ///
+    /// # Arguments
+    /// - `relative_path`: The path relative to the documented directory.
+    ///
+    /// # Returns
+    /// True if the path is a `.nix-options-docignore` file, or a `.gitignore` file if they
+    /// are honored, outside of excluded directories.
+    pub fn is_ignore_file(&self, relative_path: &Path) -> bool {
+        let is_ignore_file = relative_path
+            .file_name()
+            .is_some_and(|name| name == IGNORE_FILE || (self.gitignore && name == GITIGNORE_FILE));
+        is_ignore_file
+            && relative_path
+                .parent()
+                .is_some_and(|parent| !self.is_excluded(parent, true))
+    }
+
+    /// Determines if a path is ignored by the patterns of the command line or of the
+    /// ignore files of its parent directories, without looking at the parents themselves.
+    fn is_ignored(&self, relative_path: &Path, is_dir: bool) -> bool {
+        match self.exclude.matched(relative_path, is_dir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+
+        // Ignore files in deeper directories take precedence
+        for parent in relative_path.ancestors().skip(1) {
+            let mut ignore_files = self.ignore_files.borrow_mut();
+            let matchers = ignore_files
+                .entry(parent.to_path_buf())
+                .or_insert_with(|| self.read_ignore_files(parent));
+            let path = relative_path.strip_prefix(parent).unwrap_or(relative_path);
+            for matcher in matchers.iter() {
+                match matcher.matched(path, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Reads the ignore files of a directory, in order of precedence.
+    fn read_ignore_files(&self, relative_dir: &Path) -> Vec<Gitignore> {
+        let mut names = vec![IGNORE_FILE];
+        if self.gitignore {
+            names.push(GITIGNORE_FILE);
+        }
+
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let path = relative_dir.join(name);
+                let content = (self.read)(&path)?;
+                let lines: Vec<String> = content.lines().map(str::to_string).collect();
+                match build_patterns(&lines) {
+                    Ok(matcher) => Some(matcher),
+                    Err(e) => {
+                        log::warn!("Skipping invalid ignore file {}: {}", path.display(), e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Compiles gitignore-style patterns, matched against paths relative to their directory.
+fn build_patterns(patterns: &[String]) -> Result<Gitignore, NixDocError> {
+    let mut builder = GitignoreBuilder::new("");
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|e| NixDocError::InvalidPattern(e.to_string()))?;
+    }
+    builder
+        .build()
+        .map_err(|e| NixDocError::InvalidPattern(e.to_string()))
+}
+
+/// Parses a gitignore-style pattern given on the command line.
+///
+/// # Arguments
+/// - `pattern`: The pattern to validate.
+///
+/// # Returns
+/// The pattern, or an error describing why it is invalid.
+pub fn parse_pattern(pattern: &str) -> Result<String, NixDocError> {
+    build_patterns(&[pattern.to_string()])?;
+    Ok(pattern.to_string())
+}
@@ -14,7 +14,7 @@ pub fn generate_csv(options: &[OptionDoc]) -> Result<String, NixDocError> {
         .from_writer(vec![]);
 
     // Write header - handle CSV errors directly
-    if let Err(err) = wtr.write_record([
+    let header = [
         "Option",
         "Type",
         "Default",
@@ -22,7 +22,10 @@ pub fn generate_csv(options: &[OptionDoc]) -> Result<String, NixDocError> {
         "Description",
         "FilePath",
         "LineNumber",
-    ]) {
+        "Module",
+        "Condition",
+    ];
+    if let Err(err) = wtr.write_record(header) {
         return Err(NixDocError::Csv(err.to_string()));
     }
 
@@ -35,16 +38,22 @@ pub fn generate_csv(options: &[OptionDoc]) -> Result<String, NixDocError> {
             .map(|d| d.replace('\n', " ").replace('\r', ""))
             .unwrap_or_else(|| "-".to_string());
 
-        // Handle CSV errors directly
-        if let Err(err) = wtr.write_record([
-            &option.name,
-            &option.nix_type.to_string(),
+        let line_number = option.line_number.to_string();
+        // Module and Condition stay empty for options without an output name or condition
+        let record = [
+            option.name.as_str(),
+            &option.nix_type,
             default,
             option.example.as_deref().unwrap_or("-"),
             &description,
             &option.file_path,
-            &option.line_number.to_string(),
-        ]) {
+            &line_number,
+            option.module.as_deref().unwrap_or(""),
+            option.condition.as_deref().unwrap_or(""),
+        ];
+
+        // Handle CSV errors directly
+        if let Err(err) = wtr.write_record(record) {
             return Err(NixDocError::Csv(err.to_string()));
         }
     }
@@ -1,4 +1,5 @@
 use crate::error::NixDocError;
+use crate::generate::group_by_module;
 use crate::OptionDoc;
 use comrak::{markdown_to_html, ComrakOptions};
 
@@ -8,7 +9,7 @@ const HTML_TEMPLATE_HEADER: &str = r#"<!DOCTYPE html>
 <head>
     <meta charset="UTF-8">
     <meta name="viewport" content="width=device-width, initial-scale=1.0">
-    <title>NixOS Module Options</title>
+    <title>{title}</title>
     <style>
         body { 
             font-family: system-ui, -apple-system, sans-serif; 
@@ -25,6 +26,8 @@ const HTML_TEMPLATE_HEADER: &str = r#"<!DOCTYPE html>
             border-bottom: 1px solid #eee; 
         }
         h2 { margin-top: 0; }
+        h2.module { margin: 1.5em 0 1em; }
+        h3 { margin-top: 0; }
         .option-name { font-family: monospace; }
         a { color: #0366d6; text-decoration: none; }
         a:hover { text-decoration: underline; }
@@ -103,7 +106,7 @@ const HTML_TEMPLATE_HEADER: &str = r#"<!DOCTYPE html>
     </style>
 </head>
 <body>
-    <h1>NixOS Module Options</h1>
+    <h1>{title}</h1>
 "#;
 
 /// Formats a multiline code block for HTML output with proper syntax highlighting.
@@ -149,12 +152,13 @@ fn format_inline_code(label: &str, content: &str) -> String {
 ///
 /// # Arguments
 /// - `options`: A slice of option documentation entries to render as HTML.
+/// - `title`: The title of the document.
 ///
 /// # Returns
 /// A `Result` containing the complete HTML document with styling and navigation or an error.
-pub fn generate_html(options: &[OptionDoc]) -> Result<String, NixDocError> {
+pub fn generate_html(options: &[OptionDoc], title: &str) -> Result<String, NixDocError> {
     let mut output = String::with_capacity(options.len() * 800 + 500);
-    output.push_str(HTML_TEMPLATE_HEADER);
+    output.push_str(&HTML_TEMPLATE_HEADER.replace("{title}", &html_escape::encode_text(title)));
 
     // Set up markdown rendering options
     let mut comrak_options = ComrakOptions::default();
@@ -165,60 +169,25 @@ pub fn generate_html(options: &[OptionDoc]) -> Result<String, NixDocError> {
     comrak_options.extension.alerts = true;
     comrak_options.render.unsafe_ = true; // Allow HTML in markdown (if needed)
 
-    // Generate option entries
-    for option in options {
-        // Create a slug for the option ID from the name
-        let slug = option.name.replace(['.', ':'], "-");
-
-        // Start option section
-        output.push_str(&format!(
-            r#"    <div class="option" id="{}">
-        <h2><a href="{}#L{}" class="option-name">{}</a></h2>
-"#,
-            html_escape::encode_text(&slug),
-            html_escape::encode_text(&option.file_path),
-            option.line_number,
-            html_escape::encode_text(&option.name)
-        ));
-
-        // Description with markdown conversion
-        if let Some(description) = &option.description {
-            let html_description = markdown_to_html(description, &comrak_options);
-            output.push_str(&format!(
-                r#"        <div class="metadata">
-            {html_description}
-        </div>
-"#
-            ));
-        }
-
-        // Type information
-        if option.nix_type.contains('\n') || option.nix_type.len() > 72 {
-            output.push_str(&format_multiline_block("Type", &option.nix_type));
-        } else {
-            output.push_str(&format_inline_code("Type", &option.nix_type));
-        }
-
-        // Default value if available
-        if let Some(default) = &option.default_value {
-            if default.contains('\n') || default.len() > 72 {
-                output.push_str(&format_multiline_block("Default", default));
-            } else {
-                output.push_str(&format_inline_code("Default", default));
+    // Generate option entries, in one section per exported flake module
+    match group_by_module(options) {
+        Some(groups) => {
+            for (module, group) in groups {
+                output.push_str(&format!(
+                    "    <h2 class=\"module\" id=\"{}\"><code>{}</code></h2>\n\n",
+                    html_escape::encode_double_quoted_attribute(&module.replace(['.', ':'], "-")),
+                    html_escape::encode_text(module)
+                ));
+                for option in group {
+                    write_option(&mut output, option, "h3", &comrak_options);
+                }
             }
         }
-
-        // Example if available
-        if let Some(example) = &option.example {
-            if example.contains('\n') || example.len() > 72 {
-                output.push_str(&format_multiline_block("Example", example));
-            } else {
-                output.push_str(&format_inline_code("Example", example));
+        None => {
+            for option in options {
+                write_option(&mut output, option, "h2", &comrak_options);
             }
         }
-
-        // Close option div
-        output.push_str("    </div>\n\n");
     }
 
     // Add footer and close HTML
@@ -234,3 +203,74 @@ pub fn generate_html(options: &[OptionDoc]) -> Result<String, NixDocError> {
 
     Ok(output)
 }
+
+/// Writes the documentation of a single option.
+///
+/// # Arguments
+/// - `output`: The HTML document being generated.
+/// - `option`: The option to document.
+/// - `heading`: The HTML heading element for the option name (e.g. `h2`).
+/// - `comrak_options`: The options for rendering Markdown descriptions.
+fn write_option(
+    output: &mut String,
+    option: &OptionDoc,
+    heading: &str,
+    comrak_options: &ComrakOptions,
+) {
+    // Create a slug for the option ID from the name
+    let slug = option.name.replace(['.', ':'], "-");
+
+    // Start option section
+    output.push_str(&format!(
+        r#"    <div class="option" id="{}">
+        <{heading}><a href="{}" class="option-name">{}</a></{heading}>
+"#,
+        html_escape::encode_text(&slug),
+        html_escape::encode_double_quoted_attribute(&option.source_link()),
+        html_escape::encode_text(&option.name)
+    ));
+
+    // Description with markdown conversion
+    if let Some(description) = &option.description {
+        let html_description = markdown_to_html(description, comrak_options);
+        output.push_str(&format!(
+            r#"        <div class="metadata">
+            {html_description}
+        </div>
+"#
+        ));
+    }
+
+    // Condition the option is declared under
+    if let Some(condition) = &option.condition {
+        output.push_str(&format_inline_code("Declared if", condition));
+    }
+
+    // Type information
+    if option.nix_type.contains('\n') || option.nix_type.len() > 72 {
+        output.push_str(&format_multiline_block("Type", &option.nix_type));
+    } else {
+        output.push_str(&format_inline_code("Type", &option.nix_type));
+    }
+
+    // Default value if available
+    if let Some(default) = &option.default_value {
+        if default.contains('\n') || default.len() > 72 {
+            output.push_str(&format_multiline_block("Default", default));
+        } else {
+            output.push_str(&format_inline_code("Default", default));
+        }
+    }
+
+    // Example if available
+    if let Some(example) = &option.example {
+        if example.contains('\n') || example.len() > 72 {
+            output.push_str(&format_multiline_block("Example", example));
+        } else {
+            output.push_str(&format_inline_code("Example", example));
+        }
+    }
+
+    // Close option div
+    output.push_str("    </div>\n\n");
+}
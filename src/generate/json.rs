@@ -1,4 +1,5 @@
 use crate::error::NixDocError;
+use crate::generate::group_by_module;
 use crate::OptionDoc;
 
 /// Generates a pretty-printed JSON string documenting NixOS module options.
@@ -7,7 +8,17 @@ use crate::OptionDoc;
 /// - `options`: A slice of option documentation entries to be serialized to JSON.
 ///
 /// # Returns
-/// A `Result` containing the formatted JSON string or a serialization error.
+/// A `Result` containing the formatted JSON string or a serialization error. Options of
+/// exported flake modules are grouped in an object keyed by the output name.
 pub fn generate_json(options: &[OptionDoc]) -> Result<String, NixDocError> {
-    serde_json::to_string_pretty(options).map_err(|e| NixDocError::Serialization(e.to_string()))
+    let json = match group_by_module(options) {
+        Some(groups) => serde_json::to_string_pretty(
+            &groups
+                .into_iter()
+                .collect::<std::collections::BTreeMap<_, _>>(),
+        ),
+        None => serde_json::to_string_pretty(options),
+    };
+
+    json.map_err(|e| NixDocError::Serialization(e.to_string()))
 }
@@ -1,72 +1,486 @@
+use crate::error::NixDocError;
+use crate::generate::{
+    group_by_file, group_by_module, group_by_namespace, group_by_sections, Section,
+};
 use crate::OptionDoc;
+use std::collections::HashMap;
 use std::fmt::Write;
 
+/// Title of the section of options that belong to no other section.
+const OTHER_OPTIONS: &str = "Other options";
+
+/// How the options of a Markdown document are grouped into sections.
+#[derive(Clone, Debug, Default)]
+pub enum Grouping {
+    /// A section per flake module, if the options belong to any
+    #[default]
+    Default,
+
+    /// A section per file, introduced by the file's doc comment
+    File,
+
+    /// A section per namespace formed by the given number of leading name components
+    Namespace(usize),
+
+    /// The sections of a user mapping, introduced by their descriptions
+    Sections(Vec<Section>),
+}
+
+/// How the options of a section are laid out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MarkdownStyle {
+    /// A heading per option, followed by its details
+    #[default]
+    Headings,
+
+    /// A summary table, followed by the details of each option in collapsible blocks
+    Table,
+}
+
+/// Controls the layout of generated Markdown documents.
+#[derive(Clone, Debug, Default)]
+pub struct MarkdownOptions {
+    /// How options are grouped into sections
+    pub grouping: Grouping,
+
+    /// How the options of a section are laid out
+    pub style: MarkdownStyle,
+}
+
+/// An entry of the table of contents.
+struct TocEntry {
+    /// The nesting depth of the entry
+    depth: usize,
+
+    /// The heading line of the section
+    heading: String,
+
+    /// The text of the heading
+    label: String,
+
+    /// The number of options in the section
+    count: usize,
+}
+
 /// Generates a Markdown formatted string documenting NixOS module options.
 ///
 /// # Arguments
 /// - `options`: A slice of option documentation entries to be formatted as markdown.
+/// - `title`: The title of the document.
+/// - `settings`: The layout of the document.
 ///
 /// # Returns
 /// A `Result` containing the formatted Markdown string with headers, descriptions, and code blocks or an error.
 pub fn generate_markdown(
     options: &[OptionDoc],
+    title: &str,
+    settings: &MarkdownOptions,
 ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    let mut output = String::with_capacity(options.len() * 500 + 200);
-    output.push_str("# NixOS Module Options\n\n");
+    let mut body = String::with_capacity(options.len() * 500 + 200);
+    let mut toc = Vec::new();
 
-    for option in options {
-        // Option name as heading with link
-        writeln!(
-            output,
-            "\n## [`{}`]({}#L{})",
-            option.name, option.file_path, option.line_number
-        )?;
+    match group_by_module(options) {
+        // One section per exported flake module
+        Some(groups) => {
+            for (module, group) in groups {
+                let heading = format!("## `{}`", module);
+                writeln!(body, "\n{}", heading)?;
+                toc.push(TocEntry {
+                    depth: 0,
+                    label: heading[3..].to_string(),
+                    heading,
+                    count: group.len(),
+                });
+                write_sections(&mut body, &group, 3, settings, &mut toc)?;
+            }
+        }
+        None => {
+            let options: Vec<&OptionDoc> = options.iter().collect();
+            write_sections(&mut body, &options, 2, settings, &mut toc)?;
+        }
+    }
 
-        // Description with preserved formatting
-        if let Some(description) = &option.description {
-            // Since the description might already contain markdown, we include it directly
-            writeln!(output, "\n{}", description)?;
+    let mut output = String::with_capacity(body.len() + toc.len() * 80 + 400);
+    writeln!(output, "# {}\n", title)?;
+    if !matches!(settings.grouping, Grouping::Default) && !toc.is_empty() {
+        write_toc(&mut output, title, &toc, &body)?;
+    }
+    output.push_str(&body);
+
+    writeln!(
+        output,
+        "\n---\n*Generated with [{}]({})*",
+        env!("CARGO_PKG_NAME"),
+        option_env!("CARGO_PKG_REPOSITORY").unwrap_or(env!("CARGO_PKG_NAME"))
+    )?;
+
+    Ok(output)
+}
+
+/// Writes the documentation of a list of options, grouped into sections.
+///
+/// # Arguments
+/// - `output`: The Markdown document being generated.
+/// - `options`: The options to document.
+/// - `level`: The heading level of the sections.
+/// - `settings`: How the options are grouped into sections and laid out.
+/// - `toc`: The table of contents, extended with the written sections.
+///
+/// # Returns
+/// A `Result` indicating success or a formatting error.
+fn write_sections(
+    output: &mut String,
+    options: &[&OptionDoc],
+    level: usize,
+    settings: &MarkdownOptions,
+    toc: &mut Vec<TocEntry>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Sections nested in flake module sections are indented in the table of contents
+    let depth = level - 2;
+
+    let groups: Vec<(String, Option<String>, Vec<&OptionDoc>)> = match &settings.grouping {
+        Grouping::Default => return write_option_list(output, options, level, settings.style),
+        Grouping::File => group_by_file(options)
+            .into_iter()
+            .map(|(file, group)| (format!("`{}`", file), group[0].file_doc.clone(), group))
+            .collect(),
+        Grouping::Namespace(n) => group_by_namespace(options, *n)
+            .into_iter()
+            .map(|(namespace, group)| {
+                let label = if namespace.is_empty() {
+                    OTHER_OPTIONS.to_string()
+                } else {
+                    format!("`{}`", namespace)
+                };
+                (label, file_docs(&group), group)
+            })
+            .collect(),
+        Grouping::Sections(sections) => group_by_sections(options, sections)
+            .into_iter()
+            .map(|(section, group)| match section {
+                Some(section) => (section.title.clone(), section.description.clone(), group),
+                None => (OTHER_OPTIONS.to_string(), None, group),
+            })
+            .collect(),
+    };
+
+    for (label, intro, group) in groups {
+        let heading = format!("{} {}", "#".repeat(level), label);
+        writeln!(output, "\n{}", heading)?;
+        if let Some(intro) = intro {
+            writeln!(output, "\n{}", intro)?;
         }
+        write_option_list(output, &group, level + 1, settings.style)?;
+        toc.push(TocEntry {
+            depth,
+            heading,
+            label,
+            count: group.len(),
+        });
+    }
 
-        // Type information - escaped
-        if option.nix_type.contains('\n') || option.nix_type.len() > 72 {
-            // Multi-line or long type - use code block
-            writeln!(output, "\n**Type:**\n\n```nix\n{}\n```", option.nix_type)?;
-        } else {
-            // Single line type - use inline code
+    Ok(())
+}
+
+/// Joins the distinct doc comments of the files declaring a group of options.
+fn file_docs(options: &[&OptionDoc]) -> Option<String> {
+    let mut docs: Vec<&str> = Vec::new();
+    for (_, group) in group_by_file(options) {
+        if let Some(doc) = group[0].file_doc.as_deref() {
+            if !docs.contains(&doc) {
+                docs.push(doc);
+            }
+        }
+    }
+
+    (!docs.is_empty()).then(|| docs.join("\n\n"))
+}
+
+/// Writes a table of contents linking to the sections of the document.
+///
+/// # Arguments
+/// - `output`: The Markdown document being generated, ending with its title.
+/// - `title`: The title of the document.
+/// - `toc`: The entries of the table of contents, in order.
+/// - `body`: The sections of the document the entries refer to.
+///
+/// # Returns
+/// A `Result` indicating success, or an error if an entry has no heading in the body.
+fn write_toc(
+    output: &mut String,
+    title: &str,
+    toc: &[TocEntry],
+    body: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Anchors depend on all headings before them, since duplicates are numbered
+    let mut slugs = HashMap::new();
+    slug(&format!("# {}", title), &mut slugs);
+    slug("## Contents", &mut slugs);
+
+    writeln!(output, "## Contents\n")?;
+    let mut entries = toc.iter().peekable();
+    let mut in_code = false;
+    for line in body.lines() {
+        if line.starts_with("```") {
+            in_code = !in_code;
+        }
+        if in_code || !is_heading(line) {
+            continue;
+        }
+        let anchor = slug(line, &mut slugs);
+        if let Some(entry) = entries.next_if(|entry| entry.heading == line) {
             writeln!(
                 output,
-                "\n**Type:** `{}`",
-                option.nix_type.replace('`', "\\`")
+                "{}- [{}](#{}) ({} {})",
+                "  ".repeat(entry.depth),
+                entry.label,
+                anchor,
+                entry.count,
+                if entry.count == 1 {
+                    "option"
+                } else {
+                    "options"
+                }
             )?;
         }
+    }
+    // Every entry must link to a heading of the body
+    if let Some(entry) = entries.next() {
+        return Err(NixDocError::TableOfContents(format!(
+            "no heading `{}` in the document",
+            entry.heading
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Determines if a line of Markdown is an ATX heading.
+fn is_heading(line: &str) -> bool {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    (1..=6).contains(&level) && line[level..].starts_with(' ')
+}
+
+/// Computes the anchor GitHub assigns to a heading, numbering duplicates.
+///
+/// # Arguments
+/// - `heading`: The heading line, including its leading `#` characters.
+/// - `slugs`: The number of times each anchor was assigned before.
+///
+/// # Returns
+/// The anchor, without the leading `#`.
+fn slug(heading: &str, slugs: &mut HashMap<String, usize>) -> String {
+    lazy_static::lazy_static! {
+        static ref LINK: regex::Regex = regex::Regex::new(r"\[([^\]]*)\]\([^)]*\)").unwrap();
+    }
+
+    let text = LINK.replace_all(heading.trim_start_matches('#').trim(), "$1");
+    let base: String = text
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect();
+
+    let count = slugs.entry(base.clone()).or_insert(0);
+    let anchor = if *count == 0 {
+        base
+    } else {
+        format!("{}-{}", base, count)
+    };
+    *count += 1;
+    anchor
+}
+
+/// Writes the options of a section in the given style.
+///
+/// # Arguments
+/// - `output`: The Markdown document being generated.
+/// - `options`: The options of the section.
+/// - `level`: The heading level of the options.
+/// - `style`: How the options are laid out.
+///
+/// # Returns
+/// A `Result` indicating success or a formatting error.
+fn write_option_list(
+    output: &mut String,
+    options: &[&OptionDoc],
+    level: usize,
+    style: MarkdownStyle,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match style {
+        MarkdownStyle::Headings => {
+            for option in options {
+                write_option(output, option, &"#".repeat(level))?;
+            }
+        }
+        MarkdownStyle::Table => {
+            writeln!(output, "\n| Option | Type | Default | Description |")?;
+            writeln!(output, "|--------|------|---------|-------------|")?;
+            for option in options {
+                writeln!(
+                    output,
+                    "| [`{}`]({}) | {} | {} | {} |",
+                    option.name.replace('|', "\\|"),
+                    option.source_link(),
+                    table_code(&option.nix_type),
+                    option
+                        .default_value
+                        .as_deref()
+                        .map_or(String::new(), table_code),
+                    option
+                        .description
+                        .as_deref()
+                        .map_or(String::new(), first_sentence)
+                        .replace('|', "\\|")
+                )?;
+            }
 
-        // Default value if available - in code block to preserve formatting
-        if let Some(default) = &option.default_value {
-            if default.contains('\n') || default.len() > 72 {
-                // Multi-line or long default - use code block
-                writeln!(output, "\n**Default:**\n\n```nix\n{}\n```", default)?;
-            } else {
-                // Single line default - use inline code
-                writeln!(output, "\n**Default:** `{}`", default)?;
+            for option in options {
+                writeln!(
+                    output,
+                    "\n<details>\n<summary><code>{}</code></summary>",
+                    html_escape::encode_text(&option.name)
+                )?;
+                write_option_details(output, option)?;
+                writeln!(output, "\n</details>")?;
             }
         }
+    }
+
+    Ok(())
+}
+
+/// Formats a value as code inside a table cell, where pipes would end the cell and
+/// line breaks the row. Multi-line values keep their lines and indentation as HTML.
+fn table_code(value: &str) -> String {
+    if !value.contains('\n') && !value.contains('`') {
+        return format!("`{}`", value.replace('|', "\\|"));
+    }
 
-        if let Some(example) = &option.example {
-            if example.contains('\n') || example.len() > 72 {
-                writeln!(output, "\n**Example:**\n\n```nix\n{}\n```", example)?;
-            } else {
-                writeln!(output, "\n**Example:** `{}`", example)?;
+    let lines: Vec<String> = value
+        .lines()
+        .map(|line| {
+            let content = line.trim_start();
+            let indent = line.len() - content.len();
+            format!(
+                "{}{}",
+                "&nbsp;".repeat(indent),
+                html_escape::encode_text(content).replace('|', "&#124;")
+            )
+        })
+        .collect();
+    format!("<code>{}</code>", lines.join("<br>"))
+}
+
+/// Extracts the first sentence of a description, on a single line.
+fn first_sentence(description: &str) -> String {
+    let paragraph = description.trim().split("\n\n").next().unwrap_or_default();
+    let text = paragraph.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    // A sentence ends at a punctuation mark followed by a capitalized word, so that
+    // abbreviations like "e.g. foo" don't end it
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if matches!(c, '.' | '!' | '?') && chars.peek().is_some_and(|(_, next)| *next == ' ') {
+            let rest = &text[i + 2..];
+            if rest.starts_with(|c: char| c.is_uppercase()) {
+                return text[..=i].to_string();
             }
         }
     }
 
+    text
+}
+
+/// Writes the documentation of a single option.
+///
+/// # Arguments
+/// - `output`: The Markdown document being generated.
+/// - `option`: The option to document.
+/// - `heading`: The Markdown heading prefix for the option name (e.g. `##`).
+///
+/// # Returns
+/// A `Result` indicating success or a formatting error.
+fn write_option(
+    output: &mut String,
+    option: &OptionDoc,
+    heading: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Option name as heading with link
     writeln!(
         output,
-        "\n---\n*Generated with [{}]({})*",
-        env!("CARGO_PKG_NAME"),
-        option_env!("CARGO_PKG_REPOSITORY").unwrap_or(env!("CARGO_PKG_NAME"))
+        "\n{} [`{}`]({})",
+        heading,
+        option.name,
+        option.source_link()
     )?;
 
-    Ok(output)
+    write_option_details(output, option)
+}
+
+/// Writes the description, condition, type, default value and example of an option.
+///
+/// # Arguments
+/// - `output`: The Markdown document being generated.
+/// - `option`: The option to document.
+///
+/// # Returns
+/// A `Result` indicating success or a formatting error.
+pub fn write_option_details(
+    output: &mut String,
+    option: &OptionDoc,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Description with preserved formatting
+    if let Some(description) = &option.description {
+        // Since the description might already contain markdown, we include it directly
+        writeln!(output, "\n{}", description)?;
+    }
+
+    // Condition the option is declared under
+    if let Some(condition) = &option.condition {
+        writeln!(
+            output,
+            "\n**Declared if:** `{}`",
+            condition.replace('`', "\\`")
+        )?;
+    }
+
+    // Type information - escaped
+    if option.nix_type.contains('\n') || option.nix_type.len() > 72 {
+        // Multi-line or long type - use code block
+        writeln!(output, "\n**Type:**\n\n```nix\n{}\n```", option.nix_type)?;
+    } else {
+        // Single line type - use inline code
+        writeln!(
+            output,
+            "\n**Type:** `{}`",
+            option.nix_type.replace('`', "\\`")
+        )?;
+    }
+
+    // Default value if available - in code block to preserve formatting
+    if let Some(default) = &option.default_value {
+        if default.contains('\n') || default.len() > 72 {
+            // Multi-line or long default - use code block
+            writeln!(output, "\n**Default:**\n\n```nix\n{}\n```", default)?;
+        } else {
+            // Single line default - use inline code
+            writeln!(output, "\n**Default:** `{}`", default)?;
+        }
+    }
+
+    if let Some(example) = &option.example {
+        if example.contains('\n') || example.len() > 72 {
+            writeln!(output, "\n**Example:**\n\n```nix\n{}\n```", example)?;
+        } else {
+            writeln!(output, "\n**Example:** `{}`", example)?;
+        }
+    }
+
+    Ok(())
 }
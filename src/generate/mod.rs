@@ -13,3 +13,207 @@ pub use csv::generate_csv;
 pub use html::generate_html;
 pub use json::generate_json;
 pub use markdown::generate_markdown;
+
+use crate::error::NixDocError;
+use crate::OptionDoc;
+use globset::{Glob, GlobMatcher};
+use serde::Deserialize;
+
+/// Groups options by the flake output exporting them, keeping the order in which
+/// the outputs first appear.
+///
+/// # Arguments
+/// - `options`: A slice of option documentation entries.
+///
+/// # Returns
+/// The options of each output, or None if no option is tagged with an output.
+pub fn group_by_module(options: &[OptionDoc]) -> Option<Vec<(&str, Vec<&OptionDoc>)>> {
+    if options.iter().all(|option| option.module.is_none()) {
+        return None;
+    }
+
+    let mut groups: Vec<(&str, Vec<&OptionDoc>)> = Vec::new();
+    for option in options {
+        let module = option.module.as_deref().unwrap_or_default();
+        match groups.iter_mut().find(|(name, _)| *name == module) {
+            Some((_, group)) => group.push(option),
+            None => groups.push((module, vec![option])),
+        }
+    }
+
+    Some(groups)
+}
+
+/// Groups options by the file declaring them, keeping the order in which the files
+/// first appear.
+///
+/// # Arguments
+/// - `options`: The option documentation entries to group.
+///
+/// # Returns
+/// The path and options of each file.
+pub fn group_by_file<'a>(options: &[&'a OptionDoc]) -> Vec<(&'a str, Vec<&'a OptionDoc>)> {
+    let mut groups: Vec<(&str, Vec<&OptionDoc>)> = Vec::new();
+    for option in options {
+        match groups
+            .iter_mut()
+            .find(|(file, _)| *file == option.file_path)
+        {
+            Some((_, group)) => group.push(option),
+            None => groups.push((&option.file_path, vec![option])),
+        }
+    }
+
+    groups
+}
+
+/// Groups options by the namespace formed by the first components of their names
+/// (without the `options.` prefix), keeping the order in which the namespaces first appear.
+///
+/// # Arguments
+/// - `options`: The option documentation entries to group.
+/// - `depth`: The number of leading name components forming a namespace.
+///
+/// # Returns
+/// The namespace and options of each group, where options declared directly at the
+/// top level have an empty namespace.
+pub fn group_by_namespace<'a>(
+    options: &[&'a OptionDoc],
+    depth: usize,
+) -> Vec<(String, Vec<&'a OptionDoc>)> {
+    let mut groups: Vec<(String, Vec<&OptionDoc>)> = Vec::new();
+    for option in options {
+        let name = option.name.strip_prefix("options.").unwrap_or(&option.name);
+        let components: Vec<&str> = name.split('.').collect();
+        // The last component names the option itself
+        let namespace = components[..depth.min(components.len() - 1)].join(".");
+        match groups.iter_mut().find(|(ns, _)| *ns == namespace) {
+            Some((_, group)) => group.push(option),
+            None => groups.push((namespace, vec![option])),
+        }
+    }
+
+    groups
+}
+
+/// A section of the documentation defined by a user mapping.
+#[derive(Clone, Debug)]
+pub struct Section {
+    /// The title of the section
+    pub title: String,
+
+    /// An introduction to the options of the section
+    pub description: Option<String>,
+
+    /// Matchers of the names of the options in the section
+    patterns: Vec<NamePattern>,
+}
+
+/// Matches option names, without the `options.` prefix.
+#[derive(Clone, Debug)]
+enum NamePattern {
+    /// A namespace containing the option, or the option itself
+    Prefix(String),
+
+    /// A glob where `*` matches any text
+    Glob(GlobMatcher),
+}
+
+/// The JSON representation of a section in a mapping file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SectionSpec {
+    title: String,
+    options: Vec<String>,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+impl Section {
+    /// Parses a mapping of sections from JSON, given as an array of objects with a
+    /// `title`, the `options` in the section and an optional `description`.
+    ///
+    /// # Arguments
+    /// - `json`: The JSON mapping.
+    ///
+    /// # Returns
+    /// The sections in order, or an error if the mapping or one of its patterns is invalid.
+    pub fn parse_mapping(json: &str) -> Result<Vec<Section>, NixDocError> {
+        let specs: Vec<SectionSpec> =
+            serde_json::from_str(json).map_err(NixDocError::group_mapping_error)?;
+
+        specs
+            .into_iter()
+            .map(|spec| {
+                let patterns = spec
+                    .options
+                    .iter()
+                    .map(|pattern| {
+                        let pattern = pattern.strip_prefix("options.").unwrap_or(pattern);
+                        if pattern.contains(['*', '?', '[']) {
+                            Glob::new(pattern)
+                                .map(|glob| NamePattern::Glob(glob.compile_matcher()))
+                                .map_err(NixDocError::group_mapping_error)
+                        } else {
+                            Ok(NamePattern::Prefix(pattern.to_string()))
+                        }
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Section {
+                    title: spec.title,
+                    description: spec.description,
+                    patterns,
+                })
+            })
+            .collect()
+    }
+
+    /// Determines if an option belongs to the section.
+    ///
+    /// # Arguments
+    /// - `name`: The name of the option, with or without the `options.` prefix.
+    ///
+    /// # Returns
+    /// True if the name matches one of the patterns of the section.
+    pub fn contains(&self, name: &str) -> bool {
+        let name = name.strip_prefix("options.").unwrap_or(name);
+        self.patterns.iter().any(|pattern| match pattern {
+            NamePattern::Prefix(prefix) => name
+                .strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.')),
+            NamePattern::Glob(glob) => glob.is_match(name),
+        })
+    }
+}
+
+/// Groups options into the sections of a mapping, in the order of the mapping. Options
+/// belong to the first section containing them, and the others are gathered at the end.
+///
+/// # Arguments
+/// - `options`: The option documentation entries to group.
+/// - `sections`: The sections of the mapping.
+///
+/// # Returns
+/// The non-empty sections with their options, followed by the options of no section
+/// under None.
+pub fn group_by_sections<'a, 's>(
+    options: &[&'a OptionDoc],
+    sections: &'s [Section],
+) -> Vec<(Option<&'s Section>, Vec<&'a OptionDoc>)> {
+    let mut grouped: Vec<Vec<&OptionDoc>> = vec![Vec::new(); sections.len() + 1];
+    for option in options {
+        let index = sections
+            .iter()
+            .position(|section| section.contains(&option.name))
+            .unwrap_or(sections.len());
+        grouped[index].push(option);
+    }
+
+    sections
+        .iter()
+        .map(Some)
+        .chain([None])
+        .zip(grouped)
+        .filter(|(_, group)| !group.is_empty())
+        .collect()
+}
@@ -0,0 +1,343 @@
+//! The git module reads Nix files directly from the object database of git
+//! repositories.
+//!
+//! It walks the tree of a given revision and optionally recurses into
+//! submodules, fetching them into the repository cache when needed.
+
+use crate::cache;
+use crate::error::NixDocError;
+use crate::files::FileMatcher;
+use gix::remote::Direction;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Controls how submodules are fetched when recursing into them.
+pub struct SubmoduleOptions {
+    /// Directory to fetch submodule repositories into
+    pub cache_dir: PathBuf,
+
+    /// Git commit depth to fetch submodules with
+    pub depth: u32,
+
+    /// Only use submodule commits that are available locally
+    pub offline: bool,
+}
+
+/// A Nix file read from a git tree.
+pub struct TreeFile {
+    /// The content of the file
+    pub content: String,
+
+    /// Browsable URL of the file in its own repository, for files of submodules
+    pub source_url: Option<String>,
+}
+
+/// The repository a tree was read from, for trees of submodules.
+struct Origin {
+    /// URL of the submodule repository
+    url: String,
+
+    /// Commit of the submodule recorded in the superproject
+    commit: String,
+}
+
+/// Collects the .nix files of a git tree and its submodules.
+pub struct TreeReader<'a> {
+    /// Base directory that file paths are resolved against for filtering
+    pub dir: &'a Path,
+
+    /// Path to restrict processing to
+    pub walk_root: &'a Path,
+
+    /// Selects the files to process
+    pub files: &'a FileMatcher<'a>,
+
+    /// How to fetch submodules, or None to skip them
+    pub submodules: Option<&'a SubmoduleOptions>,
+}
+
+impl TreeReader<'_> {
+    /// Reads all matching .nix files of the tree and, if enabled, of its submodules.
+    ///
+    /// # Arguments
+    /// - `repo`: The repository containing the tree.
+    /// - `tree`: The root tree of the revision to read.
+    ///
+    /// # Returns
+    /// Pairs of file paths relative to the repository root and the files read, or a git error.
+    pub fn read(
+        &self,
+        repo: &gix::Repository,
+        tree: &gix::Tree<'_>,
+    ) -> Result<Vec<(String, TreeFile)>, NixDocError> {
+        // Like git, resolve relative submodule URLs against the repository itself
+        // if it has no default remote
+        let repo_url = repo
+            .find_default_remote(Direction::Fetch)
+            .and_then(Result::ok)
+            .and_then(|remote| remote.url(Direction::Fetch).map(|url| url.to_bstring()))
+            .map(|url| url.to_string())
+            .unwrap_or_else(|| {
+                repo.work_dir()
+                    .unwrap_or(repo.git_dir())
+                    .to_string_lossy()
+                    .into_owned()
+            });
+
+        let mut files = Vec::new();
+        self.read_tree(repo, tree, "", Some(&repo_url), None, &mut files)?;
+        Ok(files)
+    }
+
+    /// Recursively reads the .nix files of a tree, prefixing their paths with `prefix`.
+    fn read_tree(
+        &self,
+        repo: &gix::Repository,
+        tree: &gix::Tree<'_>,
+        prefix: &str,
+        repo_url: Option<&str>,
+        origin: Option<&Origin>,
+        files: &mut Vec<(String, TreeFile)>,
+    ) -> Result<(), NixDocError> {
+        let mut recorder = gix::traverse::tree::Recorder::default();
+        tree.traverse()
+            .breadthfirst(&mut recorder)
+            .map_err(NixDocError::git_error)?;
+
+        let mut gitmodules = None;
+
+        for entry in recorder.records {
+            let entry_path = entry.filepath.to_string();
+            let relative_path = join_path(prefix, &entry_path);
+            let file_path = self.dir.join(&relative_path);
+
+            if entry.mode.is_blob() {
+                if !file_path.starts_with(self.walk_root)
+                    || !self.files.is_selected(Path::new(&relative_path), true)
+                {
+                    continue;
+                }
+
+                let blob = repo
+                    .find_object(entry.oid)
+                    .map_err(NixDocError::git_error)?;
+                match String::from_utf8(blob.data.clone()) {
+                    Ok(content) => files.push((
+                        relative_path,
+                        TreeFile {
+                            content,
+                            source_url: origin
+                                .and_then(|o| browse_url(&o.url, &o.commit, &entry_path)),
+                        },
+                    )),
+                    Err(e) => log::error!("Error reading file {}: {}", relative_path, e),
+                }
+            } else if entry.mode.is_commit() {
+                let Some(options) = self.submodules else {
+                    continue;
+                };
+
+                // Skip submodules that can't contain any of the files to process
+                if self.files.is_excluded(Path::new(&relative_path), true)
+                    || !(file_path.starts_with(self.walk_root)
+                        || self.walk_root.starts_with(&file_path))
+                {
+                    continue;
+                }
+
+                let modules = match gitmodules {
+                    Some(ref modules) => modules,
+                    None => gitmodules.insert(read_gitmodules(repo, tree)?),
+                };
+
+                let Some((name, url)) = modules.get(&entry_path) else {
+                    log::warn!("Submodule {} is missing from .gitmodules", relative_path);
+                    continue;
+                };
+
+                let Some(url) = resolve_url(url, repo_url) else {
+                    log::warn!(
+                        "Unable to resolve relative URL `{}` of submodule {}",
+                        url,
+                        relative_path
+                    );
+                    continue;
+                };
+
+                log::debug!(
+                    "Reading submodule {} from {} at {}",
+                    relative_path,
+                    url,
+                    entry.oid
+                );
+
+                let sub_repo = open_submodule(repo, name, &url, entry.oid, options)?;
+                let sub_tree = sub_repo
+                    .find_object(entry.oid)
+                    .map_err(NixDocError::git_error)?
+                    .peel_to_tree()
+                    .map_err(NixDocError::git_error)?;
+
+                let sub_origin = Origin {
+                    url: url.clone(),
+                    commit: entry.oid.to_string(),
+                };
+                self.read_tree(
+                    &sub_repo,
+                    &sub_tree,
+                    &relative_path,
+                    Some(&url),
+                    Some(&sub_origin),
+                    files,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Joins a path inside a tree to the path of the tree itself.
+fn join_path(prefix: &str, path: &str) -> String {
+    if prefix.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", prefix, path)
+    }
+}
+
+/// Reads the `.gitmodules` file at the root of a tree.
+///
+/// # Arguments
+/// - `repo`: The repository containing the tree.
+/// - `tree`: The root tree of the revision.
+///
+/// # Returns
+/// A map of submodule paths to their names and URLs, empty if there is no `.gitmodules` file.
+fn read_gitmodules(
+    repo: &gix::Repository,
+    tree: &gix::Tree<'_>,
+) -> Result<HashMap<String, (String, String)>, NixDocError> {
+    let mut modules = HashMap::new();
+
+    let Some(entry) = tree
+        .lookup_entry_by_path(".gitmodules")
+        .map_err(NixDocError::git_error)?
+    else {
+        return Ok(modules);
+    };
+
+    let blob = repo
+        .find_object(entry.oid())
+        .map_err(NixDocError::git_error)?;
+    let file = gix::submodule::File::from_bytes(&blob.data, None, &Default::default())
+        .map_err(NixDocError::git_error)?;
+
+    for name in file.names() {
+        let url = file
+            .config()
+            .string(format!("submodule.{}.url", name))
+            .map(|url| url.to_string());
+        match (file.path(name), url) {
+            (Ok(path), Some(url)) => {
+                modules.insert(path.to_string(), (name.to_string(), url));
+            }
+            _ => log::warn!("Invalid submodule `{}` in .gitmodules", name),
+        }
+    }
+
+    Ok(modules)
+}
+
+/// Resolves a submodule URL relative to the URL of its superproject.
+///
+/// # Arguments
+/// - `url`: The URL of the submodule, possibly starting with `./` or `../`.
+/// - `base`: The URL of the superproject, if known.
+///
+/// # Returns
+/// The absolute URL of the submodule, or None if it is relative and the base is unknown.
+pub fn resolve_url(url: &str, base: Option<&str>) -> Option<String> {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return Some(url.to_string());
+    }
+
+    let mut base = base?.trim_end_matches('/').to_string();
+    let mut rest = url;
+    loop {
+        if let Some(stripped) = rest.strip_prefix("./") {
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("../") {
+            rest = stripped;
+            let pos = base.rfind(['/', ':'])?;
+            base.truncate(pos);
+        } else {
+            break;
+        }
+    }
+
+    Some(format!("{}/{}", base, rest))
+}
+
+/// Opens the repository of a submodule containing the given commit, preferring the
+/// superproject's local submodule repository and fetching into the cache otherwise.
+fn open_submodule(
+    repo: &gix::Repository,
+    name: &str,
+    url: &str,
+    commit: gix::ObjectId,
+    options: &SubmoduleOptions,
+) -> Result<gix::Repository, NixDocError> {
+    let local_path = repo.git_dir().join("modules").join(name);
+    if let Ok(local) = gix::open(&local_path) {
+        if local.has_object(commit) {
+            log::debug!("Using local submodule repository: {}", local_path.display());
+            return Ok(local);
+        }
+    }
+
+    let path = cache::fetch_commit(
+        &options.cache_dir,
+        url,
+        commit,
+        options.depth,
+        options.offline,
+    )?;
+    gix::open(path).map_err(NixDocError::git_error)
+}
+
+/// Builds a browsable URL for a file of a repository hosted on a forge.
+///
+/// # Arguments
+/// - `url`: The git URL of the repository (HTTPS, SSH or scp-like syntax).
+/// - `commit`: The commit to link to.
+/// - `path`: The path of the file inside the repository.
+///
+/// # Returns
+/// A URL of the form `https://<host>/<repo>/blob/<commit>/<path>`, or None for local repositories.
+pub fn browse_url(url: &str, commit: &str, path: &str) -> Option<String> {
+    let (host, repo_path) = if let Some((scheme, rest)) = url.split_once("://") {
+        if !matches!(scheme, "https" | "http" | "ssh" | "git") {
+            return None;
+        }
+        let (host, repo_path) = rest.split_once('/')?;
+        // Drop credentials and ports
+        let host = host.rsplit('@').next()?.split(':').next()?;
+        (host, repo_path)
+    } else {
+        // scp-like syntax: `user@host:owner/repo.git`
+        let (host, repo_path) = url.split_once(':')?;
+        (host.rsplit('@').next()?, repo_path)
+    };
+
+    let repo_path = repo_path.trim_end_matches('/').trim_end_matches(".git");
+    // Single letter hosts are Windows drive letters rather than scp-like URLs
+    if host.len() < 2 || repo_path.is_empty() {
+        return None;
+    }
+
+    Some(format!(
+        "https://{}/{}/blob/{}/{}",
+        host, repo_path, commit, path
+    ))
+}
@@ -0,0 +1,420 @@
+//! The imports module statically follows the `imports` of Nix modules to build
+//! the graph of modules reachable from a set of entry points.
+//!
+//! Only literal relative paths (e.g. `./a.nix` or `../b`) are followed, with
+//! directories resolving to their `default.nix`.
+
+use rnix::{SyntaxKind, SyntaxNode};
+use std::collections::{HashSet, VecDeque};
+use std::fmt::{self, Write};
+
+/// Flake outputs exporting modules, along with their legacy singular forms.
+pub const FLAKE_MODULE_OUTPUTS: [&str; 8] = [
+    "nixosModules",
+    "homeManagerModules",
+    "darwinModules",
+    "flakeModules",
+    "nixosModule",
+    "homeManagerModule",
+    "darwinModule",
+    "flakeModule",
+];
+
+/// A module of the import graph: a whole file, or an attribute of a flake's outputs.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ModuleRef {
+    /// The path of the file relative to the repository root
+    pub file: String,
+
+    /// The attribute path of the module inside the file, for inline flake modules
+    pub attr: Option<String>,
+}
+
+impl fmt::Display for ModuleRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.attr {
+            Some(ref attr) => write!(f, "{}#{}", self.file, attr),
+            None => write!(f, "{}", self.file),
+        }
+    }
+}
+
+/// The graph of modules reachable from a set of entry points.
+#[derive(Debug, Default)]
+pub struct ModuleGraph {
+    /// All reachable modules in the order they were discovered
+    pub modules: Vec<ModuleRef>,
+
+    /// Import edges between modules
+    pub edges: Vec<(ModuleRef, ModuleRef)>,
+}
+
+impl ModuleGraph {
+    /// Renders the import graph in Graphviz DOT format.
+    ///
+    /// # Returns
+    /// A DOT digraph with one node per module and one edge per import.
+    pub fn to_dot(&self) -> String {
+        let mut output = String::from("digraph imports {\n    node [shape=box];\n");
+        for module in &self.modules {
+            let _ = writeln!(output, "    {};", dot_id(&module.to_string()));
+        }
+        for (from, to) in &self.edges {
+            let _ = writeln!(
+                output,
+                "    {} -> {};",
+                dot_id(&from.to_string()),
+                dot_id(&to.to_string())
+            );
+        }
+        output.push_str("}\n");
+        output
+    }
+}
+
+/// Quotes a string for use as a DOT identifier.
+fn dot_id(id: &str) -> String {
+    format!("\"{}\"", id.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Parses an entry point given on the command line.
+///
+/// # Arguments
+/// - `entry`: A file path (e.g. `modules/default.nix`) or a flake output path such as
+///   `flake.nix#nixosModules.default` or `.#nixosModules.default`.
+/// - `base`: The directory the entry is relative to, relative to the repository root.
+///
+/// # Returns
+/// The module reference for the entry point.
+pub fn parse_entry(entry: &str, base: &str) -> ModuleRef {
+    let (file, attr) = match entry.split_once('#') {
+        Some((file, attr)) => {
+            let file = file.trim_end_matches('/');
+            let file = if file.is_empty() || file == "." {
+                "flake.nix".to_string()
+            } else if file.ends_with(".nix") {
+                file.to_string()
+            } else {
+                format!("{}/flake.nix", file)
+            };
+            (file, Some(attr.to_string()))
+        }
+        None => (entry.trim_end_matches('/').to_string(), None),
+    };
+
+    ModuleRef {
+        file: normalize_path(base, &file).unwrap_or(file),
+        attr,
+    }
+}
+
+/// Builds the graph of modules reachable from the entry points.
+///
+/// # Arguments
+/// - `entries`: The entry point modules.
+/// - `read`: Reads a file given its path relative to the repository root, returning None
+///   for directories, missing or excluded files.
+///
+/// # Returns
+/// The import graph of all modules that could be read.
+pub fn build_graph(entries: &[ModuleRef], read: &dyn Fn(&str) -> Option<String>) -> ModuleGraph {
+    let mut graph = ModuleGraph::default();
+    let mut seen = HashSet::new();
+    let mut queue: VecDeque<(Option<ModuleRef>, ModuleRef)> =
+        entries.iter().map(|entry| (None, entry.clone())).collect();
+
+    while let Some((parent, module)) = queue.pop_front() {
+        // Directories resolve to their `default.nix`
+        let (module, content) = match read(&module.file) {
+            Some(content) => (module, content),
+            None if module.attr.is_none() => {
+                let default = ModuleRef {
+                    file: format!("{}/default.nix", module.file),
+                    attr: None,
+                };
+                match read(&default.file) {
+                    Some(content) => (default, content),
+                    None => {
+                        log::warn!("Unable to read imported module: {}", module);
+                        continue;
+                    }
+                }
+            }
+            None => {
+                log::warn!("Unable to read flake: {}", module.file);
+                continue;
+            }
+        };
+
+        let root = rnix::Root::parse(&content).syntax();
+        let node = match module.attr {
+            Some(ref attr) => {
+                let Some(node) = find_attr(&root, attr) else {
+                    log::warn!("Flake output not found: {}", module);
+                    continue;
+                };
+
+                // Flake outputs referring to a file are followed to the file itself
+                let file = path_value(&node)
+                    .and_then(|path| normalize_path(&parent_dir(&module.file), &path));
+                if let Some(file) = file {
+                    queue.push_front((parent, ModuleRef { file, attr: None }));
+                    continue;
+                }
+                node
+            }
+            None => root,
+        };
+
+        if let Some(parent) = parent {
+            graph.edges.push((parent, module.clone()));
+        }
+        if !seen.insert(module.clone()) {
+            continue;
+        }
+        graph.modules.push(module.clone());
+
+        let dir = parent_dir(&module.file);
+        for import in import_paths(&node) {
+            match normalize_path(&dir, &import) {
+                Some(file) => {
+                    queue.push_back((Some(module.clone()), ModuleRef { file, attr: None }))
+                }
+                None => log::warn!(
+                    "Import `{}` in {} points outside of the repository",
+                    import,
+                    module
+                ),
+            }
+        }
+    }
+
+    graph
+}
+
+/// Finds the value of an attribute path anywhere in a Nix file, such as a flake output.
+///
+/// # Arguments
+/// - `node`: The syntax node to search in.
+/// - `attr`: The dot-separated attribute path to look for (e.g. `nixosModules.default`).
+///
+/// # Returns
+/// The syntax node of the attribute's value, if found.
+pub fn find_attr(node: &SyntaxNode, attr: &str) -> Option<SyntaxNode> {
+    find_attr_with_prefix(node, "", attr)
+}
+
+/// Searches for an attribute path, tracking the path of the enclosing attribute sets.
+fn find_attr_with_prefix(node: &SyntaxNode, prefix: &str, attr: &str) -> Option<SyntaxNode> {
+    for child in node.children() {
+        if child.kind() != SyntaxKind::NODE_ATTRPATH_VALUE {
+            if let Some(found) = find_attr_with_prefix(&child, "", attr) {
+                return Some(found);
+            }
+            continue;
+        }
+
+        let Some(key) = child
+            .children()
+            .find(|n| n.kind() == SyntaxKind::NODE_ATTRPATH)
+            .map(|n| attrpath_text(&n))
+        else {
+            continue;
+        };
+        let Some(value) = child.children().nth(1) else {
+            continue;
+        };
+
+        let key = if prefix.is_empty() {
+            key
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+
+        let found = if key == attr {
+            Some(value)
+        } else if attr.starts_with(&format!("{}.", key)) {
+            // Nested attribute sets (`nixosModules = { default = ...; }`)
+            find_attr_with_prefix(&value, &key, attr)
+        } else {
+            find_attr_with_prefix(&value, "", attr)
+        };
+
+        if found.is_some() {
+            return found;
+        }
+    }
+
+    None
+}
+
+/// Lists the modules exported by a flake, such as `nixosModules.default`.
+///
+/// # Arguments
+/// - `node`: The syntax node of the flake.
+///
+/// # Returns
+/// The attribute paths of the exported modules in the order they are defined.
+pub fn flake_module_outputs(node: &SyntaxNode) -> Vec<String> {
+    let mut outputs = Vec::new();
+    collect_module_outputs(node, &mut outputs);
+    outputs
+}
+
+/// Recursively collects the attribute paths of exported modules.
+fn collect_module_outputs(node: &SyntaxNode, outputs: &mut Vec<String>) {
+    for child in node.children() {
+        if child.kind() != SyntaxKind::NODE_ATTRPATH_VALUE {
+            collect_module_outputs(&child, outputs);
+            continue;
+        }
+
+        let Some(key) = child
+            .children()
+            .find(|n| n.kind() == SyntaxKind::NODE_ATTRPATH)
+            .map(|n| attrpath_text(&n))
+        else {
+            continue;
+        };
+        let Some(value) = child.children().nth(1) else {
+            continue;
+        };
+
+        let category = key.split('.').next().unwrap_or_default();
+        if !FLAKE_MODULE_OUTPUTS.contains(&category) {
+            collect_module_outputs(&value, outputs);
+            continue;
+        }
+
+        let mut push = |output: String| {
+            if !outputs.contains(&output) {
+                outputs.push(output);
+            }
+        };
+
+        if key != category || category.ends_with("Module") {
+            // `nixosModules.default = ...;` or `nixosModule = ...;`
+            push(key);
+        } else if value.kind() == SyntaxKind::NODE_ATTR_SET {
+            // `nixosModules = { default = ...; };`
+            for name in value
+                .children()
+                .filter(|n| n.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+                .filter_map(|n| n.children().find(|n| n.kind() == SyntaxKind::NODE_ATTRPATH))
+                .filter_map(|n| n.children().next())
+            {
+                push(format!(
+                    "{}.{}",
+                    key,
+                    name.text().to_string().trim_matches('"')
+                ));
+            }
+        } else {
+            log::warn!(
+                "Unable to list the modules of the `{}` flake output statically",
+                key
+            );
+        }
+    }
+}
+
+/// Joins the segments of an attribute path, stripping quotes from string segments.
+fn attrpath_text(node: &SyntaxNode) -> String {
+    node.children()
+        .map(|n| n.text().to_string().trim_matches('"').to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Extracts the path a value refers to, for values such as `./module.nix`,
+/// `import ./module.nix` or `(./module.nix)`.
+fn path_value(node: &SyntaxNode) -> Option<String> {
+    match node.kind() {
+        SyntaxKind::NODE_PATH => Some(node.text().to_string()),
+        SyntaxKind::NODE_PAREN => node.children().next().and_then(|n| path_value(&n)),
+        SyntaxKind::NODE_APPLY => {
+            let mut children = node.children();
+            let function = children.next()?;
+            if function.text() == "import" {
+                children.next().and_then(|n| path_value(&n))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Collects the literal paths imported by a module through `imports = [ ... ]`.
+///
+/// # Arguments
+/// - `node`: The syntax node of the module.
+///
+/// # Returns
+/// The raw relative path literals found in the `imports` lists of the module.
+pub fn import_paths(node: &SyntaxNode) -> Vec<String> {
+    let mut paths = Vec::new();
+
+    for attr in node
+        .descendants()
+        .filter(|n| n.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+    {
+        let is_imports = attr
+            .children()
+            .find(|n| n.kind() == SyntaxKind::NODE_ATTRPATH)
+            .is_some_and(|n| attrpath_text(&n) == "imports");
+        if !is_imports {
+            continue;
+        }
+
+        let Some(value) = attr.children().nth(1) else {
+            continue;
+        };
+
+        for path in value
+            .descendants()
+            .filter(|n| n.kind() == SyntaxKind::NODE_PATH)
+        {
+            let text = path.text().to_string();
+            if text.contains("${") {
+                log::warn!("Skipping interpolated import path: {}", text);
+            } else if text.starts_with("./") || text.starts_with("../") {
+                paths.push(text);
+            } else {
+                log::debug!("Skipping non-relative import path: {}", text);
+            }
+        }
+    }
+
+    paths
+}
+
+/// Returns the directory of a file path relative to the repository root.
+fn parent_dir(file: &str) -> String {
+    match file.rsplit_once('/') {
+        Some((dir, _)) => dir.to_string(),
+        None => String::new(),
+    }
+}
+
+/// Resolves a relative path against a directory, both relative to the repository root.
+///
+/// # Arguments
+/// - `dir`: The directory the path is relative to.
+/// - `path`: The relative path, possibly containing `.` and `..` segments.
+///
+/// # Returns
+/// The normalized path relative to the repository root, or None if it points outside of it.
+pub fn normalize_path(dir: &str, path: &str) -> Option<String> {
+    let mut segments: Vec<&str> = dir.split('/').filter(|s| !s.is_empty()).collect();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
@@ -0,0 +1,80 @@
+//! The inject module embeds generated documentation into existing files.
+//!
+//! The documentation replaces whatever is between a pair of marker comments, so the
+//! rest of a README or HTML page can be edited by hand and is left untouched.
+
+use crate::error::NixDocError;
+use crate::OutputFormat;
+
+/// Marks the start of the generated documentation in a file.
+pub const BEGIN_MARKER: &str = "<!-- BEGIN nix-options-doc -->";
+
+/// Marks the end of the generated documentation in a file.
+pub const END_MARKER: &str = "<!-- END nix-options-doc -->";
+
+/// Extracts the part of a generated document that is embedded into other files, leaving
+/// out the title and, for HTML, everything outside of the body.
+///
+/// # Arguments
+/// - `doc`: The generated document.
+/// - `format`: The format of the document.
+///
+/// # Returns
+/// The embeddable part of the document, or an error if the format can't be embedded.
+pub fn fragment(doc: &str, format: OutputFormat) -> Result<String, NixDocError> {
+    let content = match format {
+        OutputFormat::Markdown => doc
+            .strip_prefix("# ")
+            .and_then(|doc| doc.split_once('\n'))
+            .map_or(doc, |(_, rest)| rest),
+        OutputFormat::Html => {
+            let body = doc
+                .split_once("<body>")
+                .and_then(|(_, body)| body.rsplit_once("</body>"))
+                .map_or(doc, |(body, _)| body);
+            body.split_once("</h1>").map_or(body, |(_, rest)| rest)
+        }
+        OutputFormat::Json | OutputFormat::Csv => {
+            return Err(NixDocError::Inject(
+                "only Markdown and HTML documentation can be injected".to_string(),
+            ))
+        }
+    };
+
+    Ok(content.trim_matches('\n').to_string())
+}
+
+/// Replaces the content between the markers of a file with generated documentation.
+///
+/// # Arguments
+/// - `existing`: The current content of the file.
+/// - `fragment`: The documentation to put between the markers.
+///
+/// # Returns
+/// The updated content of the file, or an error if it doesn't contain exactly one pair
+/// of markers.
+pub fn inject(existing: &str, fragment: &str) -> Result<String, NixDocError> {
+    let begin = match existing.matches(BEGIN_MARKER).count() {
+        0 => return Err(NixDocError::Inject(format!("missing `{}`", BEGIN_MARKER))),
+        1 => existing.find(BEGIN_MARKER).unwrap_or_default() + BEGIN_MARKER.len(),
+        _ => return Err(NixDocError::Inject(format!("duplicate `{}`", BEGIN_MARKER))),
+    };
+    let end = match existing.matches(END_MARKER).count() {
+        0 => return Err(NixDocError::Inject(format!("missing `{}`", END_MARKER))),
+        1 => existing.find(END_MARKER).unwrap_or_default(),
+        _ => return Err(NixDocError::Inject(format!("duplicate `{}`", END_MARKER))),
+    };
+    if end < begin {
+        return Err(NixDocError::Inject(format!(
+            "`{}` comes before `{}`",
+            END_MARKER, BEGIN_MARKER
+        )));
+    }
+
+    Ok(format!(
+        "{}\n{}\n{}",
+        &existing[..begin],
+        fragment,
+        &existing[end..]
+    ))
+}
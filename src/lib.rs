@@ -1,11 +1,22 @@
+pub mod cache;
+pub mod check;
 pub mod error;
+pub mod files;
 pub mod generate;
+pub mod git;
+pub mod imports;
+pub mod inject;
+pub mod lsp;
 pub mod parser;
+pub mod query;
+pub mod search;
+pub mod serve;
+pub mod strings;
 pub mod utils;
+pub mod watch;
 
 use crate::error::NixDocError;
-use clap::{command, ArgGroup, Args, Parser};
-use gix::{progress::Discard, remote::fetch::Shallow};
+use clap::{ArgGroup, Args, Parser, Subcommand};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::{
@@ -28,6 +39,77 @@ pub enum OutputFormat {
     Csv,
 }
 
+/// Ways of grouping the options of Markdown documents into sections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    File,
+    Namespace,
+    Mapping,
+}
+
+/// Module systems with their own conventions for declaring options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ModuleSystem {
+    Nixos,
+    HomeManager,
+    Darwin,
+    FlakeParts,
+}
+
+impl ModuleSystem {
+    /// Returns the default title of documents generated for the module system.
+    pub fn title(&self) -> &'static str {
+        match self {
+            ModuleSystem::Nixos => "NixOS Module Options",
+            ModuleSystem::HomeManager => "Home Manager Module Options",
+            ModuleSystem::Darwin => "nix-darwin Module Options",
+            ModuleSystem::FlakeParts => "flake-parts Module Options",
+        }
+    }
+
+    /// Determines the module system of a module exported by a flake.
+    ///
+    /// # Arguments
+    /// - `output`: The flake output exporting the module (e.g. `nixosModules.default`).
+    ///
+    /// # Returns
+    /// The module system, or None if the output isn't a known module output.
+    pub fn from_flake_output(output: &str) -> Option<Self> {
+        match output.split('.').next()? {
+            "nixosModules" | "nixosModule" => Some(ModuleSystem::Nixos),
+            "homeManagerModules" | "homeManagerModule" => Some(ModuleSystem::HomeManager),
+            "darwinModules" | "darwinModule" => Some(ModuleSystem::Darwin),
+            "flakeModules" | "flakeModule" => Some(ModuleSystem::FlakeParts),
+            _ => None,
+        }
+    }
+
+    /// Normalizes the name of an option declared in a module of this module system.
+    ///
+    /// All module systems declare options under the top-level `options` attribute set.
+    /// flake-parts additionally declares per-system options in `perSystem` modules (or
+    /// with `mkPerSystemOption`), which are namespaced as `options.perSystem.<name>`.
+    ///
+    /// # Arguments
+    /// - `name`: The name of the option as found in the module.
+    ///
+    /// # Returns
+    /// The normalized option name, or None if the option is declared outside of the
+    /// option roots of the module system.
+    pub fn normalize_name(&self, name: &str) -> Option<String> {
+        if *self == ModuleSystem::FlakeParts {
+            let per_system = name
+                .strip_prefix("perSystem.options.")
+                .or_else(|| name.strip_prefix("options.perSystem.options."));
+            if let Some(per_system) = per_system {
+                return Some(format!("options.perSystem.{}", per_system));
+            }
+        }
+
+        name.starts_with("options.").then(|| name.to_string())
+    }
+}
+
 /// Command-line interface configuration and options.
 ///
 /// Contains all command-line arguments grouped by functionality.
@@ -45,6 +127,59 @@ pub struct Cli {
 
     #[command(flatten)]
     pub util: UtilityOptions,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Subcommands for tasks other than generating documentation.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Manage the cache of remote repositories
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommand,
+    },
+
+    /// Run a language server over stdio for the options of the module tree
+    Lsp,
+
+    /// Search the options by fuzzy matching their names and descriptions
+    Search {
+        /// Terms to match against the names and descriptions of options
+        #[arg(required = true)]
+        query: Vec<String>,
+
+        /// Maximum number of results to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+
+    /// Show an option in full
+    Show {
+        /// Name of the option (e.g. `services.nginx.enable`)
+        option: String,
+    },
+
+    /// Print a shell completion script
+    Completions {
+        /// Shell to generate the completion script for
+        shell: clap_complete::Shell,
+    },
+
+    /// Preview the HTML documentation on a local server, reloading it when files change
+    Serve {
+        /// Port to listen on at 127.0.0.1
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+    },
+}
+
+/// Cache management subcommands.
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Remove all cached repositories and parse results
+    Clean,
 }
 
 /// Input/output related command options.
@@ -61,6 +196,17 @@ pub struct IoOptions {
     #[arg(short, long, default_value = "stdout")]
     pub out: String,
 
+    /// Replace the content between the `<!-- BEGIN nix-options-doc -->`
+    /// and `<!-- END nix-options-doc -->` markers of the existing output
+    /// file instead of overwriting it (Markdown and HTML only)
+    #[arg(long)]
+    pub inject: bool,
+
+    /// Don't write the output file, but print a diff and fail
+    /// if it is out of date
+    #[arg(long, conflicts_with = "watch", requires = "out")]
+    pub check: bool,
+
     /// Output format
     #[arg(short = 'f', long, default_value = "markdown")]
     pub format: OutputFormat,
@@ -72,6 +218,92 @@ pub struct IoOptions {
     /// Prefix path or URL for the output options
     #[arg(long, value_name = "PATH")]
     pub out_prefix: Option<String>,
+
+    /// Subdirectory or file within the path to document (source
+    /// paths stay relative to the repository root). Remote URLs
+    /// also accept the `<url>//<subdir>` syntax
+    #[arg(long, value_name = "PATH")]
+    pub subdir: Option<String>,
+
+    /// Entry point module to follow `imports` from instead of
+    /// processing every file, relative to the subdirectory
+    /// (e.g. `modules/default.nix` or `.#nixosModules.default`,
+    /// can be specified multiple times)
+    #[arg(long, value_name = "MODULE")]
+    pub entry: Vec<String>,
+
+    /// Path to write the import graph of the entry points to
+    /// in DOT format, or 'stdout' to print only the graph
+    #[arg(long, value_name = "PATH", requires = "entry")]
+    pub import_graph: Option<String>,
+
+    /// Treat `flake.nix` like any other file instead of documenting
+    /// the modules exported by the flake separately
+    #[arg(long)]
+    pub no_flake: bool,
+
+    /// Module system the documented modules belong to, only keeping
+    /// options declared in its option roots (inferred from the
+    /// outputs of flakes)
+    #[arg(long, value_name = "SYSTEM")]
+    pub module_system: Option<ModuleSystem>,
+
+    /// Title of the generated document (defaults to a title
+    /// matching the module system)
+    #[arg(long)]
+    pub title: Option<String>,
+
+    /// Layout of the options of Markdown documents, where `table`
+    /// summarizes them in a table with collapsible details
+    #[arg(long, value_name = "STYLE", default_value = "headings")]
+    pub markdown_style: generate::markdown::MarkdownStyle,
+
+    /// Group the options of Markdown documents into sections
+    /// listed in a table of contents
+    #[arg(long, value_name = "MODE")]
+    pub group_by: Option<GroupBy>,
+
+    /// Number of leading components of option names forming
+    /// the namespaces of `--group-by namespace`
+    #[arg(long, value_name = "N", default_value = "2")]
+    #[arg(value_parser = clap::value_parser!(u16).range(1..))]
+    pub group_depth: u16,
+
+    /// JSON file mapping option names to the sections of
+    /// `--group-by mapping`
+    #[arg(long, value_name = "FILE")]
+    #[arg(required_if_eq("group_by", "mapping"))]
+    pub group_mapping: Option<PathBuf>,
+}
+
+impl IoOptions {
+    /// Determines the layout of generated Markdown documents.
+    ///
+    /// # Returns
+    /// The Markdown options, or an error if the mapping file couldn't be read or is invalid.
+    pub fn markdown_options(&self) -> Result<generate::markdown::MarkdownOptions, NixDocError> {
+        let grouping = match self.group_by {
+            None => generate::markdown::Grouping::Default,
+            Some(GroupBy::File) => generate::markdown::Grouping::File,
+            Some(GroupBy::Namespace) => {
+                generate::markdown::Grouping::Namespace(self.group_depth.into())
+            }
+            Some(GroupBy::Mapping) => {
+                // Required by clap with `--group-by mapping`
+                let path = self.group_mapping.as_deref().ok_or_else(|| {
+                    NixDocError::GroupMapping("--group-mapping is required".to_string())
+                })?;
+                let json = std::fs::read_to_string(path)
+                    .map_err(|e| NixDocError::GroupMapping(format!("{}: {}", path.display(), e)))?;
+                generate::markdown::Grouping::Sections(generate::Section::parse_mapping(&json)?)
+            }
+        };
+
+        Ok(generate::markdown::MarkdownOptions {
+            grouping,
+            style: self.markdown_style,
+        })
+    }
 }
 
 /// Git repository related command options.
@@ -87,12 +319,36 @@ pub struct GitOptions {
     /// Git commit depth (set to 1 for shallow clone)
     #[arg(short, long, default_value = "1")]
     pub depth: u32,
+
+    /// Git revision (commit, tag or branch) to read the files
+    /// from, without checking it out
+    #[arg(long, value_name = "REV")]
+    pub rev: Option<String>,
+
+    /// Clone remote repositories into a temporary directory
+    /// instead of the persistent cache
+    #[arg(long, conflicts_with = "offline")]
+    pub no_cache: bool,
+
+    /// Include the files of git submodules (requires reading
+    /// from a remote repository or using --rev)
+    #[arg(long)]
+    pub recurse_submodules: bool,
+
+    /// Use the cached copy of remote repositories without fetching
+    #[arg(long)]
+    pub offline: bool,
+
+    /// Directory to cache remote repositories in
+    /// (defaults to the user cache directory)
+    #[arg(long, value_name = "DIR", global = true)]
+    pub cache_dir: Option<PathBuf>,
 }
 
 /// Options for filtering and modifying the documentation output.
 ///
 /// Controls which options to include and how to format them.
-#[derive(Args)]
+#[derive(Args, Clone)]
 #[command(group(ArgGroup::new("filter")))]
 pub struct FilterOptions {
     /// Filter options by prefix (e.g. "services.nginx")
@@ -104,8 +360,8 @@ pub struct FilterOptions {
     pub filter_by_type: Option<String>,
 
     /// Search in option names and descriptions
-    #[arg(long, value_name = "OPTION")]
-    pub search: Option<String>,
+    #[arg(long, value_name = "OPTION", value_parser = utils::parse_search)]
+    pub search: Option<regex::Regex>,
 
     /// Only show options that have a default value
     #[arg(long)]
@@ -115,6 +371,11 @@ pub struct FilterOptions {
     #[arg(long)]
     pub has_description: bool,
 
+    /// Only show options matching a query combining conditions
+    /// with AND, OR and NOT (e.g. `name:services.* AND NOT has:example`)
+    #[arg(long, value_parser = query::Query::parse)]
+    pub query: Option<query::Query>,
+
     /// Replace nix variables in the generated
     /// document with the specified value
     /// (can be used multiple times)
@@ -140,6 +401,25 @@ pub struct UtilityOptions {
     #[arg(short = 'e', long, value_delimiter = ',')]
     pub exclude_dir: Vec<String>,
 
+    /// Only process files matching a gitignore-style pattern
+    /// (e.g. `modules/**`, can be specified multiple times)
+    #[arg(long, value_name = "PATTERN", value_parser = files::parse_pattern)]
+    pub include: Vec<String>,
+
+    /// Skip files matching a gitignore-style pattern, or process them
+    /// again if it starts with `!` (e.g. `**/tests/**`, can be specified
+    /// multiple times)
+    #[arg(long, value_name = "PATTERN", value_parser = files::parse_pattern)]
+    pub exclude: Vec<String>,
+
+    /// Skip files ignored by `.gitignore` files
+    #[arg(long)]
+    pub gitignore: bool,
+
+    /// Process hidden files and directories
+    #[arg(long)]
+    pub hidden: bool,
+
     /// Enable traversing through symbolic links
     #[arg(long)]
     pub follow_symlinks: bool,
@@ -147,6 +427,31 @@ pub struct UtilityOptions {
     /// Show progress bar
     #[arg(long)]
     pub progress: bool,
+
+    /// Parse every file instead of reusing the options of unchanged files from previous runs
+    #[arg(long)]
+    pub no_parse_cache: bool,
+
+    /// Keep running and regenerate the documentation whenever a Nix
+    /// file in the local path changes
+    #[arg(long, conflicts_with = "rev")]
+    pub watch: bool,
+}
+
+impl UtilityOptions {
+    /// Builds the filter selecting the files to process.
+    ///
+    /// # Returns
+    /// The file filter combining the excluded directories, patterns and switches.
+    pub fn file_filter(&self) -> files::FileFilter {
+        files::FileFilter {
+            exclude_dirs: self.exclude_dir.clone(),
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            gitignore: self.gitignore,
+            hidden: self.hidden,
+        }
+    }
 }
 
 /// Represents a documented NixOS module option.
@@ -175,6 +480,50 @@ pub struct OptionDoc {
 
     /// The line number where the option is defined in the file
     pub line_number: usize,
+
+    /// The column number where the option is defined in the file
+    #[serde(default)]
+    pub column_number: usize,
+
+    /// The line number where the definition of the option ends
+    #[serde(default)]
+    pub end_line_number: usize,
+
+    /// The column number following the end of the definition of the option
+    #[serde(default)]
+    pub end_column_number: usize,
+
+    /// URL of the file in its own repository, for files vendored from git submodules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+
+    /// The flake output exporting the option (e.g. `nixosModules.default`), if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+
+    /// The condition the option is declared under (`mkIf`/`optionalAttrs`), if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+
+    /// The doc comment at the top of the file declaring the option, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_doc: Option<String>,
+}
+
+impl OptionDoc {
+    /// Builds the link to the definition of the option.
+    ///
+    /// # Returns
+    /// The URL of the file in its submodule repository if known, or the file path,
+    /// followed by an anchor to the lines the option is defined at.
+    pub fn source_link(&self) -> String {
+        let file = self.source_url.as_deref().unwrap_or(&self.file_path);
+        if self.end_line_number > self.line_number {
+            format!("{}#L{}-L{}", file, self.line_number, self.end_line_number)
+        } else {
+            format!("{}#L{}", file, self.line_number)
+        }
+    }
 }
 
 /// Filters the list of option documentation entries based on CLI parameters.
@@ -186,15 +535,55 @@ pub struct OptionDoc {
 /// # Returns
 /// A vector of options that match all specified filter conditions.
 pub fn filter_options(options: &[OptionDoc], cli: &Cli) -> Vec<OptionDoc> {
+    apply_filters(options, &cli.filter, &cli.io)
+}
+
+/// Filters the list of option documentation entries based on filter criteria.
+///
+/// # Arguments
+/// - `options`: A slice of option documentation entries to filter.
+/// - `filter`: The filter criteria (prefix, type, search term, etc.).
+/// - `io`: The input/output options containing the module system and output prefix.
+///
+/// # Returns
+/// A vector of options that match all specified filter conditions.
+pub fn apply_filters(
+    options: &[OptionDoc],
+    filter: &FilterOptions,
+    io: &IoOptions,
+) -> Vec<OptionDoc> {
     let mut filtered = options.to_vec();
 
+    // Keep only the options declared in the option roots of their module system
+    filtered.retain_mut(|opt| {
+        let system = io.module_system.or_else(|| {
+            opt.module
+                .as_deref()
+                .and_then(ModuleSystem::from_flake_output)
+        });
+        let Some(system) = system else {
+            return true;
+        };
+
+        match system.normalize_name(&opt.name) {
+            Some(name) => {
+                opt.name = name;
+                true
+            }
+            None => {
+                log::debug!("Skipping {} declared outside of the option roots", opt.name);
+                false
+            }
+        }
+    });
+
     // Filter by prefix
-    if let Some(ref prefix) = cli.filter.filter_by_prefix {
+    if let Some(ref prefix) = filter.filter_by_prefix {
         filtered.retain(|opt| opt.name.starts_with(prefix));
     }
 
     // Filter by type
-    if let Some(ref type_str) = cli.filter.filter_by_type {
+    if let Some(ref type_str) = filter.filter_by_type {
         filtered.retain(|opt| {
             let type_info = opt.nix_type.to_string().to_lowercase();
             type_info.contains(&type_str.to_lowercase())
@@ -202,38 +591,34 @@ pub fn filter_options(options: &[OptionDoc], cli: &Cli) -> Vec<OptionDoc> {
     }
 
     // Filter by search text
-    if let Some(ref search) = cli.filter.search {
-        // let search_lower = search.to_lowercase();
-        match regex::Regex::new(search) {
-            Ok(re) => {
-                filtered.retain(|opt| {
-                    re.is_match(&opt.name)
-                        || opt
-                            .description
-                            .as_ref()
-                            .map(|d| re.is_match(d))
-                            .unwrap_or(false)
-                });
-            }
-            Err(e) => {
-                // Log the error but don't filter out anything if the regex is invalid
-                log::error!("Invalid regex pattern '{}': {}", search, e);
-            }
-        }
+    if let Some(ref re) = filter.search {
+        filtered.retain(|opt| {
+            re.is_match(&opt.name)
+                || opt
+                    .description
+                    .as_ref()
+                    .map(|d| re.is_match(d))
+                    .unwrap_or(false)
+        });
     }
 
     // Filter by having default value
-    if cli.filter.has_default {
+    if filter.has_default {
         filtered.retain(|opt| opt.default_value.is_some());
     }
 
     // Filter by having description
-    if cli.filter.has_description {
+    if filter.has_description {
         filtered.retain(|opt| opt.description.is_some());
     }
 
+    // Filter by query
+    if let Some(ref query) = filter.query {
+        filtered.retain(|opt| query.matches(opt));
+    }
+
     // Strip prefix: `options.*`
-    if let Some(strip_prefix) = &cli.filter.strip_prefix {
+    if let Some(strip_prefix) = &filter.strip_prefix {
         let prefix = if strip_prefix.is_empty() {
             "options.".to_string()
         } else if strip_prefix.starts_with("options.") {
@@ -253,7 +638,7 @@ pub fn filter_options(options: &[OptionDoc], cli: &Cli) -> Vec<OptionDoc> {
         }
     }
 
-    if let Some(out_prefix) = &cli.io.out_prefix {
+    if let Some(out_prefix) = &io.out_prefix {
         let prefix = if out_prefix.ends_with('/') {
             out_prefix.strip_suffix('/').unwrap_or(out_prefix.as_str())
         } else {
@@ -268,26 +653,127 @@ pub fn filter_options(options: &[OptionDoc], cli: &Cli) -> Vec<OptionDoc> {
     filtered
 }
 
+/// Resolves the subdirectory or file of the repository that should be documented.
+///
+/// # Arguments
+/// - `cli`: The CLI arguments containing the path and subdirectory options.
+///
+/// # Returns
+/// The path given with `--subdir` if present, otherwise the `//<subdir>` suffix of a
+/// remote repository URL, or None if the whole repository should be documented.
+pub fn resolve_subdir(cli: &Cli) -> Option<PathBuf> {
+    let url_subdir = if Path::new(&cli.io.path).exists() {
+        None
+    } else {
+        utils::split_subdir(&cli.io.path).1
+    };
+
+    match (&cli.io.subdir, url_subdir) {
+        (Some(subdir), Some(url_subdir)) => {
+            log::warn!(
+                "Both --subdir and a URL subdirectory were specified, using `{}` instead of `{}`",
+                subdir,
+                url_subdir
+            );
+            Some(PathBuf::from(subdir))
+        }
+        (Some(subdir), None) => Some(PathBuf::from(subdir)),
+        (None, url_subdir) => url_subdir.map(PathBuf::from),
+    }
+}
+
+/// A local directory prepared for processing Nix files.
+pub struct PreparedPath {
+    /// Path to the local directory or git repository containing the Nix files
+    pub path: PathBuf,
+
+    /// Revision to read the Nix files at from the repository's object database
+    /// instead of reading them from disk, if any
+    pub rev: Option<String>,
+
+    /// Directory the repository was fetched into, used for fetching submodules
+    pub cache_dir: Option<PathBuf>,
+
+    /// Temporary directory holding a fresh clone, removed when dropped
+    pub temp_dir: Option<TempDir>,
+}
+
+impl PreparedPath {
+    /// Opens the cache of the options parsed from the files of the path in previous runs.
+    ///
+    /// # Arguments
+    /// - `cli`: The CLI arguments containing the cache options.
+    ///
+    /// # Returns
+    /// The parse cache, or None if it is disabled, the path is a temporary clone or no
+    /// cache directory is available.
+    pub fn parse_cache(&self, cli: &Cli) -> Option<cache::ParseCache> {
+        if cli.util.no_parse_cache || self.temp_dir.is_some() {
+            return None;
+        }
+
+        match cache::cache_dir(cli.git.cache_dir.as_deref()) {
+            Ok(cache_dir) => Some(cache::ParseCache::open(&cache_dir, &self.path)),
+            Err(e) => {
+                log::debug!("Parse cache disabled: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Determines how submodules should be fetched, if recursing into them is enabled.
+    ///
+    /// # Arguments
+    /// - `cli`: The CLI arguments containing the submodule and cache options.
+    ///
+    /// # Returns
+    /// The submodule options, fetching into the same directory as the repository
+    /// itself, or None if submodules should be skipped.
+    pub fn submodule_options(
+        &self,
+        cli: &Cli,
+    ) -> Result<Option<git::SubmoduleOptions>, NixDocError> {
+        if !cli.git.recurse_submodules {
+            return Ok(None);
+        }
+
+        let cache_dir = match self.cache_dir {
+            Some(ref dir) => dir.clone(),
+            None => cache::cache_dir(cli.git.cache_dir.as_deref())?,
+        };
+
+        Ok(Some(git::SubmoduleOptions {
+            cache_dir,
+            depth: cli.git.depth,
+            offline: cli.git.offline,
+        }))
+    }
+}
+
 /// Prepares a local directory for processing Nix files.
 ///
 /// # Arguments
 /// - `cli`: The CLI arguments containing path, branch, depth, and other repository options.
 ///
 /// # Returns
-/// A tuple containing the path to the working directory and an optional `TempDir` (for cleanup).
-/// If the path is local, returns the local path with None for TempDir.
-/// If the path is a git URL, clones the repository and returns the temp directory.
-pub fn prepare_path(cli: &Cli) -> Result<(PathBuf, Option<TempDir>), NixDocError> {
+/// The prepared path along with the revision to read the files at, if any.
+/// If the path is local, returns the local path.
+/// If the path is a git URL, fetches the repository into the persistent cache (or a
+/// temporary directory if caching is disabled) and returns the fetched repository
+/// along with the fetched revision.
+pub fn prepare_path(cli: &Cli) -> Result<PreparedPath, NixDocError> {
     // Check if the path is a local directory
     let path = Path::new(&cli.io.path);
     if path.exists() {
         log::debug!("Found local path: {}", path.to_string_lossy());
-        return Ok((path.to_path_buf(), None));
+        return Ok(PreparedPath {
+            path: path.to_path_buf(),
+            rev: cli.git.rev.clone(),
+            cache_dir: None,
+            temp_dir: None,
+        });
     }
 
-    let temp_dir = TempDir::new()?;
-    let temp_path = temp_dir.path();
-
     // Attempt to fetch git repository
     // Initialize interrupt handler.
     unsafe {
@@ -296,75 +782,75 @@ pub fn prepare_path(cli: &Cli) -> Result<(PathBuf, Option<TempDir>), NixDocError
         })?;
     }
 
-    let url = gix::url::parse(cli.io.path.as_bytes().into())
-        .map_err(|e| NixDocError::InvalidPath(format!("Invalid git URL: {}", e)))?;
+    // Strip the `//<subdir>` suffix, if any, before parsing the URL
+    let (repo_url, _) = utils::split_subdir(&cli.io.path);
 
-    // Prepare the clone builder
-    let mut prepare_clone = gix::prepare_clone(url, temp_path).map_err(|e| {
-        let err_msg = e.to_string();
-        if err_msg.contains("auth") || err_msg.contains("credentials") {
-            NixDocError::GitClone(cli.io.path.clone(), err_msg)
-        } else {
-            NixDocError::GitOperation(format!("Failed to prepare clone: {}", e))
-        }
-    })?;
-
-    // Configure shallow clone with the provided depth (defaults to 1)
-    let shallow = Shallow::DepthAtRemote(
-        std::num::NonZeroU32::new(cli.git.depth)
-            .unwrap_or_else(|| std::num::NonZeroU32::new(1).unwrap()),
-    );
-
-    if let Some(ref branch) = cli.git.branch {
-        prepare_clone = prepare_clone.with_ref_name(Some(branch)).unwrap();
-    }
-    let (mut prepare_checkout, _) = prepare_clone
-        .with_shallow(shallow)
-        .fetch_then_checkout(Discard, &gix::interrupt::IS_INTERRUPTED)
-        .map_err(|e| NixDocError::GitClone(cli.io.path.clone(), e.to_string()))?;
-
-    let (repo, _) = prepare_checkout
-        .main_worktree(Discard, &gix::interrupt::IS_INTERRUPTED)
-        .map_err(|e| NixDocError::GitOperation(format!("Failed to checkout worktree: {}", e)))?;
+    // Fetch into the persistent cache, or into a temporary directory
+    // that is removed once the documentation is generated
+    let temp_dir = if cli.git.no_cache {
+        Some(TempDir::new()?)
+    } else {
+        None
+    };
+    let cache_dir = match temp_dir {
+        Some(ref temp_dir) => temp_dir.path().to_path_buf(),
+        None => cache::cache_dir(cli.git.cache_dir.as_deref())?,
+    };
 
-    let work_dir = repo.work_dir().ok_or(NixDocError::NoWorkDir)?;
-    Ok((work_dir.to_path_buf(), Some(temp_dir)))
+    let (repo_path, commit) = cache::fetch_repo(
+        &cache_dir,
+        repo_url,
+        cli.git.branch.as_deref(),
+        cli.git.depth,
+        cli.git.offline,
+    )?;
+
+    Ok(PreparedPath {
+        path: repo_path,
+        rev: Some(cli.git.rev.clone().unwrap_or(commit)),
+        cache_dir: Some(cache_dir),
+        temp_dir,
+    })
 }
 
-/// Recursively collects NixOS module options from all .nix files in the specified directory.
+/// Resolves the path that traversal should start from, validating that the optional
+/// subdirectory exists and does not escape the base directory.
 ///
 /// # Arguments
-/// - `dir`: The base directory to search for Nix files.
-/// - `exclude_dirs`: A list of directory paths to exclude from processing.
-/// - `replacements`: A map of variable replacements for dynamic parts in option definitions.
-/// - `show_progress`: Displays a progress bar if set to true.
-/// - `follow_symlinks`: Whether to follow symbolic links during directory traversal.
+/// - `dir`: The base directory of the repository.
+/// - `subdir`: An optional subdirectory or file relative to `dir`.
 ///
 /// # Returns
-/// A `Result` containing a vector of unique option documentation entries or an error.
-pub fn collect_options(
-    dir: &Path,
-    exclude_dirs: &[String],
-    replacements: &HashMap<String, String>,
-    show_progress: bool,
-    follow_symlinks: bool,
-) -> Result<Vec<OptionDoc>, NixDocError> {
-    if !dir.exists() {
+/// The path to start processing from or an error if the subdirectory is invalid.
+fn resolve_walk_root(dir: &Path, subdir: Option<&Path>) -> Result<PathBuf, NixDocError> {
+    let Some(subdir) = subdir else {
+        return Ok(dir.to_path_buf());
+    };
+
+    if subdir.is_absolute()
+        || subdir
+            .components()
+            .any(|c| matches!(c, std::path::Component::ParentDir))
+    {
         return Err(NixDocError::InvalidPath(format!(
-            "Directory does not exist: {}",
-            dir.display()
+            "Subdirectory must be a relative path inside the repository: {}",
+            subdir.display()
         )));
     }
 
-    if !replacements.is_empty() {
-        log::debug!("Using variable replacements:");
-        for (key, value) in replacements {
-            log::debug!("\t${{{0}}} => {1}", key, value);
-        }
-    }
+    log::debug!("Restricting processing to: {}", subdir.display());
+    Ok(dir.join(subdir))
+}
 
-    // Collect list of directories and paths to be excluded
-    // from the generated documentation
+/// Builds the list of paths to be excluded from the generated documentation.
+///
+/// # Arguments
+/// - `dir`: The base directory that relative exclusions are resolved against.
+/// - `exclude_dirs`: A list of absolute or relative directory paths to exclude.
+///
+/// # Returns
+/// A vector of absolute paths to exclude from processing.
+pub(crate) fn resolve_exclude_paths(dir: &Path, exclude_dirs: &[String]) -> Vec<PathBuf> {
     let exclude_paths: Vec<PathBuf> = exclude_dirs
         .iter()
         .map(|s| {
@@ -384,28 +870,26 @@ pub fn collect_options(
         }
     }
 
-    // Collect all .nix files first
-    let mut nix_files = Vec::new();
-
-    // Walk the directory, filtering out excluded paths
-    for result in WalkDir::new(dir).follow_links(follow_symlinks).into_iter() {
-        // Handle any errors during directory traversal
-        let entry = match result {
-            Ok(entry) => entry,
-            Err(e) => {
-                log::warn!("An error occurred, skipping directory: {}", e);
-                continue;
-            }
-        };
-
-        if utils::should_process_file(&entry, &exclude_paths) {
-            nix_files.push(entry.path().to_path_buf());
-        }
-    }
+    exclude_paths
+}
 
+/// Parses a list of Nix sources in parallel and deduplicates the resulting options.
+///
+/// # Arguments
+/// - `sources`: Pairs of relative file paths and the source to parse for each of them.
+/// - `show_progress`: Displays a progress bar if set to true.
+/// - `parse`: Function extracting the options from a single source given its relative path.
+///
+/// # Returns
+/// A vector of unique option documentation entries.
+fn parse_sources<T, F>(sources: &[(String, T)], show_progress: bool, parse: F) -> Vec<OptionDoc>
+where
+    T: Sync,
+    F: Fn(&str, &T) -> Vec<OptionDoc> + Sync,
+{
     // Set up progress bar
     let progress_bar = if show_progress {
-        let pb = indicatif::ProgressBar::new(nix_files.len() as u64);
+        let pb = indicatif::ProgressBar::new(sources.len() as u64);
         pb.set_style(
             indicatif::ProgressStyle::default_bar()
                 .template(
@@ -423,27 +907,21 @@ pub fn collect_options(
     let counter = std::sync::atomic::AtomicUsize::new(0);
 
     // Process files in parallel
-    let options: Vec<OptionDoc> = nix_files
+    let options: Vec<OptionDoc> = sources
         .par_iter()
-        .flat_map(|file_path| {
+        .flat_map(|(relative_path, source)| {
             // Update progress
             if let Some(ref pb) = progress_bar {
                 let count = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
                 pb.set_position(count as u64);
-                if let Some(file_name) = file_path.file_name() {
+                if let Some(file_name) = Path::new(relative_path).file_name() {
                     pb.set_message(format!("Processing {}", file_name.to_string_lossy()));
                 }
             }
 
-            log::debug!(
-                "Processing file: {}",
-                match file_path.strip_prefix(dir) {
-                    Ok(rel) => rel.to_string_lossy(),
-                    Err(_) => file_path.to_string_lossy(),
-                }
-            );
+            log::debug!("Processing file: {}", relative_path);
 
-            utils::process_nix_file(file_path, dir, replacements)
+            parse(relative_path, source)
         })
         .collect();
 
@@ -464,7 +942,510 @@ pub fn collect_options(
         }
     }
 
-    Ok(unique_options)
+    unique_options
+}
+
+/// Extracts the options of a source, reusing the result of a previous run if it is cached.
+///
+/// # Arguments
+/// - `parse_cache`: The cache of previously parsed files, or None to always parse.
+/// - `source`: The path of the file (or module) relative to the documented directory.
+/// - `content`: The contents of the file.
+/// - `replacements`: The variable replacements used for parsing.
+/// - `parse`: Extracts the options of the source.
+///
+/// # Returns
+/// The options of the source.
+fn parse_cached<F>(
+    parse_cache: Option<&cache::ParseCache>,
+    source: &str,
+    content: &str,
+    replacements: &HashMap<String, String>,
+    parse: F,
+) -> Vec<OptionDoc>
+where
+    F: FnOnce() -> Vec<OptionDoc>,
+{
+    match parse_cache {
+        Some(parse_cache) => parse_cache.get_or_parse(source, content, replacements, parse),
+        None => parse(),
+    }
+}
+
+/// Settings shared by the functions collecting options.
+#[derive(Clone, Copy)]
+pub struct CollectOptions<'a> {
+    /// Selects the files to process
+    pub files: &'a files::FileFilter,
+
+    /// Variable replacements for dynamic parts in option definitions
+    pub replacements: &'a HashMap<String, String>,
+
+    /// Whether to display a progress bar
+    pub show_progress: bool,
+
+    /// Whether to follow symbolic links when walking a directory on disk
+    pub follow_symlinks: bool,
+
+    /// How to fetch submodules when reading at a revision, or None to skip them
+    pub submodules: Option<&'a git::SubmoduleOptions>,
+
+    /// The cache of previously parsed files, or None to parse every file
+    pub parse_cache: Option<&'a cache::ParseCache>,
+}
+
+impl<'a> CollectOptions<'a> {
+    /// Creates settings that process the selected files without a progress bar, symbolic
+    /// links, submodules or parse cache.
+    pub fn new(files: &'a files::FileFilter, replacements: &'a HashMap<String, String>) -> Self {
+        Self {
+            files,
+            replacements,
+            show_progress: false,
+            follow_symlinks: false,
+            submodules: None,
+            parse_cache: None,
+        }
+    }
+}
+
+/// Recursively collects NixOS module options from all .nix files in the specified directory,
+/// reusing the options of files that haven't changed since they were cached.
+///
+/// # Arguments
+/// - `dir`: The base directory to search for Nix files.
+/// - `subdir`: An optional subdirectory or file relative to `dir` to restrict processing to.
+///   File paths in the generated documentation remain relative to `dir`.
+/// - `settings`: The files to process, replacements and parse cache.
+///
+/// # Returns
+/// A `Result` containing a vector of unique option documentation entries or an error.
+pub fn collect_options(
+    dir: &Path,
+    subdir: Option<&Path>,
+    settings: &CollectOptions,
+) -> Result<Vec<OptionDoc>, NixDocError> {
+    let CollectOptions {
+        files,
+        replacements,
+        show_progress,
+        follow_symlinks,
+        parse_cache,
+        ..
+    } = *settings;
+
+    if !dir.exists() {
+        return Err(NixDocError::InvalidPath(format!(
+            "Directory does not exist: {}",
+            dir.display()
+        )));
+    }
+
+    let walk_root = resolve_walk_root(dir, subdir)?;
+    if !walk_root.exists() {
+        return Err(NixDocError::InvalidPath(format!(
+            "Subdirectory does not exist: {}",
+            walk_root.display()
+        )));
+    }
+
+    if !replacements.is_empty() {
+        log::debug!("Using variable replacements:");
+        for (key, value) in replacements {
+            log::debug!("\t${{{0}}} => {1}", key, value);
+        }
+    }
+
+    let matcher = files.matcher(dir)?;
+
+    // Collect all .nix files first
+    let mut nix_files = Vec::new();
+
+    // Walk the directory in a stable order, without descending into excluded directories
+    for result in WalkDir::new(&walk_root)
+        .follow_links(follow_symlinks)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            let path = entry.path();
+            !entry.file_type().is_dir()
+                || !matcher.is_excluded(path.strip_prefix(dir).unwrap_or(path), true)
+        })
+    {
+        // Handle any errors during directory traversal
+        let entry = match result {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!("An error occurred, skipping directory: {}", e);
+                continue;
+            }
+        };
+
+        let file_path = entry.path();
+        let is_file = entry.file_type().is_file();
+        if matcher.is_selected(file_path.strip_prefix(dir).unwrap_or(file_path), is_file) {
+            let relative_path = utils::relative_path(file_path, dir);
+            nix_files.push((relative_path, file_path.to_path_buf()));
+        }
+    }
+
+    Ok(parse_sources(
+        &nix_files,
+        show_progress,
+        |relative_path, file_path| {
+            // Files that didn't change since the previous run of a watch aren't read again
+            if let Some(options) = parse_cache.and_then(|cache| cache.get_unchanged(relative_path))
+            {
+                return options;
+            }
+
+            match std::fs::read_to_string(file_path) {
+                Ok(content) => {
+                    parse_cached(parse_cache, relative_path, &content, replacements, || {
+                        utils::process_nix_source(&content, relative_path, replacements)
+                    })
+                }
+                Err(e) => {
+                    log::error!("Error reading file {}: {}", file_path.display(), e);
+                    Vec::new()
+                }
+            }
+        },
+    ))
+}
+
+/// Reads the contents of the matching .nix files of a local git repository at a given revision.
+///
+/// # Arguments
+/// - `dir`: The path to the local git repository.
+/// - `rev`: The revision (commit, tag, branch or any rev-spec) to read the files from.
+/// - `subdir`: An optional subdirectory or file relative to `dir` to restrict reading to.
+/// - `files`: Selects the files to read.
+/// - `submodules`: How to fetch submodules, or None to skip the files of submodules.
+///
+/// # Returns
+/// Pairs of file paths relative to `dir` and the files read, or an error.
+fn read_files_at_rev(
+    dir: &Path,
+    rev: &str,
+    subdir: Option<&Path>,
+    files: &files::FileFilter,
+    submodules: Option<&git::SubmoduleOptions>,
+) -> Result<Vec<(String, git::TreeFile)>, NixDocError> {
+    let repo = gix::open(dir).map_err(|e| {
+        NixDocError::InvalidPath(format!("Not a git repository: {} ({})", dir.display(), e))
+    })?;
+
+    let tree = repo
+        .rev_parse_single(rev)
+        .map_err(NixDocError::git_error)?
+        .object()
+        .map_err(NixDocError::git_error)?
+        .peel_to_tree()
+        .map_err(NixDocError::git_error)?;
+
+    log::debug!("Reading files at revision `{}` ({})", rev, tree.id);
+
+    let walk_root = resolve_walk_root(dir, subdir)?;
+    if let Some(subdir) = subdir {
+        let entry = tree
+            .lookup_entry_by_path(subdir)
+            .map_err(NixDocError::git_error)?;
+        if entry.is_none() {
+            return Err(NixDocError::InvalidPath(format!(
+                "Subdirectory does not exist at revision `{}`: {}",
+                rev,
+                subdir.display()
+            )));
+        }
+    }
+
+    // Ignore files are read from the revision as well
+    let matcher = files.matcher_with(dir, |path| {
+        let entry = tree.lookup_entry_by_path(path).ok()??;
+        let blob = entry.object().ok()?;
+        String::from_utf8(blob.data.clone()).ok()
+    })?;
+
+    git::TreeReader {
+        dir,
+        walk_root: &walk_root,
+        files: &matcher,
+        submodules,
+    }
+    .read(&repo, &tree)
+}
+
+/// Collects NixOS module options from the .nix files of a local git repository at a
+/// given revision, reading them directly from the object database without touching
+/// the working tree, and reusing the options of files that haven't changed since they
+/// were cached.
+///
+/// # Arguments
+/// - `dir`: The path to the local git repository.
+/// - `rev`: The revision (commit, tag, branch or any rev-spec) to read the files from.
+/// - `subdir`: An optional subdirectory or file relative to `dir` to restrict processing to.
+/// - `settings`: The files to process, replacements, submodules and parse cache.
+///
+/// # Returns
+/// A `Result` containing a vector of unique option documentation entries or an error.
+pub fn collect_options_at_rev(
+    dir: &Path,
+    rev: &str,
+    subdir: Option<&Path>,
+    settings: &CollectOptions,
+) -> Result<Vec<OptionDoc>, NixDocError> {
+    let nix_files = read_files_at_rev(dir, rev, subdir, settings.files, settings.submodules)?;
+
+    Ok(parse_sources(
+        &nix_files,
+        settings.show_progress,
+        |relative_path, file: &git::TreeFile| {
+            let mut options = parse_cached(
+                settings.parse_cache,
+                relative_path,
+                &file.content,
+                settings.replacements,
+                || utils::process_nix_source(&file.content, relative_path, settings.replacements),
+            );
+            for option in &mut options {
+                option.source_url = file.source_url.clone();
+            }
+            options
+        },
+    ))
+}
+
+/// Reads the files of modules relative to the repository root, either from disk or
+/// from the object database at a given revision.
+struct ModuleReader<'a> {
+    /// The base directory (or git repository) containing the Nix files
+    dir: &'a Path,
+
+    /// Selects the modules that are read from disk
+    matcher: files::FileMatcher<'static>,
+
+    /// All files of the revision, when reading from the object database
+    tree_files: Option<HashMap<String, git::TreeFile>>,
+}
+
+impl<'a> ModuleReader<'a> {
+    /// Prepares reading modules from disk or, if `rev` is given, from a git revision.
+    fn new(
+        dir: &'a Path,
+        rev: Option<&str>,
+        files: &files::FileFilter,
+        submodules: Option<&git::SubmoduleOptions>,
+    ) -> Result<Self, NixDocError> {
+        if !dir.exists() {
+            return Err(NixDocError::InvalidPath(format!(
+                "Directory does not exist: {}",
+                dir.display()
+            )));
+        }
+
+        // Files at a revision are all read upfront, as imports may point anywhere in the tree
+        let tree_files = match rev {
+            Some(rev) => Some(
+                read_files_at_rev(dir, rev, None, files, submodules)?
+                    .into_iter()
+                    .collect(),
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            dir,
+            matcher: files.matcher(dir)?,
+            tree_files,
+        })
+    }
+
+    /// Reads a file given its path relative to the repository root, returning None for
+    /// directories, missing or excluded files.
+    fn read(&self, relative_path: &str) -> Option<String> {
+        if let Some(ref tree_files) = self.tree_files {
+            return tree_files.get(relative_path).map(|f| f.content.clone());
+        }
+
+        let file_path = self.dir.join(relative_path);
+        if !self
+            .matcher
+            .is_selected(Path::new(relative_path), file_path.is_file())
+        {
+            return None;
+        }
+        std::fs::read_to_string(&file_path)
+            .map_err(|e| log::error!("Error reading file {}: {}", relative_path, e))
+            .ok()
+    }
+
+    /// Returns the browsable URL of a file vendored from a git submodule, if any.
+    fn source_url(&self, relative_path: &str) -> Option<String> {
+        self.tree_files
+            .as_ref()
+            .and_then(|files| files.get(relative_path))
+            .and_then(|f| f.source_url.clone())
+    }
+
+    /// Collects the options of the modules reachable from the entry points.
+    fn collect(
+        &self,
+        entries: &[imports::ModuleRef],
+        settings: &CollectOptions,
+    ) -> (Vec<OptionDoc>, imports::ModuleGraph) {
+        let graph = imports::build_graph(entries, &|path| self.read(path));
+        log::debug!("Found {} reachable modules", graph.modules.len());
+
+        let mut sources = Vec::new();
+        for module in &graph.modules {
+            if let Some(content) = self.read(&module.file) {
+                let source_url = self.source_url(&module.file);
+                sources.push((module.file.clone(), (module, content, source_url)));
+            }
+        }
+
+        let options = parse_sources(
+            &sources,
+            settings.show_progress,
+            |_, (module, content, source_url)| {
+                let mut options = parse_cached(
+                    settings.parse_cache,
+                    &module.to_string(),
+                    content,
+                    settings.replacements,
+                    || utils::process_module_source(content, module, settings.replacements),
+                );
+                for option in &mut options {
+                    option.source_url = source_url.clone();
+                }
+                options
+            },
+        );
+
+        (options, graph)
+    }
+}
+
+/// Collects NixOS module options from the modules reachable from the given entry points
+/// by statically following their `imports`, instead of processing every file, and reusing
+/// the options of modules that haven't changed since they were cached.
+///
+/// # Arguments
+/// - `dir`: The base directory (or git repository) containing the Nix files.
+/// - `entries`: The entry point modules, relative to `dir`.
+/// - `rev`: The revision to read the files at from the repository's object database,
+///   or None to read them from disk.
+/// - `settings`: The files whose modules are followed, replacements, submodules and
+///   parse cache.
+///
+/// # Returns
+/// A `Result` containing the unique option documentation entries along with the import graph,
+/// or an error if none of the entry points could be read.
+pub fn collect_options_from_entries(
+    dir: &Path,
+    entries: &[imports::ModuleRef],
+    rev: Option<&str>,
+    settings: &CollectOptions,
+) -> Result<(Vec<OptionDoc>, imports::ModuleGraph), NixDocError> {
+    let reader = ModuleReader::new(dir, rev, settings.files, settings.submodules)?;
+    let (options, graph) = reader.collect(entries, settings);
+
+    if graph.modules.is_empty() {
+        return Err(NixDocError::InvalidPath(format!(
+            "None of the entry points could be read: {}",
+            entries
+                .iter()
+                .map(|entry| entry.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+
+    Ok((options, graph))
+}
+
+/// Collects the options of each module exported by a flake (`nixosModules`,
+/// `homeManagerModules`, `darwinModules` and `flakeModules` outputs) separately,
+/// following their imports statically and reusing the options of modules that haven't
+/// changed since they were cached.
+///
+/// # Arguments
+/// - `dir`: The base directory (or git repository) containing the flake.
+/// - `flake`: The path of the `flake.nix` file relative to `dir`.
+/// - `rev`: The revision to read the files at from the repository's object database,
+///   or None to read them from disk.
+/// - `settings`: The files whose modules are followed, replacements, submodules and
+///   parse cache.
+///
+/// # Returns
+/// A `Result` containing the options of all exported modules, tagged with the name of the
+/// output exporting them, or None if there is no flake or it exports no modules.
+pub fn collect_flake_options(
+    dir: &Path,
+    flake: &str,
+    rev: Option<&str>,
+    settings: &CollectOptions,
+) -> Result<Option<Vec<OptionDoc>>, NixDocError> {
+    let reader = ModuleReader::new(dir, rev, settings.files, settings.submodules)?;
+    let Some(content) = reader.read(flake) else {
+        return Ok(None);
+    };
+
+    let outputs = imports::flake_module_outputs(&rnix::Root::parse(&content).syntax());
+    if outputs.is_empty() {
+        log::debug!("Flake {} exports no modules", flake);
+        return Ok(None);
+    }
+
+    let mut options = Vec::new();
+    for output in outputs {
+        log::debug!("Collecting options of flake output {}", output);
+        let entry = imports::ModuleRef {
+            file: flake.to_string(),
+            attr: Some(output.clone()),
+        };
+
+        let (mut output_options, _) = reader.collect(&[entry], settings);
+        for option in &mut output_options {
+            option.module = Some(output.clone());
+        }
+        options.append(&mut output_options);
+    }
+
+    Ok(Some(options))
+}
+
+/// Determines the title of the generated document.
+///
+/// # Arguments
+/// - `options`: The documented options.
+/// - `cli`: The CLI arguments containing the title and module system options.
+///
+/// # Returns
+/// The title given with `--title`, otherwise the default title of the module system given
+/// with `--module-system` or shared by all exported flake modules, defaulting to NixOS.
+pub fn document_title(options: &[OptionDoc], cli: &Cli) -> String {
+    if let Some(ref title) = cli.io.title {
+        return title.clone();
+    }
+
+    let mut systems = options.iter().map(|opt| {
+        opt.module
+            .as_deref()
+            .and_then(ModuleSystem::from_flake_output)
+    });
+    let inferred = match systems.next() {
+        Some(first) if systems.all(|system| system == first) => first,
+        _ => None,
+    };
+
+    cli.io
+        .module_system
+        .or(inferred)
+        .unwrap_or(ModuleSystem::Nixos)
+        .title()
+        .to_string()
 }
 
 /// Generates documentation for the given options in the specified output format.
@@ -473,6 +1454,8 @@ pub fn collect_options(
 /// - `options`: A slice of option documentation entries to be formatted.
 /// - `format`: The desired output format (Markdown, JSON, HTML, or CSV).
 /// - `sorted`: If true, sorts the options alphabetically by name.
+/// - `title`: The title of Markdown and HTML documents.
+/// - `markdown`: The layout of Markdown documents.
 ///
 /// # Returns
 /// A `Result` containing the generated documentation string in the specified format or an error.
@@ -480,16 +1463,18 @@ pub fn generate_doc(
     options: &[OptionDoc],
     format: OutputFormat,
     sorted: bool,
+    title: &str,
+    markdown: &generate::markdown::MarkdownOptions,
 ) -> Result<String, NixDocError> {
     let mut options_copy = options.to_vec();
     if sorted {
-        options_copy.sort_by(|a, b| a.name.cmp(&b.name));
+        options_copy.sort_by(|a, b| a.module.cmp(&b.module).then_with(|| a.name.cmp(&b.name)));
     }
 
     match format {
-        OutputFormat::Markdown => Ok(generate::generate_markdown(&options_copy)?),
+        OutputFormat::Markdown => Ok(generate::generate_markdown(&options_copy, title, markdown)?),
         OutputFormat::Json => generate::generate_json(&options_copy),
-        OutputFormat::Html => generate::generate_html(&options_copy),
+        OutputFormat::Html => generate::generate_html(&options_copy, title),
         OutputFormat::Csv => generate::generate_csv(&options_copy),
     }
 }
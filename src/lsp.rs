@@ -0,0 +1,573 @@
+//! The lsp module implements a language server for the options of a module tree.
+//!
+//! It speaks the Language Server Protocol over stdio and uses the collected options
+//! to complete option paths inside `config` blocks, to show their documentation on
+//! hover and to jump to their declarations.
+
+use crate::error::NixDocError;
+use crate::generate::markdown::write_option_details;
+use crate::parser::PLACEHOLDER;
+use crate::{strings, OptionDoc};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidChangeWatchedFiles, DidCloseTextDocument, DidOpenTextDocument,
+    DidSaveTextDocument, Notification as _,
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, Documentation,
+    GotoDefinitionParams, Hover, HoverContents, HoverParams, HoverProviderCapability, Location,
+    MarkupContent, MarkupKind, OneOf, Position, Range, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Uri,
+};
+use rnix::{SyntaxKind, SyntaxNode, TextSize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// The options of a module tree, looked up by their path inside `config`.
+pub struct Index {
+    /// The directory the file paths of the options are relative to
+    root: PathBuf,
+
+    /// The options, with the first declaration of each name
+    options: Vec<OptionDoc>,
+}
+
+impl Index {
+    /// Creates an index of options.
+    ///
+    /// # Arguments
+    /// - `root`: The directory the file paths of the options are relative to.
+    /// - `options`: The collected options, possibly declared by several modules.
+    ///
+    /// # Returns
+    /// The index, keeping only the first declaration of each option name.
+    pub fn new(root: &Path, mut options: Vec<OptionDoc>) -> Self {
+        let mut seen = std::collections::HashSet::new();
+        options.retain(|option| seen.insert(option.name.clone()));
+        Self {
+            root: root.to_path_buf(),
+            options,
+        }
+    }
+
+    /// Returns the number of indexed options.
+    pub fn len(&self) -> usize {
+        self.options.len()
+    }
+
+    /// Checks whether no options are indexed.
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty()
+    }
+
+    /// Completes the attribute path being written at an offset inside a `config` block.
+    ///
+    /// # Arguments
+    /// - `text`: The contents of the Nix file.
+    /// - `offset`: The byte offset of the cursor.
+    ///
+    /// # Returns
+    /// The names of the attributes that may follow the path before the cursor.
+    pub fn complete(&self, text: &str, offset: usize) -> Vec<CompletionItem> {
+        let root = rnix::Root::parse(text).syntax();
+        let Some((path, partial)) = completion_context(&root, offset) else {
+            return Vec::new();
+        };
+        let Some(path) = config_path(&path) else {
+            return Vec::new();
+        };
+
+        // Attributes completed by several options are offered once, as an attribute set
+        let mut items: BTreeMap<&str, Option<&OptionDoc>> = BTreeMap::new();
+        for option in &self.options {
+            let segments = option_segments(option);
+            if segments.len() <= path.len() || !segments_match(&segments[..path.len()], path) {
+                continue;
+            }
+            let next = segments[path.len()];
+            if next == PLACEHOLDER || !next.starts_with(partial.as_str()) {
+                continue;
+            }
+            let leaf = (segments.len() == path.len() + 1).then_some(option);
+            items
+                .entry(next)
+                .and_modify(|item| *item = None)
+                .or_insert(leaf);
+        }
+
+        items
+            .into_iter()
+            .map(|(name, option)| match option {
+                Some(option) => CompletionItem {
+                    label: name.to_string(),
+                    kind: Some(CompletionItemKind::PROPERTY),
+                    detail: Some(option.nix_type.clone()),
+                    documentation: Some(Documentation::MarkupContent(markdown(&option_docs(
+                        option,
+                    )))),
+                    ..Default::default()
+                },
+                None => CompletionItem {
+                    label: name.to_string(),
+                    kind: Some(CompletionItemKind::MODULE),
+                    detail: Some("attribute set".to_string()),
+                    ..Default::default()
+                },
+            })
+            .collect()
+    }
+
+    /// Finds the option whose attribute path is under an offset inside a `config` block.
+    ///
+    /// # Arguments
+    /// - `text`: The contents of the Nix file.
+    /// - `offset`: The byte offset of the cursor.
+    ///
+    /// # Returns
+    /// The option, or None if the cursor isn't on the path of a known option.
+    pub fn option_at(&self, text: &str, offset: usize) -> Option<&OptionDoc> {
+        let root = rnix::Root::parse(text).syntax();
+        let path = attrpath_at(&root, offset)?;
+        let path = config_path(&path)?;
+
+        self.options.iter().find(|option| {
+            let segments = option_segments(option);
+            segments.len() == path.len() && segments_match(&segments, path)
+        })
+    }
+
+    /// Describes the option under an offset.
+    ///
+    /// # Arguments
+    /// - `text`: The contents of the Nix file.
+    /// - `offset`: The byte offset of the cursor.
+    ///
+    /// # Returns
+    /// The documentation of the option as Markdown, or None if there is no option.
+    pub fn hover(&self, text: &str, offset: usize) -> Option<Hover> {
+        let option = self.option_at(text, offset)?;
+        Some(Hover {
+            contents: HoverContents::Markup(markdown(&option_docs(option))),
+            range: None,
+        })
+    }
+
+    /// Locates the declaration of the option under an offset.
+    ///
+    /// # Arguments
+    /// - `text`: The contents of the Nix file.
+    /// - `offset`: The byte offset of the cursor.
+    ///
+    /// # Returns
+    /// The location of the declaration, or None if there is no option.
+    pub fn definition(&self, text: &str, offset: usize) -> Option<Location> {
+        let option = self.option_at(text, offset)?;
+        let uri = url::Url::from_file_path(self.root.join(&option.file_path))
+            .ok()?
+            .as_str()
+            .parse::<Uri>()
+            .ok()?;
+
+        // Columns of options are counted in characters, which matches UTF-16 outside the
+        // supplementary planes
+        let position = |line: usize, column: usize| Position {
+            line: line.saturating_sub(1) as u32,
+            character: column.saturating_sub(1) as u32,
+        };
+        let start = position(option.line_number, option.column_number);
+        let end = if option.end_line_number > 0 {
+            position(option.end_line_number, option.end_column_number)
+        } else {
+            start
+        };
+
+        Some(Location {
+            uri,
+            range: Range { start, end },
+        })
+    }
+}
+
+/// Runs the language server over stdio until the client shuts it down.
+///
+/// # Arguments
+/// - `root`: The directory the file paths of the options are relative to.
+/// - `collect`: Collects the options, called on startup and whenever files are saved.
+///
+/// # Returns
+/// An error if the connection to the client failed.
+pub fn run<F>(root: &Path, mut collect: F) -> Result<(), NixDocError>
+where
+    F: FnMut() -> Vec<OptionDoc>,
+{
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![".".to_string()]),
+            ..Default::default()
+        }),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+    connection
+        .initialize(serde_json::to_value(capabilities).map_err(NixDocError::serialization_error)?)
+        .map_err(NixDocError::server_error)?;
+
+    let mut index = Index::new(root, collect());
+    log::info!("Indexed {} options", index.len());
+
+    // The contents of the open documents, which may differ from the files on disk
+    let mut documents: HashMap<String, String> = HashMap::new();
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection
+                    .handle_shutdown(&request)
+                    .map_err(NixDocError::server_error)?
+                {
+                    break;
+                }
+                let response = respond(&index, &documents, request);
+                connection
+                    .sender
+                    .send(Message::Response(response))
+                    .map_err(NixDocError::server_error)?;
+            }
+            Message::Notification(notification) => {
+                if update_documents(&mut documents, &notification) {
+                    index = Index::new(root, collect());
+                    log::info!("Indexed {} options", index.len());
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    // The writer thread stops once the connection is dropped
+    drop(connection);
+    io_threads.join().map_err(NixDocError::server_error)?;
+    Ok(())
+}
+
+/// Answers a request of the client.
+///
+/// # Arguments
+/// - `index`: The indexed options.
+/// - `documents`: The contents of the open documents.
+/// - `request`: The request.
+///
+/// # Returns
+/// The response, or an error response for unsupported methods and invalid parameters.
+fn respond(index: &Index, documents: &HashMap<String, String>, request: Request) -> Response {
+    let id = request.id.clone();
+    let result = match request.method.as_str() {
+        Completion::METHOD => params::<CompletionParams>(request).map(|params| {
+            let position = params.text_document_position;
+            locate(documents, &position.text_document.uri, position.position)
+                .map(|(text, offset)| index.complete(text, offset))
+                .map(serde_json::to_value)
+        }),
+        HoverRequest::METHOD => params::<HoverParams>(request).map(|params| {
+            let position = params.text_document_position_params;
+            locate(documents, &position.text_document.uri, position.position)
+                .and_then(|(text, offset)| index.hover(text, offset))
+                .map(serde_json::to_value)
+        }),
+        GotoDefinition::METHOD => params::<GotoDefinitionParams>(request).map(|params| {
+            let position = params.text_document_position_params;
+            locate(documents, &position.text_document.uri, position.position)
+                .and_then(|(text, offset)| index.definition(text, offset))
+                .map(serde_json::to_value)
+        }),
+        method => {
+            return Response::new_err(
+                id,
+                ErrorCode::MethodNotFound as i32,
+                format!("Unsupported method: {}", method),
+            )
+        }
+    };
+
+    match result {
+        Ok(Some(Ok(value))) => Response::new_ok(id, value),
+        Ok(_) => Response::new_ok(id, serde_json::Value::Null),
+        Err(message) => Response::new_err(id, ErrorCode::InvalidParams as i32, message),
+    }
+}
+
+/// Deserializes the parameters of a request.
+fn params<P: serde::de::DeserializeOwned>(request: Request) -> Result<P, String> {
+    serde_json::from_value(request.params).map_err(|e| e.to_string())
+}
+
+/// Finds the contents of a document and the byte offset of a position in it.
+fn locate<'a>(
+    documents: &'a HashMap<String, String>,
+    uri: &Uri,
+    position: Position,
+) -> Option<(&'a str, usize)> {
+    let text = documents.get(uri.as_str())?;
+    Some((text, offset_at(text, position)))
+}
+
+/// Tracks the contents of open documents.
+///
+/// # Arguments
+/// - `documents`: The contents of the open documents.
+/// - `notification`: A notification of the client.
+///
+/// # Returns
+/// True if files were saved or changed on disk, so the options should be collected again.
+fn update_documents(documents: &mut HashMap<String, String>, notification: &Notification) -> bool {
+    let params = notification.params.clone();
+    match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            if let Ok(params) =
+                serde_json::from_value::<lsp_types::DidOpenTextDocumentParams>(params)
+            {
+                documents.insert(
+                    params.text_document.uri.as_str().to_string(),
+                    params.text_document.text,
+                );
+            }
+            false
+        }
+        DidChangeTextDocument::METHOD => {
+            if let Ok(params) =
+                serde_json::from_value::<lsp_types::DidChangeTextDocumentParams>(params)
+            {
+                // Documents are synchronized in full, so the last change holds the contents
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    documents.insert(params.text_document.uri.as_str().to_string(), change.text);
+                }
+            }
+            false
+        }
+        DidCloseTextDocument::METHOD => {
+            if let Ok(params) =
+                serde_json::from_value::<lsp_types::DidCloseTextDocumentParams>(params)
+            {
+                documents.remove(params.text_document.uri.as_str());
+            }
+            false
+        }
+        DidSaveTextDocument::METHOD | DidChangeWatchedFiles::METHOD => true,
+        _ => false,
+    }
+}
+
+/// Converts a position, with a column counted in UTF-16 code units, to a byte offset.
+///
+/// # Arguments
+/// - `text`: The contents of the document.
+/// - `position`: The zero-based line and column.
+///
+/// # Returns
+/// The byte offset, clamped to the end of the line or document.
+pub fn offset_at(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(end) => line_start += end + 1,
+            None => return text.len(),
+        }
+    }
+
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if c == '\n' || units >= position.character as usize {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+/// Wraps Markdown text for the client.
+fn markdown(value: &str) -> MarkupContent {
+    MarkupContent {
+        kind: MarkupKind::Markdown,
+        value: value.to_string(),
+    }
+}
+
+/// Documents an option like the Markdown output, followed by its declaration.
+fn option_docs(option: &OptionDoc) -> String {
+    let mut output = format!("`{}`\n", option.name);
+    if let Err(e) = write_option_details(&mut output, option) {
+        log::warn!("Unable to document {}: {}", option.name, e);
+    }
+    output.push_str(&format!(
+        "\n**Declared in:** `{}:{}`\n",
+        option.file_path, option.line_number
+    ));
+    output
+}
+
+/// Splits the name of an option into the segments of its path inside `config`.
+fn option_segments(option: &OptionDoc) -> Vec<&str> {
+    option
+        .name
+        .strip_prefix("options.")
+        .unwrap_or(&option.name)
+        .split('.')
+        .collect()
+}
+
+/// Checks whether the segments of an option name match a path, where placeholders
+/// of generated names match any attribute.
+fn segments_match(segments: &[&str], path: &[String]) -> bool {
+    segments
+        .iter()
+        .zip(path)
+        .all(|(segment, attr)| *segment == PLACEHOLDER || segment == attr)
+}
+
+/// Strips the leading `config` attribute from a path.
+///
+/// # Returns
+/// The path inside `config`, or None if the path isn't inside `config`.
+fn config_path(path: &[String]) -> Option<&[String]> {
+    match path.split_first() {
+        Some((first, rest)) if first == "config" => Some(rest),
+        _ => None,
+    }
+}
+
+/// Finds the attribute path being written at an offset.
+///
+/// # Arguments
+/// - `root`: The syntax tree of the file.
+/// - `offset`: The byte offset of the cursor.
+///
+/// # Returns
+/// The complete attributes of the path before the cursor, and the partially written
+/// attribute at the cursor, or None if the cursor isn't where an attribute is expected.
+fn completion_context(root: &SyntaxNode, offset: usize) -> Option<(Vec<String>, String)> {
+    let token = root
+        .token_at_offset(TextSize::try_from(offset).ok()?)
+        .left_biased()?;
+    let parent = token.parent()?;
+
+    match token.kind() {
+        SyntaxKind::TOKEN_IDENT | SyntaxKind::TOKEN_DOT => {
+            let attrpath = parent
+                .ancestors()
+                .find(|node| node.kind() == SyntaxKind::NODE_ATTRPATH)?;
+            let binding = attrpath.parent()?;
+
+            let mut path = enclosing_path(&binding)?;
+            let mut partial = String::new();
+            for attr in attrpath.children() {
+                let range = attr.text_range();
+                if usize::from(range.end()) < offset {
+                    path.push(attr_name(&attr)?);
+                } else if usize::from(range.start()) < offset {
+                    let len = offset - usize::from(range.start());
+                    partial = attr.text().to_string().get(..len)?.to_string();
+                }
+            }
+            Some((path, partial))
+        }
+        // Between the bindings of an attribute set
+        SyntaxKind::TOKEN_WHITESPACE
+        | SyntaxKind::TOKEN_COMMENT
+        | SyntaxKind::TOKEN_L_BRACE
+        | SyntaxKind::TOKEN_SEMICOLON
+            if matches!(
+                parent.kind(),
+                SyntaxKind::NODE_ATTR_SET | SyntaxKind::NODE_ATTRPATH_VALUE
+            ) =>
+        {
+            let attrset = if parent.kind() == SyntaxKind::NODE_ATTR_SET {
+                parent
+            } else {
+                // After the semicolon ending a binding
+                if token.kind() != SyntaxKind::TOKEN_SEMICOLON {
+                    return None;
+                }
+                parent.parent()?
+            };
+            Some((enclosing_path(&attrset)?, String::new()))
+        }
+        _ => None,
+    }
+}
+
+/// Finds the attribute path under an offset, up to and including the attribute at the cursor.
+///
+/// # Arguments
+/// - `root`: The syntax tree of the file.
+/// - `offset`: The byte offset of the cursor.
+///
+/// # Returns
+/// The attributes of the path, including those of enclosing bindings, or None if the
+/// cursor isn't on an attribute of a binding.
+fn attrpath_at(root: &SyntaxNode, offset: usize) -> Option<Vec<String>> {
+    let token = root
+        .token_at_offset(TextSize::try_from(offset).ok()?)
+        .find(|token| token.kind() == SyntaxKind::TOKEN_IDENT)?;
+    let attr = token.parent()?;
+    let attrpath = attr.parent()?;
+    if attrpath.kind() != SyntaxKind::NODE_ATTRPATH {
+        return None;
+    }
+
+    let mut path = enclosing_path(&attrpath.parent()?)?;
+    for segment in attrpath.children() {
+        path.push(attr_name(&segment)?);
+        if segment == attr {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Collects the attributes of the bindings enclosing a node, from the outermost one.
+///
+/// # Returns
+/// The attributes, or None if an enclosing binding has a dynamic attribute.
+fn enclosing_path(node: &SyntaxNode) -> Option<Vec<String>> {
+    let mut path = Vec::new();
+    for binding in node
+        .ancestors()
+        .skip(1)
+        .filter(|ancestor| ancestor.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+    {
+        // An unfinished binding swallows the next one, whose attribute path ends up in an error
+        let mut attrs = match binding
+            .children()
+            .find(|child| child.kind() == SyntaxKind::NODE_ERROR)
+        {
+            Some(error) => error
+                .children_with_tokens()
+                .filter_map(|element| element.into_token())
+                .filter(|token| token.kind() == SyntaxKind::TOKEN_IDENT)
+                .map(|token| token.text().to_string())
+                .collect(),
+            None => binding
+                .children()
+                .find(|child| child.kind() == SyntaxKind::NODE_ATTRPATH)?
+                .children()
+                .map(|attr| attr_name(&attr))
+                .collect::<Option<Vec<_>>>()?,
+        };
+        attrs.append(&mut path);
+        path = attrs;
+    }
+    Some(path)
+}
+
+/// Returns the name of a static attribute, such as `enable` or `"enable"`.
+fn attr_name(attr: &SyntaxNode) -> Option<String> {
+    match attr.kind() {
+        SyntaxKind::NODE_IDENT => Some(attr.text().to_string()),
+        SyntaxKind::NODE_STRING => strings::decode(attr, |_| None),
+        _ => None,
+    }
+}
@@ -1,8 +1,14 @@
-use clap::Parser;
-use nix_options_doc::{collect_options, filter_options, generate_doc, prepare_path, Cli};
-use std::collections::HashMap;
+use clap::{CommandFactory, Parser};
+use nix_options_doc::{
+    cache, check, collect_flake_options, collect_options, collect_options_at_rev,
+    collect_options_from_entries, document_title, error::NixDocError, filter_options, generate_doc,
+    imports, inject, lsp, prepare_path, resolve_subdir, search, serve, utils, watch, CacheCommand,
+    Cli, CollectOptions, Command, OptionDoc, PreparedPath,
+};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 
 /// Entry point of the application.
 ///
@@ -16,47 +22,400 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     env_logger::init();
     let cli = Cli::parse();
 
+    if let Some(Command::Cache { action }) = &cli.command {
+        let cache_dir = cache::cache_dir(cli.git.cache_dir.as_deref())?;
+        match action {
+            CacheCommand::Clean => {
+                let count = cache::clean(&cache_dir)?;
+                println!(
+                    "Removed {} cached repositories from {}",
+                    count,
+                    cache_dir.display()
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Completions { shell }) = cli.command {
+        let mut script = Vec::new();
+        clap_complete::generate(
+            shell,
+            &mut Cli::command(),
+            env!("CARGO_PKG_NAME"),
+            &mut script,
+        );
+        std::io::stdout().lock().write_all(&script)?;
+        return Ok(());
+    }
+
     log::info!("Starting {}", env!("CARGO_PKG_NAME"));
     log::debug!("Input path: {}", cli.io.path);
     log::debug!("Output: {}", cli.io.out);
 
-    let (path, _temp_dir) = prepare_path(&cli)?;
+    let prepared = prepare_path(&cli)?;
 
-    log::debug!("Using path: {}", path.display());
-    log::debug!("Collecting options...");
+    match &cli.command {
+        Some(Command::Serve { port }) => return serve(&cli, &prepared, *port),
+        Some(Command::Lsp) => return lsp(&cli, &prepared),
+        Some(Command::Search { query, limit }) => {
+            return search(&cli, &prepared, &query.join(" "), *limit)
+        }
+        Some(Command::Show { option }) => return show(&cli, &prepared, option),
+        _ => {}
+    }
 
-    // Get replacements for any dynamic variables if defined
-    let replacements: HashMap<String, String> = cli.filter.replace.clone().into_iter().collect();
-    let options = collect_options(
-        &path,
-        &cli.util.exclude_dir,
-        &replacements,
-        cli.util.progress,
-        cli.util.follow_symlinks,
-    )?;
+    if !cli.util.watch {
+        let parse_cache = prepared.parse_cache(&cli);
+        let result = run(&cli, &prepared, parse_cache.as_ref());
+        if let Some(e @ NixDocError::OutOfDate(_)) = result
+            .as_ref()
+            .err()
+            .and_then(|e| e.downcast_ref::<NixDocError>())
+        {
+            eprintln!("{}", e);
+            std::process::exit(check::EXIT_OUT_OF_DATE);
+        }
+        return result;
+    }
+
+    // Files read from the object database don't change, and --rev conflicts with --watch
+    if prepared.rev.is_some() {
+        return Err(NixDocError::Watch(
+            "remote repositories can't be watched, use a local path".to_string(),
+        )
+        .into());
+    }
+    let root = prepared.path.canonicalize()?;
+
+    // Unchanged files are reused between runs even if the parse cache isn't persisted
+    let mut parse_cache = prepared
+        .parse_cache(&cli)
+        .unwrap_or_else(cache::ParseCache::in_memory);
+
+    if let Err(e) = run(&cli, &prepared, Some(&parse_cache)) {
+        log::error!("{}", e);
+    }
+    eprintln!(
+        "Watching {} for changes, press Ctrl-C to stop",
+        prepared.path.display()
+    );
+    watch::watch(&prepared.path, &cli.util.file_filter(), |changed| {
+        eprintln!(
+            "{} files changed, regenerating documentation",
+            changed.len()
+        );
+        parse_cache.next_run(Some(&changed_sources(&root, changed)));
+        if let Err(e) = run(&cli, &prepared, Some(&parse_cache)) {
+            log::error!("{}", e);
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Serves the HTML documentation on a local preview server, regenerating it whenever
+/// a Nix file of a local path changes.
+///
+/// # Arguments
+/// - `cli`: The parsed command line arguments.
+/// - `prepared`: The local directory or repository to document.
+/// - `port`: The port to listen on at 127.0.0.1.
+///
+/// # Returns
+/// Returns an error if the server couldn't be started.
+fn serve(
+    cli: &Cli,
+    prepared: &PreparedPath,
+    port: u16,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let server = serve::Server::bind(cli, &format!("127.0.0.1:{}", port))?;
 
-    if options.is_empty() {
-        log::warn!("No NixOS options found in the specified path");
+    let mut parse_cache = prepared
+        .parse_cache(cli)
+        .unwrap_or_else(cache::ParseCache::in_memory);
+    server.update(collect(cli, prepared, Some(&parse_cache))?.unwrap_or_default())?;
+    eprintln!(
+        "Serving documentation at http://{}, press Ctrl-C to stop",
+        server.address()
+    );
+
+    std::thread::scope(|scope| {
+        // Files read from the object database don't change, so only local paths are watched
+        if prepared.rev.is_none() {
+            scope.spawn(|| {
+                let root = match prepared.path.canonicalize() {
+                    Ok(root) => root,
+                    Err(e) => {
+                        log::error!("{}", e);
+                        return;
+                    }
+                };
+                let result = watch::watch(&prepared.path, &cli.util.file_filter(), |changed| {
+                    eprintln!(
+                        "{} files changed, regenerating documentation",
+                        changed.len()
+                    );
+                    parse_cache.next_run(Some(&changed_sources(&root, changed)));
+                    let result = collect(cli, prepared, Some(&parse_cache))
+                        .and_then(|options| Ok(server.update(options.unwrap_or_default())?));
+                    if let Err(e) = result {
+                        log::error!("{}", e);
+                    }
+                });
+                if let Err(e) = result {
+                    log::error!("{}", e);
+                }
+            });
+        }
+        server.run();
+    });
+
+    Ok(())
+}
+
+/// Converts the changed files reported while watching into paths relative to the documented
+/// directory, as used by the parse cache.
+///
+/// # Arguments
+/// - `root`: The absolute path of the documented directory.
+/// - `changed`: The absolute paths of the changed files.
+///
+/// # Returns
+/// The paths of the changed files relative to `root`.
+fn changed_sources(root: &Path, changed: &[PathBuf]) -> Vec<String> {
+    changed
+        .iter()
+        .map(|path| utils::relative_path(path, root))
+        .collect()
+}
+
+/// Runs a language server over stdio for the options of the prepared path, collecting
+/// them again whenever files are saved.
+///
+/// # Arguments
+/// - `cli`: The parsed command line arguments.
+/// - `prepared`: The local directory or repository to index.
+///
+/// # Returns
+/// Returns an error if the connection to the client failed.
+fn lsp(cli: &Cli, prepared: &PreparedPath) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Declarations are located on disk, so the paths of the options must be absolute
+    let root = prepared.path.canonicalize()?;
+
+    let mut parse_cache = prepared
+        .parse_cache(cli)
+        .unwrap_or_else(cache::ParseCache::in_memory);
+    lsp::run(&root, || {
+        parse_cache.next_run(None);
+        match collect(cli, prepared, Some(&parse_cache)) {
+            Ok(options) => options.unwrap_or_default(),
+            Err(e) => {
+                log::error!("{}", e);
+                Vec::new()
+            }
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Prints the options best matching a query.
+///
+/// # Arguments
+/// - `cli`: The parsed command line arguments.
+/// - `prepared`: The local directory or repository to search.
+/// - `query`: The terms to match against the names and descriptions of options.
+/// - `limit`: The maximum number of results to print.
+///
+/// # Returns
+/// Returns an error if the options couldn't be collected or printed.
+fn search(
+    cli: &Cli,
+    prepared: &PreparedPath,
+    query: &str,
+    limit: usize,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let options = lookup_options(cli, prepared)?;
+    let results = search::rank(&options, query);
+    if results.is_empty() {
+        eprintln!("No options match `{}`", query);
         return Ok(());
     }
 
+    let width = textwrap::termwidth();
+    let mut stdout = anstream::stdout().lock();
+    for (i, option) in results.iter().take(limit).enumerate() {
+        if i > 0 {
+            writeln!(stdout)?;
+        }
+        write!(stdout, "{}", search::render_summary(option, width))?;
+    }
+    if results.len() > limit {
+        eprintln!(
+            "\n{} more options match, use --limit to show them",
+            results.len() - limit
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints an option in full.
+///
+/// # Arguments
+/// - `cli`: The parsed command line arguments.
+/// - `prepared`: The local directory or repository declaring the option.
+/// - `name`: The name of the option, with or without the `options.` prefix.
+///
+/// # Returns
+/// Returns an error suggesting similar names if there is no such option.
+fn show(
+    cli: &Cli,
+    prepared: &PreparedPath,
+    name: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let options = lookup_options(cli, prepared)?;
+    let Some(option) = search::find(&options, name) else {
+        let suggestions: Vec<_> = search::rank(&options, name)
+            .into_iter()
+            .take(3)
+            .map(|option| format!("`{}`", option.name))
+            .collect();
+        let mut message = format!("`{}`", name);
+        if !suggestions.is_empty() {
+            message.push_str(&format!(", did you mean {}?", suggestions.join(", ")));
+        }
+        return Err(NixDocError::OptionNotFound(message).into());
+    };
+
+    write!(
+        anstream::stdout().lock(),
+        "{}",
+        search::render_option(option, textwrap::termwidth())
+    )?;
+    Ok(())
+}
+
+/// Collects and filters the options of the prepared path for looking them up, keeping
+/// each declaration once even if several flake modules export it.
+///
+/// # Arguments
+/// - `cli`: The parsed command line arguments.
+/// - `prepared`: The local directory or repository to collect the options of.
+///
+/// # Returns
+/// The options matching the command line filters.
+fn lookup_options(
+    cli: &Cli,
+    prepared: &PreparedPath,
+) -> Result<Vec<OptionDoc>, Box<dyn std::error::Error + Send + Sync>> {
+    let parse_cache = prepared.parse_cache(cli);
+    let options = collect(cli, prepared, parse_cache.as_ref())?.unwrap_or_default();
+
+    let mut seen = HashSet::new();
+    Ok(filter_options(&options, cli)
+        .into_iter()
+        .filter(|option| {
+            seen.insert((
+                option.name.clone(),
+                option.file_path.clone(),
+                option.line_number,
+            ))
+        })
+        .collect())
+}
+
+/// Collects the options of the prepared path and writes the documentation once.
+///
+/// # Arguments
+/// - `cli`: The parsed command line arguments.
+/// - `prepared`: The local directory or repository to document.
+/// - `parse_cache`: The cache of previously parsed files, or None to parse every file.
+///
+/// # Returns
+/// Returns `Ok(())` if the documentation was written; otherwise returns an error with details.
+fn run(
+    cli: &Cli,
+    prepared: &PreparedPath,
+    parse_cache: Option<&cache::ParseCache>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Read the section mapping before collecting, so mistakes are reported early
+    let markdown = cli.io.markdown_options()?;
+    if cli.io.inject && cli.io.out == "stdout" {
+        return Err(NixDocError::Inject("--inject requires an --out file".to_string()).into());
+    }
+    if cli.io.check && cli.io.out == "stdout" {
+        return Err(NixDocError::Check("--check requires an --out file".to_string()).into());
+    }
+
+    let Some(options) = collect(cli, prepared, parse_cache)? else {
+        return Ok(());
+    };
+
     // Apply module filters if specified
-    let filtered_options = filter_options(&options, &cli);
+    let filtered_options = filter_options(&options, cli);
 
     if filtered_options.is_empty() {
-        log::warn!(
-            "No options match the specified filters (from {} total options)",
-            options.len()
-        );
-        return Ok(());
+        if options.is_empty() {
+            log::warn!("No NixOS options found in the specified path");
+        } else {
+            log::warn!(
+                "No options match the specified filters (from {} total options)",
+                options.len()
+            );
+        }
+
+        // Output files are still written (or checked), so that a file documenting
+        // options that no longer exist isn't left behind or reported as up to date
+        if cli.io.out == "stdout" {
+            return Ok(());
+        }
     }
 
     log::debug!("Generating documentation...");
 
-    let output = generate_doc(&filtered_options, cli.io.format, cli.io.sort)?;
+    let title = document_title(&filtered_options, cli);
+    let output = generate_doc(
+        &filtered_options,
+        cli.io.format,
+        cli.io.sort,
+        &title,
+        &markdown,
+    )?;
 
-    // Output to stdout or file path
-    if cli.io.out == "stdout" {
+    // Inject the documentation between the markers of the existing file, if requested
+    let (output, existing) = if cli.io.inject {
+        let existing = fs::read_to_string(&cli.io.out)
+            .map_err(|e| NixDocError::Inject(format!("{}: {}", cli.io.out, e)))?;
+        let updated = inject::inject(&existing, &inject::fragment(&output, cli.io.format)?)?;
+        (updated, Some(existing))
+    } else {
+        (output, None)
+    };
+
+    if cli.io.check {
+        // A missing file is out of date, with all of its lines added
+        let existing = match existing {
+            Some(existing) => existing,
+            None => match fs::read_to_string(&cli.io.out) {
+                Ok(existing) => existing,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e.into()),
+            },
+        };
+
+        if output != existing {
+            let diff = check::unified_diff(&existing, &output, &cli.io.out);
+            anstream::stdout().write_all(check::colorize(&diff).as_bytes())?;
+            return Err(NixDocError::OutOfDate(format!(
+                "{} (run without --check to update it)",
+                cli.io.out
+            ))
+            .into());
+        }
+        log::info!("Documentation in {} is up to date", cli.io.out);
+    } else if cli.io.out == "stdout" {
         let stdout = std::io::stdout();
         let mut handle = stdout.lock();
 
@@ -73,3 +432,114 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     Ok(())
 }
+
+/// Collects the options of the prepared path, writing the import graph if requested.
+///
+/// # Arguments
+/// - `cli`: The parsed command line arguments.
+/// - `prepared`: The local directory or repository to document.
+/// - `parse_cache`: The cache of previously parsed files, or None to parse every file.
+///
+/// # Returns
+/// The collected options, or None if only the import graph should be printed.
+fn collect(
+    cli: &Cli,
+    prepared: &PreparedPath,
+    parse_cache: Option<&cache::ParseCache>,
+) -> Result<Option<Vec<OptionDoc>>, Box<dyn std::error::Error + Send + Sync>> {
+    let path = &prepared.path;
+    let subdir = resolve_subdir(cli);
+    log::debug!("Using path: {}", path.display());
+    log::debug!("Collecting options...");
+
+    // Get replacements for any dynamic variables if defined
+    let replacements: HashMap<String, String> = cli.filter.replace.clone().into_iter().collect();
+    let files = cli.util.file_filter();
+    let submodules = prepared.submodule_options(cli)?;
+    let settings = CollectOptions {
+        files: &files,
+        replacements: &replacements,
+        show_progress: cli.util.progress,
+        follow_symlinks: cli.util.follow_symlinks,
+        submodules: submodules.as_ref(),
+        parse_cache,
+    };
+    // Entry points and the flake are relative to the subdirectory, if any
+    let base = subdir
+        .as_deref()
+        .map(|subdir| subdir.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    // Document the modules exported by a flake separately, if there is one
+    let flake_options = if cli.io.entry.is_empty() && !cli.io.no_flake {
+        collect_flake_options(
+            path,
+            &imports::parse_entry("flake.nix", &base).file,
+            prepared.rev.as_deref(),
+            &settings,
+        )?
+    } else {
+        None
+    };
+
+    let options = if let Some(options) = flake_options {
+        log::info!(
+            "Documenting the modules exported by the flake, use --no-flake to process every file"
+        );
+        options
+    } else if !cli.io.entry.is_empty() {
+        let entries: Vec<_> = cli
+            .io
+            .entry
+            .iter()
+            .map(|entry| imports::parse_entry(entry, &base))
+            .collect();
+
+        let (options, graph) =
+            collect_options_from_entries(path, &entries, prepared.rev.as_deref(), &settings)?;
+
+        match cli.io.import_graph.as_deref() {
+            Some("stdout") => {
+                std::io::stdout()
+                    .lock()
+                    .write_all(graph.to_dot().as_bytes())?;
+                return Ok(None);
+            }
+            Some(graph_path) => {
+                fs::write(graph_path, graph.to_dot())?;
+                log::info!("Import graph written to: {}", graph_path);
+            }
+            None => {}
+        }
+
+        options
+    } else {
+        match prepared.rev {
+            Some(ref rev) => collect_options_at_rev(path, rev, subdir.as_deref(), &settings)?,
+            None => collect_options(path, subdir.as_deref(), &settings)?,
+        }
+    };
+
+    if let Some(parse_cache) = parse_cache {
+        let (hits, misses) = parse_cache.stats();
+        let stats = format!(
+            "Parse cache: {} files unchanged, {} files parsed",
+            hits, misses
+        );
+        if cli.util.progress {
+            eprintln!("{}", stats);
+        } else {
+            log::info!("{}", stats);
+        }
+        // Entries not used by a run over the whole directory belong to files that are gone
+        let full = subdir.is_none()
+            && files.include.is_empty()
+            && files.exclude.is_empty()
+            && files.exclude_dirs.is_empty();
+        if let Err(e) = parse_cache.save(full) {
+            log::warn!("Unable to save the parse cache: {}", e);
+        }
+    }
+
+    Ok(Some(options))
+}
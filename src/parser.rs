@@ -4,19 +4,240 @@
 //! It traverses the abstract syntax tree of Nix files to identify
 //! module options and their metadata.
 
-use crate::utils::{apply_replacements, clean_description, clean_literal_expr, custom_dedent};
+use crate::strings;
+use crate::utils::{apply_replacements, clean_description, dedent_expression, LineIndex, Position};
 use crate::OptionDoc;
 use rnix::{SyntaxKind, SyntaxNode};
 use std::collections::HashMap;
 
+/// Static knowledge about the variables in scope while traversing a Nix file.
+#[derive(Clone, Debug, Default)]
+pub struct Scope {
+    /// Values of variables, used for dynamic attribute names and interpolations
+    replacements: HashMap<String, String>,
+
+    /// Expressions bound by `let` and `rec` attribute sets, used for expanding `inherit`
+    bindings: HashMap<String, SyntaxNode>,
+
+    /// The condition options are declared under, if any
+    condition: Option<String>,
+}
+
+impl Scope {
+    /// Creates the scope at the root of a file.
+    ///
+    /// # Arguments
+    /// - `replacements`: A map of variable replacements given by the user.
+    ///
+    /// # Returns
+    /// A scope knowing only the user-provided variable values.
+    pub fn new(replacements: &HashMap<String, String>) -> Self {
+        Self {
+            replacements: replacements.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the scope for options declared under an additional condition.
+    fn with_condition(&self, condition: &str) -> Self {
+        let condition = condition.split_whitespace().collect::<Vec<_>>().join(" ");
+        let condition = match self.condition {
+            Some(ref outer) => format!("{} && {}", outer, parenthesize(&condition)),
+            None => parenthesize(&condition),
+        };
+
+        Self {
+            condition: Some(condition),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the scope extended with the bindings of a `let ... in` expression or a
+    /// `rec` attribute set, which shadow the variables of the outer scope.
+    fn with_bindings(&self, node: &SyntaxNode) -> Self {
+        let mut scope = self.clone();
+
+        for binding in node
+            .children()
+            .filter(|n| n.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+        {
+            let Some(name) = binding
+                .children()
+                .find(|n| n.kind() == SyntaxKind::NODE_ATTRPATH)
+                .filter(|n| n.children().count() == 1)
+                .and_then(|n| n.children().next())
+                .filter(|n| n.kind() == SyntaxKind::NODE_IDENT)
+                .map(|n| n.text().to_string())
+            else {
+                continue;
+            };
+            let Some(value) = binding.children().nth(1) else {
+                continue;
+            };
+
+            // Bindings may refer to each other, so evaluate them in the extended scope
+            match scope.eval_string(&value) {
+                Some(text) => {
+                    scope.replacements.insert(name.clone(), text);
+                }
+                None => {
+                    scope.replacements.remove(&name);
+                }
+            }
+            scope.bindings.insert(name, value);
+        }
+
+        scope
+    }
+
+    /// Returns the scope extended with the default values of a function's arguments,
+    /// unless values for them were given by the user.
+    fn with_arguments(&self, lambda: &SyntaxNode) -> Self {
+        let mut scope = self.clone();
+
+        let entries = lambda
+            .children()
+            .filter(|n| n.kind() == SyntaxKind::NODE_PATTERN)
+            .flat_map(|n| n.children())
+            .filter(|n| n.kind() == SyntaxKind::NODE_PAT_ENTRY);
+
+        for entry in entries {
+            let mut children = entry.children();
+            let (Some(name), Some(default)) = (children.next(), children.next()) else {
+                continue;
+            };
+            let name = name.text().to_string();
+            scope.bindings.remove(&name);
+            if !scope.replacements.contains_key(&name) {
+                if let Some(text) = scope.eval_string(&default) {
+                    scope.replacements.insert(name, text);
+                }
+            }
+        }
+
+        scope
+    }
+
+    /// Returns the scope without a binding, to avoid following it recursively.
+    fn without(&self, name: &str) -> Self {
+        let mut scope = self.clone();
+        scope.bindings.remove(name);
+        scope
+    }
+
+    /// Returns the scope with a function argument bound to a known name.
+    fn with_value(&self, name: &str, value: &str) -> Self {
+        let mut scope = self.without(name);
+        scope
+            .replacements
+            .insert(name.to_string(), value.to_string());
+        scope
+    }
+
+    /// Returns the scope with a function argument bound to an expression.
+    ///
+    /// # Arguments
+    /// - `name`: The name of the argument.
+    /// - `value`: The expression passed as the argument.
+    /// - `value_scope`: The variables in scope of the expression.
+    ///
+    /// # Returns
+    /// The extended scope, which knows the argument's value if it is a string.
+    fn with_argument(&self, name: &str, value: &SyntaxNode, value_scope: &Scope) -> Self {
+        let mut scope = self.clone();
+        match value_scope.eval_string(value) {
+            Some(text) => {
+                scope.replacements.insert(name.to_string(), text);
+            }
+            None => {
+                scope.replacements.remove(name);
+            }
+        }
+        scope.bindings.insert(name.to_string(), value.clone());
+        scope
+    }
+
+    /// Statically evaluates an expression to a string, if possible.
+    ///
+    /// # Arguments
+    /// - `node`: The expression, such as a string literal whose interpolations are known
+    ///   variables, or a variable with a known value.
+    ///
+    /// # Returns
+    /// The value of the expression, or None if it can't be determined statically.
+    fn eval_string(&self, node: &SyntaxNode) -> Option<String> {
+        match node.kind() {
+            SyntaxKind::NODE_IDENT => self.replacements.get(&node.text().to_string()).cloned(),
+            SyntaxKind::NODE_PAREN => node.children().next().and_then(|n| self.eval_string(&n)),
+            SyntaxKind::NODE_BIN_OP if is_concat(node) => node
+                .children()
+                .map(|n| self.eval_string(&n))
+                .collect::<Option<String>>(),
+            SyntaxKind::NODE_STRING => strings::decode(node, |expr| self.eval_string(expr)),
+            _ => None,
+        }
+    }
+
+    /// Evaluates a string expression as far as possible, for use in descriptions.
+    ///
+    /// # Arguments
+    /// - `node`: The expression, such as an interpolated string or a concatenation of strings.
+    ///
+    /// # Returns
+    /// The value of the expression, with the parts that can't be determined statically
+    /// written as `${...}` interpolations unless the user gave a replacement for them.
+    fn eval_text(&self, node: &SyntaxNode) -> String {
+        match node.kind() {
+            SyntaxKind::NODE_PAREN => node
+                .children()
+                .next()
+                .map(|n| self.eval_text(&n))
+                .unwrap_or_default(),
+            SyntaxKind::NODE_BIN_OP if is_concat(node) => {
+                node.children().map(|n| self.eval_text(&n)).collect()
+            }
+            SyntaxKind::NODE_STRING => {
+                strings::decode(node, |expr| Some(self.eval_text(expr))).unwrap_or_default()
+            }
+            _ => self.eval_string(node).unwrap_or_else(|| {
+                apply_replacements(&format!("${{{}}}", node.text()), &self.replacements)
+            }),
+        }
+    }
+
+    /// Evaluates a segment of an attribute path to its name.
+    ///
+    /// # Arguments
+    /// - `node`: The segment, an identifier, string or `${...}` interpolation.
+    ///
+    /// # Returns
+    /// The evaluated name, or the segment's text with known variables replaced if it
+    /// can't be fully evaluated.
+    fn eval_attr(&self, node: &SyntaxNode) -> String {
+        let value = match node.kind() {
+            SyntaxKind::NODE_IDENT => Some(node.text().to_string()),
+            SyntaxKind::NODE_STRING => self.eval_string(node),
+            SyntaxKind::NODE_DYNAMIC => node.children().next().and_then(|n| self.eval_string(&n)),
+            _ => None,
+        };
+
+        value.unwrap_or_else(|| {
+            apply_replacements(
+                node.text().to_string().trim_matches('"'),
+                &self.replacements,
+            )
+        })
+    }
+}
+
 /// Recursively traverses the syntax tree of a Nix file to extract option definitions.
 ///
 /// # Arguments
 /// - `node`: The current syntax node being processed.
 /// - `file_path`: The relative file path of the Nix file for documentation reference.
 /// - `prefix`: The current option name prefix in the hierarchy.
-/// - `replacements`: A map of variable replacements for dynamic segments.
-/// - `source_text`: The full text of the source file for line number calculation.
+/// - `scope`: The variables in scope, including the user's replacements for dynamic segments.
+/// - `line_index`: The line index of the source file, for determining positions.
 ///
 /// # Returns
 /// A vector of OptionDoc structs representing the found options or an error.
@@ -24,97 +245,361 @@ pub fn visit_node(
     node: &SyntaxNode,
     file_path: &str,
     prefix: &str,
-    replacements: &HashMap<String, String>,
-    source_text: &str,
+    scope: &Scope,
+    line_index: &LineIndex,
 ) -> Result<Vec<OptionDoc>, Box<dyn std::error::Error + Send + Sync>> {
     let mut options = Vec::new();
 
-    if node.kind() == SyntaxKind::NODE_ATTRPATH_VALUE {
-        let key = node
-            .children()
-            .find(|n| n.kind() == SyntaxKind::NODE_ATTRPATH)
-            .as_ref()
-            .map(|n| parse_attrpath(n, replacements));
-
-        if let Some(value_node) = node.children().nth(1) {
-            if let Some(key) = key {
-                let new_prefix = if prefix.is_empty() {
-                    key
-                } else {
-                    format!("{}.{}", prefix, key)
+    match node.kind() {
+        SyntaxKind::NODE_ATTRPATH_VALUE => {
+            let key = node
+                .children()
+                .find(|n| n.kind() == SyntaxKind::NODE_ATTRPATH)
+                .as_ref()
+                .map(|n| parse_attrpath(n, scope));
+
+            if let Some(value_node) = node.children().nth(1) {
+                if let Some(key) = key {
+                    let new_prefix = join_prefix(prefix, &key);
+                    let mut nested_options =
+                        parse_attrset(&value_node, file_path, &new_prefix, scope, line_index)?;
+                    options.append(&mut nested_options);
+                }
+            }
+        }
+        // `inherit foo;` and `inherit (bar) foo;` of let-bound option definitions
+        SyntaxKind::NODE_INHERIT => {
+            let from = node
+                .children()
+                .find(|n| n.kind() == SyntaxKind::NODE_INHERIT_FROM)
+                .and_then(|n| n.children().next());
+
+            for attr in node
+                .children()
+                .filter(|n| n.kind() != SyntaxKind::NODE_INHERIT_FROM)
+            {
+                let name = scope.eval_attr(&attr);
+                let value = match from {
+                    Some(ref from) => resolve_binding(from, scope)
+                        .and_then(|(set, set_scope)| find_binding(&set, &name, &set_scope)),
+                    None => scope
+                        .bindings
+                        .get(&name)
+                        .map(|value| (value.clone(), scope.without(&name))),
                 };
-                let mut nested_options = parse_attrset(
-                    &value_node,
+
+                match value {
+                    Some((value, value_scope)) => {
+                        let mut nested_options = parse_attrset(
+                            &value,
+                            file_path,
+                            &join_prefix(prefix, &name),
+                            &value_scope,
+                            line_index,
+                        )?;
+                        options.append(&mut nested_options);
+                    }
+                    None => log::debug!("Unable to resolve inherited attribute: {}", name),
+                }
+            }
+        }
+        // Only the body of `let ... in` is part of the module
+        SyntaxKind::NODE_LET_IN => {
+            if let Some(body) = node.children().last() {
+                let mut nested_options = visit_node(
+                    &body,
                     file_path,
-                    &new_prefix,
-                    replacements,
-                    source_text,
+                    prefix,
+                    &scope.with_bindings(node),
+                    line_index,
                 )?;
                 options.append(&mut nested_options);
             }
         }
-    } else {
-        // Visit all children for other node types
-        for child in node.children() {
-            let mut child_options =
-                visit_node(&child, file_path, prefix, replacements, source_text)?;
-            options.append(&mut child_options);
+        SyntaxKind::NODE_LAMBDA => {
+            if let Some(body) = node.children().nth(1) {
+                let mut nested_options = visit_node(
+                    &body,
+                    file_path,
+                    prefix,
+                    &scope.with_arguments(node),
+                    line_index,
+                )?;
+                options.append(&mut nested_options);
+            }
+        }
+        SyntaxKind::NODE_ATTR_SET if is_rec(node) => {
+            let scope = scope.with_bindings(node);
+            for child in node.children() {
+                let mut child_options = visit_node(&child, file_path, prefix, &scope, line_index)?;
+                options.append(&mut child_options);
+            }
+        }
+        // `mkIf cond { ... }`, `mkMerge [ ... ]` and similar outside of attribute values
+        _ if is_wrapper(node) => {
+            let mut nested_options = parse_attrset(node, file_path, prefix, scope, line_index)?;
+            options.append(&mut nested_options);
+        }
+        _ => {
+            // Visit all children for other node types
+            for child in node.children() {
+                let mut child_options = visit_node(&child, file_path, prefix, scope, line_index)?;
+                options.append(&mut child_options);
+            }
         }
     }
 
     Ok(options)
 }
 
+/// Appends an attribute name to an option name prefix.
+fn join_prefix(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
 /// Parses an attribute path node and returns a dot-separated string representing the option name.
 ///
 /// # Arguments
 /// - `node`: The syntax node representing the attribute path.
-/// - `replacements`: A map of variable replacements to apply to any dynamic segments.
+/// - `scope`: The variables in scope for evaluating string and dynamic segments.
 ///
 /// # Returns
 /// A dot-separated string that represents the full option name with any variables replaced.
-fn parse_attrpath(node: &SyntaxNode, replacements: &HashMap<String, String>) -> String {
+fn parse_attrpath(node: &SyntaxNode, scope: &Scope) -> String {
     node.children()
-        .map(|child| apply_replacements(&child.text().to_string(), replacements))
+        .map(|child| scope.eval_attr(&child))
         .collect::<Vec<_>>()
         .join(".")
 }
 
-/// Determines the 1-based line number where a syntax node starts in the source file.
+/// Resolves a variable to the expression it is bound to, following chains of variables.
 ///
 /// # Arguments
-/// - `node`: The syntax node for which to determine the line number.
-/// - `source_text`: The complete text of the source file.
+/// - `node`: The expression, typically a variable bound by `let`.
+/// - `scope`: The variables in scope.
 ///
 /// # Returns
-/// The 1-based line number where the node starts, calculated by counting newlines.
-fn get_line_number(node: &SyntaxNode, source_text: &str) -> usize {
-    // Get the text range of this node
-    let text_range = node.text_range();
-    let start_offset: usize = text_range.start().into();
-
-    // Count newlines up to this position
-    let line_count = source_text[..start_offset]
-        .chars()
-        .filter(|&c| c == '\n')
-        .count();
-
-    // Line numbers are 1-based
-    line_count + 1
+/// The bound expression along with the scope to evaluate it in, or None if it isn't bound.
+fn resolve_binding(node: &SyntaxNode, scope: &Scope) -> Option<(SyntaxNode, Scope)> {
+    match node.kind() {
+        SyntaxKind::NODE_IDENT => {
+            let name = node.text().to_string();
+            let value = scope.bindings.get(&name)?;
+            let scope = scope.without(&name);
+            resolve_binding(value, &scope).or(Some((value.clone(), scope)))
+        }
+        SyntaxKind::NODE_PAREN => resolve_binding(&node.children().next()?, scope),
+        _ => None,
+    }
 }
 
-/// Clean and format a description string for documentation.
+/// Finds the value of an attribute in an attribute set expression.
 ///
 /// # Arguments
-/// - `description`: The raw description string from the Nix file.
-/// - `replacements`: A map of variable replacements to apply.
+/// - `set`: The attribute set, possibly wrapped in `let ... in` or parentheses.
+/// - `name`: The name of the attribute.
+/// - `scope`: The variables in scope of the attribute set.
 ///
 /// # Returns
-/// A cleaned and formatted description string with proper indentation and variable substitution.
-fn process_description(description: &str, replacements: &HashMap<String, String>) -> String {
-    let replaced = apply_replacements(description, replacements);
-    let dedented = custom_dedent(&replaced);
-    clean_description(&dedented)
+/// The value of the attribute along with the scope to evaluate it in, if found.
+fn find_binding(set: &SyntaxNode, name: &str, scope: &Scope) -> Option<(SyntaxNode, Scope)> {
+    match set.kind() {
+        SyntaxKind::NODE_ATTR_SET => {
+            let scope = if is_rec(set) {
+                scope.with_bindings(set)
+            } else {
+                scope.clone()
+            };
+            set.children()
+                .filter(|n| n.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+                .find(|n| {
+                    n.children()
+                        .find(|n| n.kind() == SyntaxKind::NODE_ATTRPATH)
+                        .is_some_and(|path| parse_attrpath(&path, &scope) == name)
+                })
+                .and_then(|n| n.children().nth(1))
+                .map(|value| (value, scope))
+        }
+        SyntaxKind::NODE_LET_IN => {
+            find_binding(&set.children().last()?, name, &scope.with_bindings(set))
+        }
+        SyntaxKind::NODE_PAREN => find_binding(&set.children().next()?, name, scope),
+        _ => None,
+    }
+}
+
+/// Determines the positions where a syntax node starts and ends in the source file.
+///
+/// # Arguments
+/// - `node`: The syntax node for which to determine the span.
+/// - `line_index`: The line index of the source file.
+///
+/// # Returns
+/// The 1-based positions of the start of the node and of the end of its last character.
+fn get_span(node: &SyntaxNode, line_index: &LineIndex) -> (Position, Position) {
+    let range = node.text_range();
+    (
+        line_index.position(range.start().into()),
+        line_index.position(range.end().into()),
+    )
+}
+
+/// Extracts the text of a description or literal example from its expression.
+///
+/// # Arguments
+/// - `node`: The expression, a string possibly wrapped in `mdDoc`, `literalMD` or
+///   `literalExpression`.
+/// - `scope`: The variables in scope for evaluating interpolations.
+///
+/// # Returns
+/// The decoded text, or the expression's source if it isn't a string.
+fn doc_text(node: &SyntaxNode, scope: &Scope) -> String {
+    match node.kind() {
+        SyntaxKind::NODE_STRING | SyntaxKind::NODE_PAREN => scope.eval_text(node),
+        SyntaxKind::NODE_BIN_OP if is_concat(node) => scope.eval_text(node),
+        SyntaxKind::NODE_APPLY => {
+            let (function, args) = split_apply(node);
+            match (function_name(&function).as_deref(), args.last()) {
+                (Some("mdDoc" | "literalMD" | "literalExpression"), Some(arg)) => {
+                    doc_text(arg, scope)
+                }
+                _ => node.text().to_string(),
+            }
+        }
+        _ => scope
+            .eval_string(node)
+            .unwrap_or_else(|| node.text().to_string()),
+    }
+}
+
+/// Formats the value of a `default` or `example` attribute for documentation.
+///
+/// # Arguments
+/// - `node`: The expression of the value.
+/// - `scope`: The variables in scope for evaluating interpolations.
+/// - `line_index`: The line index of the source file, for determining the indentation.
+///
+/// # Returns
+/// The Nix code of a `literalExpression`, the text of a `literalMD`, or the source of
+/// any other expression without its indentation.
+fn literal_text(node: &SyntaxNode, scope: &Scope, line_index: &LineIndex) -> String {
+    if node.kind() == SyntaxKind::NODE_APPLY {
+        let (function, _) = split_apply(node);
+        if matches!(
+            function_name(&function).as_deref(),
+            Some("literalExpression" | "literalMD")
+        ) {
+            return doc_text(node, scope).trim().to_string();
+        }
+    }
+
+    dedent_expression(&node.text().to_string(), get_column(node, line_index))
+}
+
+/// Adds the doc comment preceding the declaration of an option to its description.
+///
+/// # Arguments
+/// - `description`: The description given in the option's declaration, if any.
+/// - `node`: The syntax node of the option's declaration (`mkOption { ... }`).
+///
+/// # Returns
+/// The description followed by a `/** ... */` doc comment, or whichever of them exists.
+/// `#` comments are only used when the option has no description.
+fn with_doc_comment(description: Option<String>, node: &SyntaxNode) -> Option<String> {
+    let comment = node
+        .ancestors()
+        .find(|n| n.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+        .and_then(|binding| doc_comment(binding.prev_sibling_or_token(), description.is_none()))
+        .map(|comment| clean_description(&comment));
+
+    match (description, comment) {
+        (Some(description), Some(comment)) => {
+            Some(format!("{}\n\n{}", description.trim_end(), comment))
+        }
+        (description, comment) => description.or(comment),
+    }
+}
+
+/// Extracts the doc comment at the top of a file, documenting the module as a whole.
+///
+/// # Arguments
+/// - `root`: The root node of the file's syntax tree.
+///
+/// # Returns
+/// The text of the `/** ... */` comment preceding the file's expression, if any.
+pub fn file_doc_comment(root: &SyntaxNode) -> Option<String> {
+    let expression = root.children().next()?;
+    let mut first = expression.prev_sibling_or_token();
+
+    // Unlike for options, blank lines may separate the comment from the module
+    while let Some(ref element) = first {
+        if element.kind() != SyntaxKind::TOKEN_WHITESPACE {
+            break;
+        }
+        first = element.prev_sibling_or_token();
+    }
+    doc_comment(first, false).map(|comment| clean_description(&comment))
+}
+
+/// Reads the doc comment ending at a syntax element, walking backwards.
+///
+/// # Arguments
+/// - `last`: The element preceding the documented expression, i.e. the whitespace
+///   following the comment or the comment itself.
+/// - `line_comments`: Whether consecutive `#` comment lines are accepted as well.
+///
+/// # Returns
+/// The text of a `/** ... */` comment (RFC 145), or of consecutive `#` comment lines if
+/// accepted, directly preceding the expression, without their delimiters and indentation.
+fn doc_comment(last: Option<rnix::SyntaxElement>, line_comments: bool) -> Option<String> {
+    let mut lines = Vec::new();
+    let mut current = last;
+
+    while let Some(element) = current {
+        match element {
+            // A blank line separates unrelated comments
+            rnix::NodeOrToken::Token(ref token) if token.kind() == SyntaxKind::TOKEN_WHITESPACE => {
+                if token.text().matches('\n').count() > 1 {
+                    break;
+                }
+            }
+            rnix::NodeOrToken::Token(ref token) if token.kind() == SyntaxKind::TOKEN_COMMENT => {
+                let text = token.text();
+                if let Some(line) = text.strip_prefix('#').filter(|_| line_comments) {
+                    lines.push(
+                        line.strip_prefix(' ')
+                            .unwrap_or(line)
+                            .trim_end()
+                            .to_string(),
+                    );
+                } else if lines.is_empty() && text.starts_with("/**") && text != "/**/" {
+                    // The first line follows the delimiter, so only the others are dedented
+                    let block = text.trim_start_matches("/**").trim_end_matches("*/");
+                    let (first, rest) = block.split_once('\n').unwrap_or((block, ""));
+                    let text = format!("{}\n{}", first.trim(), textwrap::dedent(rest));
+                    let text = text.trim();
+                    return (!text.is_empty()).then(|| text.to_string());
+                } else {
+                    break;
+                }
+            }
+            _ => break,
+        }
+        current = element.prev_sibling_or_token();
+    }
+
+    lines.reverse();
+    let text = lines.join("\n").trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// Determines the 0-based column where a syntax node starts in the source file.
+fn get_column(node: &SyntaxNode, line_index: &LineIndex) -> usize {
+    line_index.position(node.text_range().start().into()).column - 1
 }
 
 /// Parses an attribute set node to extract NixOS module option definitions.
@@ -123,8 +608,8 @@ fn process_description(description: &str, replacements: &HashMap<String, String>
 /// - `node`: The syntax node representing the attribute set.
 /// - `file_path`: The file path of the Nix file for reference.
 /// - `current_prefix`: The current option name hierarchy as a dot-separated string.
-/// - `replacements`: A map of variable replacements for dynamic values.
-/// - `source_text`: The source text of the file for line number calculation.
+/// - `scope`: The variables in scope and the condition the options are declared under.
+/// - `line_index`: The line index of the source file, for determining positions.
 ///
 /// # Returns
 /// A vector of OptionDoc structs representing the options in the attribute set or an error.
@@ -132,62 +617,172 @@ fn parse_attrset(
     node: &SyntaxNode,
     file_path: &str,
     current_prefix: &str,
-    replacements: &HashMap<String, String>,
-    source_text: &str,
+    scope: &Scope,
+    line_index: &LineIndex,
 ) -> Result<Vec<OptionDoc>, Box<dyn std::error::Error + Send + Sync>> {
     let mut options = Vec::new();
 
     match node.kind() {
         // Nested attributes
         SyntaxKind::NODE_ATTR_SET => {
+            let scope = if is_rec(node) {
+                scope.with_bindings(node)
+            } else {
+                scope.clone()
+            };
             for child in node.children() {
                 let mut child_options =
-                    visit_node(&child, file_path, current_prefix, replacements, source_text)?;
+                    visit_node(&child, file_path, current_prefix, &scope, line_index)?;
                 options.append(&mut child_options);
             }
         }
         // Child node, parse for mkOption or mkEnableOption
         SyntaxKind::NODE_APPLY => {
-            // Try to get the function name from SELECT node (lib.mkOption style)
-            let select_fn = node
-                .children()
-                .find(|n| n.kind() == SyntaxKind::NODE_SELECT)
-                .and_then(|n| n.children().last())
-                .map(|n| n.text().to_string());
-
-            // If not found via SELECT, try IDENT node (direct mkOption style)
-            let ident_fn = if select_fn.is_none() {
-                node.children()
-                    .find(|n| n.kind() == SyntaxKind::NODE_IDENT)
-                    .map(|n| n.text().to_string())
-            } else {
-                None
-            };
-
-            // Use whichever function name we found
-            let fn_name = select_fn.or(ident_fn);
+            let (function, args) = split_apply(node);
+            let fn_name = function_name(&function);
             match fn_name.as_deref() {
                 Some("mkEnableOption") => {
-                    let description = node
-                        .children()
-                        .find(|n| n.kind() == SyntaxKind::NODE_STRING)
-                        .map(|n| {
-                            let desc_text =
-                                n.text().to_string().trim_matches(['"', '\'']).to_string();
-                            // Apply replacements and formatting to description
-                            process_description(&desc_text, replacements)
-                        });
+                    // nixpkgs describes enable options as "Whether to enable <name>."
+                    let description = args.first().map(|name| {
+                        clean_description(&format!("Whether to enable {}.", scope.eval_text(name)))
+                    });
 
+                    let (start, end) = get_span(node, line_index);
                     options.push(OptionDoc {
                         name: current_prefix.to_string(),
-                        description,
+                        description: with_doc_comment(description, node),
                         nix_type: "boolean".to_string(),
                         default_value: Some(String::from("false")),
                         example: Some(String::from("true")),
                         file_path: file_path.to_string(),
-                        line_number: get_line_number(node, source_text),
+                        line_number: start.line,
+                        column_number: start.column,
+                        end_line_number: end.line,
+                        end_column_number: end.column,
+                        source_url: None,
+                        module: None,
+                        condition: scope.condition.clone(),
+                        file_doc: None,
                     });
                 }
+                // Conditionally declared options
+                Some("mkIf" | "optionalAttrs") if args.len() == 2 => {
+                    let mut nested_options = parse_attrset(
+                        &args[1],
+                        file_path,
+                        current_prefix,
+                        &scope.with_condition(&args[0].text().to_string()),
+                        line_index,
+                    )?;
+                    options.append(&mut nested_options);
+                }
+                // Merged lists of attribute sets
+                Some("mkMerge") => {
+                    for element in args.iter().flat_map(|list| list.children()) {
+                        let mut nested_options =
+                            parse_attrset(&element, file_path, current_prefix, scope, line_index)?;
+                        options.append(&mut nested_options);
+                    }
+                }
+                // Priority modifiers wrapping their last argument
+                Some("mkDefault" | "mkForce" | "mkOverride" | "mkOptionDefault") => {
+                    if let Some(value) = args.last() {
+                        let mut nested_options =
+                            parse_attrset(value, file_path, current_prefix, scope, line_index)?;
+                        options.append(&mut nested_options);
+                    }
+                }
+                // `genAttrs [ "a" "b" ] (name: mkOption { ... })`
+                Some("genAttrs") if args.len() == 2 => {
+                    let names = literal_list(&args[0], scope).and_then(|(elements, list_scope)| {
+                        elements
+                            .iter()
+                            .map(|element| list_scope.eval_string(element))
+                            .collect::<Option<Vec<_>>>()
+                    });
+                    let (params, body) = lambda_params(&args[1]);
+
+                    for name in names.unwrap_or_else(|| vec![PLACEHOLDER.to_string()]) {
+                        let item_scope = match params.first() {
+                            Some(param) => scope.with_value(param, &name),
+                            None => scope.clone(),
+                        };
+                        let mut nested_options = parse_attrset(
+                            &body,
+                            file_path,
+                            &join_prefix(current_prefix, &name),
+                            &item_scope,
+                            line_index,
+                        )?;
+                        options.append(&mut nested_options);
+                    }
+                }
+                // `mapAttrs (name: value: mkOption { ... }) { a = ...; }`
+                Some("mapAttrs") if args.len() == 2 => {
+                    let (params, body) = lambda_params(&args[0]);
+                    let attrs = literal_attrs(&args[1], scope);
+
+                    let items: Vec<(String, Scope)> = match attrs {
+                        Some((attrs, set_scope)) => attrs
+                            .into_iter()
+                            .map(|(name, value)| {
+                                let mut item_scope = scope.clone();
+                                if let Some(param) = params.first() {
+                                    item_scope = item_scope.with_value(param, &name);
+                                }
+                                if let Some(param) = params.get(1) {
+                                    item_scope =
+                                        item_scope.with_argument(param, &value, &set_scope);
+                                }
+                                (name, item_scope)
+                            })
+                            .collect(),
+                        None => {
+                            let mut item_scope = scope.clone();
+                            if let Some(param) = params.first() {
+                                item_scope = item_scope.with_value(param, PLACEHOLDER);
+                            }
+                            if let Some(param) = params.get(1) {
+                                item_scope = item_scope.without(param);
+                                item_scope.replacements.remove(param);
+                            }
+                            vec![(PLACEHOLDER.to_string(), item_scope)]
+                        }
+                    };
+
+                    for (name, item_scope) in items {
+                        let mut nested_options = parse_attrset(
+                            &body,
+                            file_path,
+                            &join_prefix(current_prefix, &name),
+                            &item_scope,
+                            line_index,
+                        )?;
+                        options.append(&mut nested_options);
+                    }
+                }
+                // `listToAttrs [ { name = ...; value = ...; } ]` or
+                // `listToAttrs (map (name: nameValuePair name (mkOption { ... })) [ ... ])`
+                Some("listToAttrs") if args.len() == 1 => {
+                    for (name, value, item_scope) in name_value_pairs(&args[0], scope) {
+                        let mut nested_options = parse_attrset(
+                            &value,
+                            file_path,
+                            &join_prefix(current_prefix, &name),
+                            &item_scope,
+                            line_index,
+                        )?;
+                        options.append(&mut nested_options);
+                    }
+                }
+                // flake-parts `mkPerSystemOption ({ ... }: { options = ...; })`
+                Some("mkPerSystemOption") => {
+                    if let Some(module) = args.first() {
+                        let mut nested_options =
+                            visit_node(module, file_path, current_prefix, scope, line_index)?;
+                        options.append(&mut nested_options);
+                    }
+                }
                 Some("mkOption") => {
                     let mut nix_type = "any".to_string();
                     let mut description = None;
@@ -210,29 +805,19 @@ fn parse_attrset(
 
                                 match (attr_key.as_deref(), attr_value) {
                                     (Some("type"), Some(v)) => {
-                                        nix_type = custom_dedent(&v.text().to_string());
+                                        nix_type = dedent_expression(
+                                            &v.text().to_string(),
+                                            get_column(&v, line_index),
+                                        );
                                     }
                                     (Some("description"), Some(v)) => {
-                                        let desc_text = v
-                                            .text()
-                                            .to_string()
-                                            .trim_matches(['"', '\''])
-                                            .to_string();
-
-                                        description =
-                                            Some(process_description(&desc_text, replacements));
+                                        description = Some(clean_description(&doc_text(&v, scope)));
                                     }
                                     (Some("default"), Some(v)) => {
-                                        // Clean and process default value
-                                        let raw_value = v.text().to_string();
-                                        let cleaned = clean_literal_expr(&raw_value);
-                                        default_value = Some(custom_dedent(&cleaned));
+                                        default_value = Some(literal_text(&v, scope, line_index));
                                     }
                                     (Some("example"), Some(v)) => {
-                                        // Clean and process example
-                                        let raw_value = v.text().to_string();
-                                        let cleaned = clean_literal_expr(&raw_value);
-                                        example = Some(custom_dedent(&cleaned));
+                                        example = Some(literal_text(&v, scope, line_index));
                                     }
                                     _ => {}
                                 }
@@ -240,14 +825,22 @@ fn parse_attrset(
                         }
                     }
 
+                    let (start, end) = get_span(node, line_index);
                     options.push(OptionDoc {
                         name: current_prefix.to_string(),
-                        description,
+                        description: with_doc_comment(description, node),
                         nix_type,
                         default_value,
                         example,
                         file_path: file_path.to_string(),
-                        line_number: get_line_number(node, source_text),
+                        line_number: start.line,
+                        column_number: start.column,
+                        end_line_number: end.line,
+                        end_column_number: end.column,
+                        source_url: None,
+                        module: None,
+                        condition: scope.condition.clone(),
+                        file_doc: None,
                     });
                 }
                 _ => {
@@ -255,11 +848,61 @@ fn parse_attrset(
                 }
             }
         }
+        // Parenthesized expressions
+        SyntaxKind::NODE_PAREN => {
+            if let Some(inner) = node.children().next() {
+                let mut nested_options =
+                    parse_attrset(&inner, file_path, current_prefix, scope, line_index)?;
+                options.append(&mut nested_options);
+            }
+        }
+        // Both operands of attribute set updates (`a // b`)
+        SyntaxKind::NODE_BIN_OP if is_update(node) => {
+            for operand in node.children() {
+                let mut nested_options =
+                    parse_attrset(&operand, file_path, current_prefix, scope, line_index)?;
+                options.append(&mut nested_options);
+            }
+        }
+        // Option definitions bound by `let` (`options.foo = commonOptions;`)
+        SyntaxKind::NODE_IDENT => {
+            if let Some((value, value_scope)) = resolve_binding(node, scope) {
+                let mut nested_options =
+                    parse_attrset(&value, file_path, current_prefix, &value_scope, line_index)?;
+                options.append(&mut nested_options);
+            }
+        }
+        // Handle `let ... in { ... }`
+        SyntaxKind::NODE_LET_IN => {
+            if let Some(body) = node.children().last() {
+                let mut nested_options = parse_attrset(
+                    &body,
+                    file_path,
+                    current_prefix,
+                    &scope.with_bindings(node),
+                    line_index,
+                )?;
+                options.append(&mut nested_options);
+            }
+        }
+        // flake-parts `perSystem = { ... }: { options = ...; }` modules
+        SyntaxKind::NODE_LAMBDA if current_prefix == "perSystem" => {
+            if let Some(body) = node.children().nth(1) {
+                let mut nested_options = visit_node(
+                    &body,
+                    file_path,
+                    current_prefix,
+                    &scope.with_arguments(node),
+                    line_index,
+                )?;
+                options.append(&mut nested_options);
+            }
+        }
         // Handle `with <expr>;`
         SyntaxKind::NODE_WITH => {
             if let Some(body) = node.children().nth(1) {
                 let mut nested_options =
-                    visit_node(&body, file_path, current_prefix, replacements, source_text)?;
+                    visit_node(&body, file_path, current_prefix, scope, line_index)?;
                 options.append(&mut nested_options);
             }
         }
@@ -270,3 +913,256 @@ fn parse_attrset(
 
     Ok(options)
 }
+
+/// The name of options generated from a list or attribute set that isn't known statically.
+pub const PLACEHOLDER: &str = "<name>";
+
+/// Splits a function into the names of its arguments and its body, e.g. `name` and `value`
+/// for `name: value: ...`.
+///
+/// # Arguments
+/// - `node`: The syntax node of the function.
+///
+/// # Returns
+/// The names of the plain (non-pattern) arguments and the body, which is the node itself if
+/// it isn't a function.
+fn lambda_params(node: &SyntaxNode) -> (Vec<String>, SyntaxNode) {
+    let mut params = Vec::new();
+    let mut current = node.clone();
+
+    loop {
+        match current.kind() {
+            SyntaxKind::NODE_PAREN => match current.children().next() {
+                Some(inner) => current = inner,
+                None => break,
+            },
+            SyntaxKind::NODE_LAMBDA => {
+                let mut children = current.children();
+                let (Some(param), Some(body)) = (children.next(), children.next()) else {
+                    break;
+                };
+                if param.kind() == SyntaxKind::NODE_IDENT_PARAM {
+                    params.push(param.text().to_string());
+                }
+                current = body;
+            }
+            _ => break,
+        }
+    }
+
+    (params, current)
+}
+
+/// Resolves an expression to the elements of a list literal.
+///
+/// # Arguments
+/// - `node`: The expression, a list literal or a variable bound to one.
+/// - `scope`: The variables in scope.
+///
+/// # Returns
+/// The elements of the list along with the scope to evaluate them in, or None if the
+/// list isn't known statically.
+fn literal_list(node: &SyntaxNode, scope: &Scope) -> Option<(Vec<SyntaxNode>, Scope)> {
+    match node.kind() {
+        SyntaxKind::NODE_LIST => Some((node.children().collect(), scope.clone())),
+        SyntaxKind::NODE_PAREN => literal_list(&node.children().next()?, scope),
+        SyntaxKind::NODE_IDENT => {
+            let (value, value_scope) = resolve_binding(node, scope)?;
+            literal_list(&value, &value_scope)
+        }
+        _ => None,
+    }
+}
+
+/// Resolves an expression to the attributes of an attribute set literal.
+///
+/// # Arguments
+/// - `node`: The expression, an attribute set literal or a variable bound to one.
+/// - `scope`: The variables in scope.
+///
+/// # Returns
+/// The names and values of the attributes along with the scope to evaluate the values in,
+/// or None if the attribute set isn't known statically.
+fn literal_attrs(node: &SyntaxNode, scope: &Scope) -> Option<(Vec<(String, SyntaxNode)>, Scope)> {
+    match node.kind() {
+        SyntaxKind::NODE_ATTR_SET => {
+            let scope = if is_rec(node) {
+                scope.with_bindings(node)
+            } else {
+                scope.clone()
+            };
+            let attrs = node
+                .children()
+                .filter(|n| n.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+                .filter_map(|n| {
+                    let name = n
+                        .children()
+                        .find(|n| n.kind() == SyntaxKind::NODE_ATTRPATH)
+                        .map(|path| parse_attrpath(&path, &scope))?;
+                    Some((name, n.children().nth(1)?))
+                })
+                .collect();
+            Some((attrs, scope))
+        }
+        SyntaxKind::NODE_PAREN => literal_attrs(&node.children().next()?, scope),
+        SyntaxKind::NODE_IDENT | SyntaxKind::NODE_LET_IN => {
+            let (value, value_scope) = match node.kind() {
+                SyntaxKind::NODE_IDENT => resolve_binding(node, scope)?,
+                _ => (node.children().last()?, scope.with_bindings(node)),
+            };
+            literal_attrs(&value, &value_scope)
+        }
+        _ => None,
+    }
+}
+
+/// Determines the attributes generated by `listToAttrs`.
+///
+/// # Arguments
+/// - `node`: The argument of `listToAttrs`, a list of `{ name = ...; value = ...; }` sets or
+///   `nameValuePair` applications, or a `map` over a list producing them.
+/// - `scope`: The variables in scope.
+///
+/// # Returns
+/// The names and values of the generated attributes along with the scope to evaluate the
+/// values in. Names that aren't known statically are replaced with a placeholder.
+fn name_value_pairs(node: &SyntaxNode, scope: &Scope) -> Vec<(String, SyntaxNode, Scope)> {
+    if let Some((elements, list_scope)) = literal_list(node, scope) {
+        return elements
+            .iter()
+            .filter_map(|element| name_value_pair(element, &list_scope))
+            .collect();
+    }
+
+    let node = match node.kind() {
+        SyntaxKind::NODE_PAREN => match node.children().next() {
+            Some(inner) => inner,
+            None => return Vec::new(),
+        },
+        _ => node.clone(),
+    };
+    let (function, args) = split_apply(&node);
+    if function_name(&function).as_deref() != Some("map") || args.len() != 2 {
+        return Vec::new();
+    }
+
+    let (params, body) = lambda_params(&args[0]);
+    let Some(param) = params.first() else {
+        return Vec::new();
+    };
+    match literal_list(&args[1], scope) {
+        Some((elements, list_scope)) => elements
+            .iter()
+            .filter_map(|element| {
+                name_value_pair(&body, &scope.with_argument(param, element, &list_scope))
+            })
+            .collect(),
+        None => name_value_pair(&body, &scope.with_value(param, PLACEHOLDER))
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// Determines the name and value of a `{ name = ...; value = ...; }` set or a
+/// `nameValuePair name value` application.
+fn name_value_pair(node: &SyntaxNode, scope: &Scope) -> Option<(String, SyntaxNode, Scope)> {
+    let (name, value, scope) = match node.kind() {
+        SyntaxKind::NODE_PAREN => return name_value_pair(&node.children().next()?, scope),
+        SyntaxKind::NODE_APPLY => {
+            let (function, args) = split_apply(node);
+            if function_name(&function).as_deref() != Some("nameValuePair") || args.len() != 2 {
+                return None;
+            }
+            (args[0].clone(), args[1].clone(), scope.clone())
+        }
+        _ => {
+            let (name, _) = find_binding(node, "name", scope)?;
+            let (value, scope) = find_binding(node, "value", scope)?;
+            (name, value, scope)
+        }
+    };
+
+    let name = scope
+        .eval_string(&name)
+        .unwrap_or_else(|| PLACEHOLDER.to_string());
+    Some((name, value, scope))
+}
+
+/// Splits a (possibly curried) function application into the function and its arguments.
+///
+/// # Arguments
+/// - `node`: The syntax node of the application, e.g. `lib.mkIf cond { ... }`.
+///
+/// # Returns
+/// The syntax node of the applied function and the argument nodes in order.
+fn split_apply(node: &SyntaxNode) -> (SyntaxNode, Vec<SyntaxNode>) {
+    let mut args = Vec::new();
+    let mut current = node.clone();
+
+    while current.kind() == SyntaxKind::NODE_APPLY {
+        let mut children = current.children();
+        let (Some(function), Some(arg)) = (children.next(), children.next()) else {
+            break;
+        };
+        args.push(arg);
+        current = function;
+    }
+
+    args.reverse();
+    (current, args)
+}
+
+/// Determines the name of an applied function, e.g. `mkOption` for `lib.mkOption`.
+///
+/// # Arguments
+/// - `node`: The syntax node of the function.
+///
+/// # Returns
+/// The last segment of the function's name, or None if it isn't a plain identifier or selection.
+fn function_name(node: &SyntaxNode) -> Option<String> {
+    match node.kind() {
+        SyntaxKind::NODE_IDENT => Some(node.text().to_string()),
+        SyntaxKind::NODE_SELECT => node
+            .children()
+            .last()
+            .and_then(|n| n.text().to_string().rsplit('.').next().map(String::from)),
+        _ => None,
+    }
+}
+
+/// Checks whether a node applies a function that wraps attribute sets of options, such as
+/// `mkIf`, `optionalAttrs` or `mkMerge`.
+fn is_wrapper(node: &SyntaxNode) -> bool {
+    node.kind() == SyntaxKind::NODE_APPLY
+        && matches!(
+            function_name(&split_apply(node).0).as_deref(),
+            Some("mkIf" | "optionalAttrs" | "mkMerge")
+        )
+}
+
+/// Checks whether a binary operation is an attribute set update (`//`).
+fn is_update(node: &SyntaxNode) -> bool {
+    node.children_with_tokens()
+        .any(|child| child.kind() == SyntaxKind::TOKEN_UPDATE)
+}
+
+/// Checks whether a binary operation is a concatenation (`+`).
+fn is_concat(node: &SyntaxNode) -> bool {
+    node.children_with_tokens()
+        .any(|child| child.kind() == SyntaxKind::TOKEN_ADD)
+}
+
+/// Checks whether an attribute set is recursive (`rec { ... }`).
+fn is_rec(node: &SyntaxNode) -> bool {
+    node.children_with_tokens()
+        .any(|child| child.kind() == SyntaxKind::TOKEN_REC)
+}
+
+/// Wraps a condition in parentheses unless it is a single term.
+fn parenthesize(condition: &str) -> String {
+    if condition.contains(' ') && !(condition.starts_with('(') && condition.ends_with(')')) {
+        format!("({})", condition)
+    } else {
+        condition.to_string()
+    }
+}
@@ -0,0 +1,441 @@
+//! The query module implements the query language used to filter options.
+//!
+//! A query combines conditions of the form `field:value` with `AND`, `OR`, `NOT` and
+//! parentheses, e.g. `name:services.* AND type:listOf AND NOT has:example`. Adjacent
+//! conditions without an operator are combined with `AND`. Queries are compiled once,
+//! including their glob patterns, and then evaluated against each option.
+
+use crate::error::NixDocError;
+use crate::OptionDoc;
+use globset::{GlobBuilder, GlobMatcher};
+
+/// Fields that can be matched, listed in parse errors.
+const FIELDS: &str = "name, type, description, default, example, file, module, has";
+
+/// Fields that can be checked for presence with `has:`, listed in parse errors.
+const HAS_FIELDS: &str = "description, default, example, condition, module";
+
+/// A compiled query.
+#[derive(Clone, Debug)]
+pub struct Query {
+    expr: Expr,
+}
+
+/// A boolean expression over conditions.
+#[derive(Clone, Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Condition(Condition),
+}
+
+/// A condition on a field of an option.
+#[derive(Clone, Debug)]
+enum Condition {
+    /// The name, without the `options.` prefix, matches a glob where `*` matches any text
+    Name(GlobMatcher),
+
+    /// The type contains a text or matches a glob, ignoring case
+    Type(Text),
+
+    /// The description contains a text or matches a glob, ignoring case
+    Description(Text),
+
+    /// The default value contains a text or matches a glob, ignoring case
+    Default(Text),
+
+    /// The example contains a text or matches a glob, ignoring case
+    Example(Text),
+
+    /// The file path matches a glob where `*` doesn't match `/` and `**` matches directories
+    File(GlobMatcher),
+
+    /// The exporting flake module contains a text or matches a glob, ignoring case
+    Module(Text),
+
+    /// The field is present
+    Has(Field),
+}
+
+/// A pattern for free text.
+#[derive(Clone, Debug)]
+enum Text {
+    /// Matches texts containing the lowercase string
+    Contains(String),
+
+    /// Matches texts matching the case-insensitive glob
+    Glob(GlobMatcher),
+}
+
+/// An optional field of an option.
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    Description,
+    Default,
+    Example,
+    Condition,
+    Module,
+}
+
+/// A token of a query, with the character position it starts at.
+#[derive(Debug)]
+enum Token {
+    LParen(usize),
+    RParen(usize),
+    Word(usize, String),
+}
+
+impl Token {
+    /// Returns the position of the token.
+    fn position(&self) -> usize {
+        match self {
+            Token::LParen(position) | Token::RParen(position) | Token::Word(position, _) => {
+                *position
+            }
+        }
+    }
+
+    /// Checks whether the token is the given operator, ignoring case.
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(_, word) if word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+impl Query {
+    /// Compiles a query.
+    ///
+    /// # Arguments
+    /// - `query`: The text of the query.
+    ///
+    /// # Returns
+    /// The compiled query, or an error pointing at the position of the problem.
+    pub fn parse(query: &str) -> Result<Self, NixDocError> {
+        let tokens =
+            tokenize(query).map_err(|(position, message)| error(query, position, message))?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            index: 0,
+            end: query.chars().count(),
+        };
+
+        let expr = parser
+            .parse_or()
+            .map_err(|(position, message)| error(query, position, message))?;
+        if let Some(token) = parser.peek() {
+            let message = match token {
+                Token::RParen(_) => "unexpected `)` without a matching `(`".to_string(),
+                _ => "expected `AND`, `OR` or the end of the query".to_string(),
+            };
+            return Err(error(query, token.position(), message));
+        }
+
+        Ok(Self { expr })
+    }
+
+    /// Checks whether an option matches the query.
+    pub fn matches(&self, option: &OptionDoc) -> bool {
+        self.expr.matches(option)
+    }
+}
+
+impl Expr {
+    /// Evaluates the expression for an option.
+    fn matches(&self, option: &OptionDoc) -> bool {
+        match self {
+            Expr::And(left, right) => left.matches(option) && right.matches(option),
+            Expr::Or(left, right) => left.matches(option) || right.matches(option),
+            Expr::Not(expr) => !expr.matches(option),
+            Expr::Condition(condition) => condition.matches(option),
+        }
+    }
+}
+
+impl Condition {
+    /// Evaluates the condition for an option.
+    fn matches(&self, option: &OptionDoc) -> bool {
+        match self {
+            Condition::Name(glob) => {
+                glob.is_match(option.name.strip_prefix("options.").unwrap_or(&option.name))
+            }
+            Condition::Type(text) => text.matches(Some(&option.nix_type)),
+            Condition::Description(text) => text.matches(option.description.as_deref()),
+            Condition::Default(text) => text.matches(option.default_value.as_deref()),
+            Condition::Example(text) => text.matches(option.example.as_deref()),
+            Condition::File(glob) => glob.is_match(&option.file_path),
+            Condition::Module(text) => text.matches(option.module.as_deref()),
+            Condition::Has(field) => match field {
+                Field::Description => option.description.is_some(),
+                Field::Default => option.default_value.is_some(),
+                Field::Example => option.example.is_some(),
+                Field::Condition => option.condition.is_some(),
+                Field::Module => option.module.is_some(),
+            },
+        }
+    }
+}
+
+impl Text {
+    /// Compiles a text pattern, which is a glob if it contains wildcards.
+    fn new(pattern: &str) -> Result<Self, String> {
+        if pattern.contains(['*', '?', '[']) {
+            Ok(Text::Glob(glob(pattern, true, false)?))
+        } else {
+            Ok(Text::Contains(pattern.to_lowercase()))
+        }
+    }
+
+    /// Checks whether a text, if present, matches the pattern.
+    fn matches(&self, text: Option<&str>) -> bool {
+        match (self, text) {
+            (Text::Contains(pattern), Some(text)) => text.to_lowercase().contains(pattern),
+            (Text::Glob(glob), Some(text)) => glob.is_match(text),
+            (_, None) => false,
+        }
+    }
+}
+
+/// Recursive descent parser over the tokens of a query, from the lowest precedence
+/// operator (`OR`) to the highest (`NOT`).
+struct Parser<'a> {
+    tokens: &'a [Token],
+    index: usize,
+
+    /// The position of the end of the query, for errors about missing tokens
+    end: usize,
+}
+
+/// An error at a character position of the query.
+type ParseError = (usize, String);
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.index);
+        self.index += 1;
+        token
+    }
+
+    /// Parses conditions combined with `OR`.
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_and()?;
+        while self.peek().is_some_and(|token| token.is_keyword("OR")) {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    /// Parses conditions combined with `AND`, or by juxtaposition.
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_not()?;
+        loop {
+            match self.peek() {
+                Some(token) if token.is_keyword("AND") => {
+                    self.next();
+                }
+                Some(token) if token.is_keyword("OR") => break,
+                Some(Token::LParen(_)) | Some(Token::Word(..)) => {}
+                _ => break,
+            }
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    /// Parses a negated condition or a primary expression.
+    fn parse_not(&mut self) -> Result<Expr, ParseError> {
+        if self.peek().is_some_and(|token| token.is_keyword("NOT")) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    /// Parses a parenthesized expression or a condition.
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let end = self.end;
+        match self.next() {
+            None => Err((
+                end,
+                "expected a condition such as `name:services.*`".to_string(),
+            )),
+            Some(Token::RParen(position)) => Err((*position, "expected a condition".to_string())),
+            Some(Token::LParen(position)) => {
+                let open = *position;
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen(_)) => Ok(expr),
+                    _ => Err((open, "unclosed `(`".to_string())),
+                }
+            }
+            Some(Token::Word(position, word)) => {
+                let position = *position;
+                if ["AND", "OR", "NOT"]
+                    .iter()
+                    .any(|keyword| word.eq_ignore_ascii_case(keyword))
+                {
+                    return Err((position, format!("expected a condition before `{}`", word)));
+                }
+                parse_condition(word)
+                    .map(Expr::Condition)
+                    .map_err(|(offset, message)| (position + offset, message))
+            }
+        }
+    }
+}
+
+/// Compiles a condition of the form `field:value`.
+///
+/// # Returns
+/// The condition, or an error at a character offset of the word.
+fn parse_condition(word: &str) -> Result<Condition, ParseError> {
+    let Some((field, value)) = word.split_once(':') else {
+        return Err((
+            0,
+            format!(
+                "expected `field:value`, found `{}` (fields are {})",
+                word, FIELDS
+            ),
+        ));
+    };
+    // Errors about the value point at the value
+    let value_offset = field.chars().count() + 1;
+    let value = unquote(value);
+    if value.is_empty() {
+        return Err((value_offset, format!("missing value after `{}:`", field)));
+    }
+
+    let condition = match field.to_lowercase().as_str() {
+        "name" => glob(&value, false, false).map(Condition::Name),
+        "type" => Text::new(&value).map(Condition::Type),
+        "description" | "desc" => Text::new(&value).map(Condition::Description),
+        "default" => Text::new(&value).map(Condition::Default),
+        "example" => Text::new(&value).map(Condition::Example),
+        "file" => glob(&value, false, true).map(Condition::File),
+        "module" => Text::new(&value).map(Condition::Module),
+        "has" => match value.to_lowercase().as_str() {
+            "description" | "desc" => Ok(Condition::Has(Field::Description)),
+            "default" => Ok(Condition::Has(Field::Default)),
+            "example" => Ok(Condition::Has(Field::Example)),
+            "condition" => Ok(Condition::Has(Field::Condition)),
+            "module" => Ok(Condition::Has(Field::Module)),
+            _ => Err(format!(
+                "unknown field `{}` for `has:`, expected one of {}",
+                value, HAS_FIELDS
+            )),
+        },
+        _ => {
+            return Err((
+                0,
+                format!("unknown field `{}`, expected one of {}", field, FIELDS),
+            ))
+        }
+    };
+    condition.map_err(|message| (value_offset, message))
+}
+
+/// Compiles a glob pattern.
+///
+/// # Arguments
+/// - `pattern`: The glob pattern.
+/// - `case_insensitive`: Whether to ignore case.
+/// - `literal_separator`: Whether `*` and `?` don't match `/`, so only `**` matches directories.
+///
+/// # Returns
+/// The compiled glob, or an error message.
+fn glob(
+    pattern: &str,
+    case_insensitive: bool,
+    literal_separator: bool,
+) -> Result<GlobMatcher, String> {
+    GlobBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .literal_separator(literal_separator)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|e| format!("invalid pattern `{}`: {}", pattern, e.kind()))
+}
+
+/// Removes the double quotes around a value, decoding `\"` and `\\`.
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"') {
+        Some(quoted) => {
+            let quoted = quoted.strip_suffix('"').unwrap_or(quoted);
+            let mut unquoted = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match (c, chars.clone().next()) {
+                    ('\\', Some(escaped @ ('"' | '\\'))) => {
+                        unquoted.push(escaped);
+                        chars.next();
+                    }
+                    _ => unquoted.push(c),
+                }
+            }
+            unquoted
+        }
+        None => value.to_string(),
+    }
+}
+
+/// Splits a query into parentheses and words, keeping quoted text in a single word.
+///
+/// # Returns
+/// The tokens, or the position of an unterminated quote.
+fn tokenize(query: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().enumerate().peekable();
+
+    while let Some((position, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::LParen(position)),
+            ')' => tokens.push(Token::RParen(position)),
+            _ => {
+                let mut word = String::from(c);
+                let mut quote = (c == '"').then_some(position);
+                while let Some(&(next_position, next)) = chars.peek() {
+                    if quote.is_none() && (next.is_whitespace() || next == '(' || next == ')') {
+                        break;
+                    }
+                    chars.next();
+                    word.push(next);
+                    if next == '\\' && quote.is_some() {
+                        if let Some((_, escaped)) = chars.next() {
+                            word.push(escaped);
+                        }
+                    } else if next == '"' {
+                        quote = match quote {
+                            Some(_) => None,
+                            None => Some(next_position),
+                        };
+                    }
+                }
+                if let Some(start) = quote {
+                    return Err((start, "unterminated `\"`".to_string()));
+                }
+                tokens.push(Token::Word(position, word));
+            }
+        }
+    }
+
+    if tokens.is_empty() {
+        return Err((0, "empty query".to_string()));
+    }
+    Ok(tokens)
+}
+
+/// Formats a parse error, pointing at its position in the query.
+fn error(query: &str, position: usize, message: String) -> NixDocError {
+    NixDocError::Query(format!(
+        "{} at column {}\n  {}\n  {}^",
+        message,
+        position + 1,
+        query,
+        " ".repeat(position)
+    ))
+}
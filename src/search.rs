@@ -0,0 +1,207 @@
+//! The search module looks up options and renders them for the terminal.
+//!
+//! Options are ranked by fuzzy matching the terms of a query against their names
+//! and the words of their descriptions, and rendered either as a compact summary
+//! or in full, with colors that are stripped when the output isn't a terminal.
+
+use crate::OptionDoc;
+use anstyle::{AnsiColor, Style};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+
+/// Style of option names.
+const NAME: Style = Style::new().bold();
+
+/// Style of option types.
+const TYPE: Style = AnsiColor::Cyan.on_default();
+
+/// Style of default values and examples.
+const VALUE: Style = AnsiColor::Yellow.on_default();
+
+/// Style of field labels.
+const LABEL: Style = Style::new().dimmed();
+
+/// Matches in option names count more than matches in descriptions.
+const NAME_WEIGHT: i64 = 2;
+
+/// Indentation of the descriptions of search results.
+const INDENT: &str = "    ";
+
+/// Width of the labels of the fields of an option rendered in full.
+const LABEL_WIDTH: usize = 13;
+
+/// Ranks the options matching a query, best matches first.
+///
+/// # Arguments
+/// - `options`: The options to search.
+/// - `query`: Whitespace-separated terms, each of which must fuzzy match the name of
+///   an option or a word of its description.
+///
+/// # Returns
+/// The matching options, ordered by descending score and then by name.
+pub fn rank<'a>(options: &'a [OptionDoc], query: &str) -> Vec<&'a OptionDoc> {
+    let matcher = SkimMatcherV2::default();
+    let terms: Vec<&str> = query.split_whitespace().collect();
+
+    let mut ranked: Vec<(i64, &OptionDoc)> = options
+        .iter()
+        .filter_map(|option| {
+            terms
+                .iter()
+                .map(|term| score(&matcher, option, term))
+                .sum::<Option<i64>>()
+                .map(|score| (score, option))
+        })
+        .collect();
+    ranked.sort_by(|(a, a_option), (b, b_option)| {
+        b.cmp(a).then_with(|| a_option.name.cmp(&b_option.name))
+    });
+
+    ranked.into_iter().map(|(_, option)| option).collect()
+}
+
+/// Scores how well a term matches an option.
+///
+/// Descriptions are matched word by word, since fuzzy matching a term against a whole
+/// paragraph matches almost any term.
+fn score(matcher: &SkimMatcherV2, option: &OptionDoc, term: &str) -> Option<i64> {
+    let name = matcher
+        .fuzzy_match(&option.name, term)
+        .map(|score| score * NAME_WEIGHT);
+    let description = option.description.as_deref().and_then(|description| {
+        description
+            .split_whitespace()
+            .filter_map(|word| matcher.fuzzy_match(word, term))
+            .max()
+    });
+    name.max(description)
+}
+
+/// Finds an option by its name, with or without the `options.` prefix.
+///
+/// # Arguments
+/// - `options`: The options to search.
+/// - `name`: The name of the option.
+///
+/// # Returns
+/// The option, or None if no option has the name.
+pub fn find<'a>(options: &'a [OptionDoc], name: &str) -> Option<&'a OptionDoc> {
+    let name = name.strip_prefix("options.").unwrap_or(name);
+    options
+        .iter()
+        .find(|option| option.name.strip_prefix("options.").unwrap_or(&option.name) == name)
+}
+
+/// Renders an option as a search result: its name, type and default value on one
+/// line, followed by the first paragraph of its description.
+///
+/// # Arguments
+/// - `option`: The option to render.
+/// - `width`: The width of the terminal.
+///
+/// # Returns
+/// The rendered option, ending with a newline.
+pub fn render_summary(option: &OptionDoc, width: usize) -> String {
+    let mut output = format!(
+        "{NAME}{}{NAME:#}  {TYPE}{}{TYPE:#}",
+        option.name,
+        first_line(&option.nix_type)
+    );
+    if let Some(default) = &option.default_value {
+        output.push_str(&format!(
+            " {LABEL}={LABEL:#} {VALUE}{}{VALUE:#}",
+            first_line(default)
+        ));
+    }
+    output.push('\n');
+
+    if let Some(description) = &option.description {
+        let paragraph = description.split("\n\n").next().unwrap_or_default();
+        let options = textwrap::Options::new(width.max(INDENT.len() + 20))
+            .initial_indent(INDENT)
+            .subsequent_indent(INDENT);
+        for line in textwrap::wrap(paragraph, options) {
+            output.push_str(&line);
+            output.push('\n');
+        }
+    }
+
+    output
+}
+
+/// Renders an option in full: its name, description and every known field.
+///
+/// # Arguments
+/// - `option`: The option to render.
+/// - `width`: The width of the terminal.
+///
+/// # Returns
+/// The rendered option, ending with a newline.
+pub fn render_option(option: &OptionDoc, width: usize) -> String {
+    let mut output = format!("{NAME}{}{NAME:#}\n", option.name);
+
+    if let Some(description) = &option.description {
+        output.push('\n');
+        for paragraph in description.split("\n\n") {
+            // Keep code blocks and lists as they are, only wrap prose
+            if paragraph
+                .lines()
+                .any(|line| line.starts_with([' ', '-', '*', '`']))
+            {
+                output.push_str(paragraph);
+                output.push('\n');
+            } else {
+                output.push_str(&textwrap::fill(paragraph, width.max(40)));
+                output.push('\n');
+            }
+            output.push('\n');
+        }
+    } else {
+        output.push('\n');
+    }
+
+    write_field(&mut output, "Type", &option.nix_type, TYPE);
+    if let Some(default) = &option.default_value {
+        write_field(&mut output, "Default", default, VALUE);
+    }
+    if let Some(example) = &option.example {
+        write_field(&mut output, "Example", example, VALUE);
+    }
+    if let Some(condition) = &option.condition {
+        write_field(&mut output, "Declared if", condition, Style::new());
+    }
+    if let Some(module) = &option.module {
+        write_field(&mut output, "Module", module, Style::new());
+    }
+    let location = match &option.source_url {
+        Some(_) => option.source_link(),
+        None => format!(
+            "{}:{}:{}",
+            option.file_path, option.line_number, option.column_number
+        ),
+    };
+    write_field(&mut output, "Declared in", &location, Style::new());
+
+    output
+}
+
+/// Writes a labelled field, aligning the continuation lines of multi-line values.
+fn write_field(output: &mut String, label: &str, value: &str, style: Style) {
+    let label = format!("{}:", label);
+    for (i, line) in value.lines().enumerate() {
+        if i == 0 {
+            output.push_str(&format!("{LABEL}{:<LABEL_WIDTH$}{LABEL:#}", label));
+        } else {
+            output.push_str(&" ".repeat(LABEL_WIDTH));
+        }
+        output.push_str(&format!("{style}{}{style:#}\n", line));
+    }
+}
+
+/// Returns the first line of a value, marking that the rest is omitted.
+fn first_line(value: &str) -> String {
+    match value.split_once('\n') {
+        Some((first, _)) => format!("{} …", first.trim_end()),
+        None => value.to_string(),
+    }
+}
@@ -0,0 +1,252 @@
+//! The serve module hosts the HTML documentation on a local preview server.
+//!
+//! The page is regenerated whenever new options are collected and reloaded in the
+//! browser by a small inline script, so no external assets are needed. The options
+//! are also served as JSON, filtered by query parameters, for use by other tools.
+
+use crate::error::NixDocError;
+use crate::generate::markdown::MarkdownOptions;
+use crate::query::Query;
+use crate::{apply_filters, document_title, generate_doc, utils, Cli, OptionDoc, OutputFormat};
+use std::sync::RwLock;
+
+/// Path of the endpoint returning the generation of the page, polled for live reload.
+const GENERATION_PATH: &str = "/api/generation";
+
+/// Path of the endpoint returning the filtered options as JSON.
+const OPTIONS_PATH: &str = "/api/options";
+
+/// Script reloading the page once a newer generation of the documentation is available.
+const RELOAD_SCRIPT: &str = r#"    <script>
+        (() => {
+            const generation = "{generation}";
+            setInterval(async () => {
+                try {
+                    const response = await fetch("{path}");
+                    if (response.ok && (await response.text()) !== generation) {
+                        location.reload();
+                    }
+                } catch (e) {
+                    // The server is restarting or gone, keep polling
+                }
+            }, 1000);
+        })();
+    </script>
+"#;
+
+/// A response to a request made to the preview server.
+#[derive(Debug)]
+pub struct Response {
+    /// The HTTP status code
+    pub status: u16,
+
+    /// The MIME type of the body
+    pub content_type: &'static str,
+
+    /// The body of the response
+    pub body: String,
+}
+
+impl Response {
+    /// Creates a successful response.
+    fn ok(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    /// Creates an error response with a plain text message.
+    fn error(status: u16, message: String) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: message,
+        }
+    }
+}
+
+/// The documentation currently served.
+struct State {
+    /// The collected options, before filtering
+    options: Vec<OptionDoc>,
+
+    /// The generated HTML page
+    page: String,
+
+    /// Incremented whenever the options are updated
+    generation: u64,
+}
+
+/// A local HTTP server previewing the HTML documentation.
+pub struct Server<'a> {
+    /// The CLI arguments containing the filter and output options
+    cli: &'a Cli,
+
+    /// The listening HTTP server
+    http: tiny_http::Server,
+
+    /// The documentation currently served
+    state: RwLock<State>,
+}
+
+impl<'a> Server<'a> {
+    /// Binds the preview server to an address, serving an empty page until options are set.
+    ///
+    /// # Arguments
+    /// - `cli`: The CLI arguments containing the filter and output options.
+    /// - `address`: The address to listen on, such as `127.0.0.1:8080`.
+    ///
+    /// # Returns
+    /// The server, or an error if the address couldn't be bound.
+    pub fn bind(cli: &'a Cli, address: &str) -> Result<Self, NixDocError> {
+        let http = tiny_http::Server::http(address).map_err(NixDocError::server_error)?;
+        Ok(Self {
+            cli,
+            http,
+            state: RwLock::new(State {
+                options: Vec::new(),
+                page: String::new(),
+                generation: 0,
+            }),
+        })
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn address(&self) -> String {
+        self.http.server_addr().to_string()
+    }
+
+    /// Replaces the served options and regenerates the page, reloading open browsers.
+    ///
+    /// # Arguments
+    /// - `options`: The collected options, before filtering.
+    ///
+    /// # Returns
+    /// An error if the page couldn't be generated.
+    pub fn update(&self, options: Vec<OptionDoc>) -> Result<(), NixDocError> {
+        let filtered = apply_filters(&options, &self.cli.filter, &self.cli.io);
+        let title = document_title(&filtered, self.cli);
+        let html = generate_doc(
+            &filtered,
+            OutputFormat::Html,
+            self.cli.io.sort,
+            &title,
+            &MarkdownOptions::default(),
+        )?;
+
+        let mut state = self
+            .state
+            .write()
+            .map_err(|_| NixDocError::server_error("The served documentation is unavailable"))?;
+        state.generation += 1;
+        state.page = inject_reload_script(&html, state.generation);
+        state.options = options;
+        Ok(())
+    }
+
+    /// Handles requests until the server fails.
+    pub fn run(&self) {
+        for request in self.http.incoming_requests() {
+            let response = self.respond(request.url());
+            log::debug!("{} {} {}", request.method(), request.url(), response.status);
+
+            let mut http_response =
+                tiny_http::Response::from_string(response.body).with_status_code(response.status);
+            if let Ok(header) = tiny_http::Header::from_bytes("Content-Type", response.content_type)
+            {
+                http_response.add_header(header);
+            }
+            let result = request.respond(http_response);
+            if let Err(e) = result {
+                log::warn!("Unable to send a response: {}", e);
+            }
+        }
+    }
+
+    /// Computes the response to a request.
+    ///
+    /// # Arguments
+    /// - `url`: The requested URL, with its query string.
+    ///
+    /// # Returns
+    /// The page, the filtered options as JSON, the generation of the page, or an error.
+    pub fn respond(&self, url: &str) -> Response {
+        let Ok(state) = self.state.read() else {
+            return Response::error(500, "The served documentation is unavailable".to_string());
+        };
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+
+        match path {
+            "/" | "/index.html" => Response::ok("text/html; charset=utf-8", state.page.clone()),
+            GENERATION_PATH => {
+                Response::ok("text/plain; charset=utf-8", state.generation.to_string())
+            }
+            OPTIONS_PATH => match self.options_json(&state.options, query) {
+                Ok(json) => Response::ok("application/json", json),
+                Err(message) => Response::error(400, message),
+            },
+            _ => Response::error(404, format!("Not found: {}", path)),
+        }
+    }
+
+    /// Filters the options by the query parameters of a request and serializes them.
+    ///
+    /// The parameters `prefix`, `type`, `search`, `has_default`, `has_description` and
+    /// `query` take precedence over the corresponding command line filters.
+    ///
+    /// # Arguments
+    /// - `options`: The collected options, before filtering.
+    /// - `query`: The query string of the request.
+    ///
+    /// # Returns
+    /// The options as JSON, or a message describing an invalid parameter.
+    fn options_json(&self, options: &[OptionDoc], query: &str) -> Result<String, String> {
+        let mut filter = self.cli.filter.clone();
+        for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            let value = value.into_owned();
+            match key.as_ref() {
+                "prefix" => filter.filter_by_prefix = Some(value),
+                "type" => filter.filter_by_type = Some(value),
+                "search" => filter.search = Some(utils::parse_search(&value)?),
+                "has_default" => filter.has_default = parse_flag(&value)?,
+                "has_description" => filter.has_description = parse_flag(&value)?,
+                "query" => {
+                    filter.query = Some(Query::parse(&value).map_err(|e| e.to_string())?);
+                }
+                key => return Err(format!("Unknown parameter: {}", key)),
+            }
+        }
+
+        let filtered = apply_filters(options, &filter, &self.cli.io);
+        generate_doc(
+            &filtered,
+            OutputFormat::Json,
+            self.cli.io.sort,
+            "",
+            &MarkdownOptions::default(),
+        )
+        .map_err(|e| e.to_string())
+    }
+}
+
+/// Inserts the live reload script at the end of the body of a page.
+fn inject_reload_script(html: &str, generation: u64) -> String {
+    let script = RELOAD_SCRIPT
+        .replace("{generation}", &generation.to_string())
+        .replace("{path}", GENERATION_PATH);
+    match html.rfind("</body>") {
+        Some(end) => format!("{}{}{}", &html[..end], script, &html[end..]),
+        None => format!("{}{}", html, script),
+    }
+}
+
+/// Parses the value of a boolean query parameter, where an empty value means true.
+fn parse_flag(value: &str) -> Result<bool, String> {
+    match value {
+        "" | "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        other => Err(format!("Invalid boolean value: {}", other)),
+    }
+}
@@ -0,0 +1,167 @@
+//! The strings module decodes Nix string literals.
+//!
+//! It implements the escape sequences of double-quoted and indented strings,
+//! and the stripping of common indentation from indented strings, the way
+//! Nix evaluates them.
+
+use rnix::{NodeOrToken, SyntaxKind, SyntaxNode};
+
+/// Marks the position of an interpolation while decoding the literal parts of a string.
+const INTERPOLATION: char = '\u{E000}';
+
+/// Decodes a string literal into its value.
+///
+/// # Arguments
+/// - `node`: The syntax node of the string, either `"..."` or `''...''`.
+/// - `interpolate`: Evaluates the expression of an interpolation (`${...}`) to its value.
+///
+/// # Returns
+/// The value of the string, or None if an interpolation couldn't be evaluated.
+pub fn decode<F>(node: &SyntaxNode, mut interpolate: F) -> Option<String>
+where
+    F: FnMut(&SyntaxNode) -> Option<String>,
+{
+    let mut raw = String::new();
+    let mut values = Vec::new();
+
+    for part in node.children_with_tokens() {
+        match part {
+            NodeOrToken::Token(token) if token.kind() == SyntaxKind::TOKEN_STRING_CONTENT => {
+                raw.push_str(token.text());
+            }
+            NodeOrToken::Node(interpolation)
+                if interpolation.kind() == SyntaxKind::NODE_INTERPOL =>
+            {
+                values.push(interpolate(&interpolation.children().next()?)?);
+                raw.push(INTERPOLATION);
+            }
+            _ => {}
+        }
+    }
+
+    // Indentation is stripped before escapes are decoded, so escaped whitespace is kept
+    let decoded = if is_indented(node) {
+        unescape_indented(&strip_indentation(&raw))
+    } else {
+        unescape(&raw)
+    };
+
+    let mut values = values.into_iter();
+    Some(
+        decoded
+            .split(INTERPOLATION)
+            .enumerate()
+            .fold(String::new(), |mut value, (i, literal)| {
+                if i > 0 {
+                    value.push_str(&values.next().unwrap_or_default());
+                }
+                value.push_str(literal);
+                value
+            }),
+    )
+}
+
+/// Checks whether a string literal is an indented string (`''...''`).
+pub fn is_indented(node: &SyntaxNode) -> bool {
+    node.children_with_tokens()
+        .find(|part| part.kind() == SyntaxKind::TOKEN_STRING_START)
+        .and_then(|part| part.into_token())
+        .is_some_and(|token| token.text() == "''")
+}
+
+/// Strips the indentation common to all lines of an indented string.
+///
+/// # Arguments
+/// - `raw`: The literal text of the string, with interpolations marked.
+///
+/// # Returns
+/// The text without the common indentation, without a leading whitespace-only line and
+/// without the spaces before the closing delimiter.
+fn strip_indentation(raw: &str) -> String {
+    let mut lines: Vec<&str> = raw.split('\n').collect();
+    if lines.len() > 1 && lines[0].chars().all(|c| c == ' ') {
+        lines.remove(0);
+    }
+
+    // Only spaces count as indentation, and lines containing only spaces are ignored
+    let indentation = lines
+        .iter()
+        .filter(|line| line.chars().any(|c| c != ' '))
+        .map(|line| line.len() - line.trim_start_matches(' ').len())
+        .min()
+        .unwrap_or(0);
+
+    let last = lines.len() - 1;
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == last && line.chars().all(|c| c == ' ') {
+                ""
+            } else {
+                line.get(indentation..)
+                    .unwrap_or_else(|| line.trim_start_matches(' '))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Decodes the escape sequences of a double-quoted string (`\n`, `\"`, `\${`, ...).
+fn unescape(raw: &str) -> String {
+    let mut value = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(escaped) => value.push(unescape_char(escaped)),
+            None => value.push('\\'),
+        }
+    }
+
+    value
+}
+
+/// Decodes the escape sequences of an indented string (`'''`, `''$`, `''\n`, ...).
+fn unescape_indented(raw: &str) -> String {
+    let mut value = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\'' || chars.peek() != Some(&'\'') {
+            value.push(c);
+            continue;
+        }
+        chars.next();
+        match chars.next() {
+            Some('\'') => value.push_str("''"),
+            Some('$') => value.push('$'),
+            Some('\\') => {
+                if let Some(escaped) = chars.next() {
+                    value.push(unescape_char(escaped));
+                }
+            }
+            Some(other) => {
+                value.push_str("''");
+                value.push(other);
+            }
+            None => value.push_str("''"),
+        }
+    }
+
+    value
+}
+
+/// Decodes the character following a backslash.
+fn unescape_char(escaped: char) -> char {
+    match escaped {
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        other => other,
+    }
+}
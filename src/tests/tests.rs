@@ -1,8 +1,10 @@
 use super::*;
+use crate::files::FileFilter;
 use crate::generate::generate_markdown;
+use crate::generate::markdown::{Grouping, MarkdownOptions, MarkdownStyle};
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tempfile::TempDir;
 
 /// Creates a test file with the specified filename and content in the given directory.
@@ -31,14 +33,18 @@ fn test_basic_option_parsing() -> Result<(), Box<dyn std::error::Error + Send +
 "#;
     create_test_file(temp_dir.path(), "flake.nix", content)?;
 
-    let options = collect_options(temp_dir.path(), &[], &HashMap::new(), false, false)?;
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
 
     assert_eq!(options.len(), 1);
     assert_eq!(options[0].name, "options.test.simple.enable");
     assert_eq!(options[0].nix_type.to_string(), "boolean");
     assert_eq!(
         options[0].description,
-        Some("Simple test option".to_string())
+        Some("Whether to enable Simple test option.".to_string())
     );
     assert_eq!(options[0].default_value, Some("false".to_string()));
 
@@ -67,7 +73,11 @@ fn test_complex_option_parsing() -> Result<(), Box<dyn std::error::Error + Send
 "#;
     create_test_file(temp_dir.path(), "test.nix", content)?;
 
-    let options = collect_options(temp_dir.path(), &[], &HashMap::new(), false, false)?;
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
 
     assert_eq!(options.len(), 2);
 
@@ -105,6 +115,13 @@ fn test_markdown_generation() -> Result<(), Box<dyn std::error::Error + Send + S
             example: None,
             file_path: "test.nix".to_string(),
             line_number: 1,
+            column_number: 1,
+            end_line_number: 1,
+            end_column_number: 1,
+            source_url: None,
+            module: None,
+            condition: None,
+            file_doc: None,
         },
         OptionDoc {
             name: "options.test.opt2".to_string(),
@@ -114,11 +131,22 @@ fn test_markdown_generation() -> Result<(), Box<dyn std::error::Error + Send + S
             example: None,
             file_path: "test.nix".to_string(),
             line_number: 2,
+            column_number: 1,
+            end_line_number: 2,
+            end_column_number: 1,
+            source_url: None,
+            module: None,
+            condition: None,
+            file_doc: None,
         },
     ];
 
     // Generate markdown
-    let markdown = generate_markdown(&options)?;
+    let markdown = generate_markdown(
+        &options,
+        "NixOS Module Options",
+        &MarkdownOptions::default(),
+    )?;
 
     // Validate markdown content
     assert!(markdown.contains("# NixOS Module Options"));
@@ -136,7 +164,11 @@ fn test_markdown_generation() -> Result<(), Box<dyn std::error::Error + Send + S
     // Test sorted output
     let mut sorted_options = options.clone();
     sorted_options.sort_by(|a, b| a.name.cmp(&b.name));
-    let markdown_sorted = generate_markdown(&sorted_options)?;
+    let markdown_sorted = generate_markdown(
+        &sorted_options,
+        "NixOS Module Options",
+        &MarkdownOptions::default(),
+    )?;
     let opt1_pos = markdown_sorted.find("options.test.opt1").unwrap();
     let opt2_pos = markdown_sorted.find("options.test.opt2").unwrap();
     assert!(opt1_pos < opt2_pos);
@@ -144,6 +176,48 @@ fn test_markdown_generation() -> Result<(), Box<dyn std::error::Error + Send + S
     Ok(())
 }
 
+/// Tests that CSV output always ends with the Module and Condition columns.
+#[test]
+fn test_csv_generation() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut options = vec![OptionDoc {
+        name: "options.test.opt1".to_string(),
+        description: Some("Test option 1".to_string()),
+        nix_type: "boolean".to_string(),
+        default_value: Some("false".to_string()),
+        example: None,
+        file_path: "test.nix".to_string(),
+        line_number: 1,
+        column_number: 1,
+        end_line_number: 1,
+        end_column_number: 1,
+        source_url: None,
+        module: None,
+        condition: None,
+        file_doc: None,
+    }];
+
+    let csv = generate::generate_csv(&options)?;
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("Option,Type,Default,Example,Description,FilePath,LineNumber,Module,Condition")
+    );
+    assert_eq!(
+        lines.next(),
+        Some("options.test.opt1,boolean,false,-,Test option 1,test.nix,1,,")
+    );
+
+    options[0].module = Some("nixosModules.default".to_string());
+    options[0].condition = Some("cfg.enable".to_string());
+    let csv = generate::generate_csv(&options)?;
+    assert_eq!(
+        csv.lines().nth(1),
+        Some("options.test.opt1,boolean,false,-,Test option 1,test.nix,1,nixosModules.default,cfg.enable")
+    );
+
+    Ok(())
+}
+
 /// Tests that hidden files are correctly excluded from processing.
 #[test]
 fn test_hidden_files_exclusion() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -157,7 +231,11 @@ fn test_hidden_files_exclusion() -> Result<(), Box<dyn std::error::Error + Send
 "#;
     create_test_file(temp_dir.path(), ".hidden.nix", content)?;
 
-    let options = collect_options(temp_dir.path(), &[], &HashMap::new(), false, false)?;
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
 
     assert_eq!(options.len(), 0);
 
@@ -196,7 +274,11 @@ fn test_multiline_description_parsing() -> Result<(), Box<dyn std::error::Error
 "#;
     create_test_file(temp_dir.path(), "flake.nix", content)?;
 
-    let options = collect_options(temp_dir.path(), &[], &HashMap::new(), false, false)?;
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
 
     assert_eq!(options.len(), 2);
 
@@ -270,7 +352,11 @@ fn test_duplicate_prevention() -> Result<(), Box<dyn std::error::Error + Send +
 "#;
     create_test_file(temp_dir.path(), "test.nix", content)?;
 
-    let options = collect_options(temp_dir.path(), &[], &HashMap::new(), false, false)?;
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
 
     let enable_options: Vec<_> = options
         .iter()
@@ -320,24 +406,29 @@ fn test_exclude_dir() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     )?;
 
     // Test without exclusion
-    let all_options = collect_options(temp_dir.path(), &[], &HashMap::new(), false, false)?;
+    let all_options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
 
     assert!(!all_options.is_empty()); // At least the main option
     assert!(all_options.iter().any(|o| o.name == "options.main.enable"));
 
     // Test with exclusion
-    let exclude_dirs = vec![temp_dir
-        .path()
-        .join("excluded")
-        .to_string_lossy()
-        .to_string()];
+    let files = FileFilter {
+        exclude_dirs: vec![temp_dir
+            .path()
+            .join("excluded")
+            .to_string_lossy()
+            .to_string()],
+        ..Default::default()
+    };
 
     let filtered_options = collect_options(
         temp_dir.path(),
-        &exclude_dirs,
-        &HashMap::new(),
-        false,
-        false,
+        None,
+        &CollectOptions::new(&files, &HashMap::new()),
     )?;
 
     assert!(filtered_options
@@ -350,122 +441,2372 @@ fn test_exclude_dir() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     Ok(())
 }
 
-/// Tests variable replacement functionality in option names and descriptions.
+/// Tests that processing can be restricted to a subdirectory or a single file
+/// while keeping file paths relative to the repository root.
 #[test]
-fn test_variable_replacements() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+fn test_subdir() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let temp_dir = TempDir::new()?;
 
-    // Create a file with variable interpolation
-    let content = r#"
+    let nixos_content = r#"
 {
-  options.${namespace}.hardware.bluetooth = {
-    enable = lib.mkEnableOption "Enable ${namespace} bluetooth";
+  options.nixos = {
+    enable = lib.mkEnableOption "NixOS option";
   };
-  
-  options.${system}.networking = {
-    enable = lib.mkEnableOption "Enable networking for ${system}";
+}
+"#;
+
+    let other_content = r#"
+{
+  options.other = {
+    enable = lib.mkEnableOption "Other option";
   };
 }
 "#;
-    create_test_file(temp_dir.path(), "config.nix", content)?;
 
-    // Set up replacements
-    let mut replacements = HashMap::new();
-    replacements.insert("namespace".to_string(), "snowflake".to_string());
-    replacements.insert("system".to_string(), "x86_64-linux".to_string());
+    fs::create_dir_all(temp_dir.path().join("modules/nixos"))?;
+    create_test_file(
+        temp_dir.path().join("modules/nixos").as_path(),
+        "default.nix",
+        nixos_content,
+    )?;
+    create_test_file(temp_dir.path(), "other.nix", other_content)?;
 
-    let options = collect_options(temp_dir.path(), &[], &replacements, false, false)?;
+    let options = collect_options(
+        temp_dir.path(),
+        Some(Path::new("modules/nixos")),
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
 
-    // Check if options contain the replaced values
-    let bluetooth_options: Vec<_> = options
-        .iter()
-        .filter(|o| o.name.contains("bluetooth"))
-        .collect();
+    assert_eq!(options.len(), 1);
+    assert_eq!(options[0].name, "options.nixos.enable");
+    assert_eq!(
+        Path::new(&options[0].file_path),
+        Path::new("modules/nixos/default.nix")
+    );
 
-    let networking_options: Vec<_> = options
-        .iter()
-        .filter(|o| o.name.contains("networking"))
-        .collect();
+    // A single file can be selected as well
+    let options = collect_options(
+        temp_dir.path(),
+        Some(Path::new("other.nix")),
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
 
-    if !bluetooth_options.is_empty() {
-        let bluetooth_opt = &bluetooth_options[0];
-        assert!(bluetooth_opt.name.contains("snowflake"));
-        assert!(!bluetooth_opt.name.contains("${namespace}"));
+    assert_eq!(options.len(), 1);
+    assert_eq!(options[0].file_path, "other.nix");
 
-        // Check if description also had replacements
-        if let Some(desc) = &bluetooth_opt.description {
-            assert!(desc.contains("snowflake"));
-            assert!(!desc.contains("${namespace}"));
-        }
-    }
+    // Subdirectories outside the repository are rejected
+    let result = collect_options(
+        temp_dir.path(),
+        Some(Path::new("../outside")),
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    );
+    assert!(result.is_err());
 
-    if !networking_options.is_empty() {
-        let networking_opt = &networking_options[0];
-        assert!(networking_opt.name.contains("x86_64-linux"));
-        assert!(!networking_opt.name.contains("${system}"));
+    Ok(())
+}
 
-        // Check if description also had replacements
-        if let Some(desc) = &networking_opt.description {
-            assert!(desc.contains("x86_64-linux"));
-            assert!(!desc.contains("${system}"));
-        }
-    }
+/// Tests splitting the `//<subdir>` suffix from repository URLs.
+#[test]
+fn test_split_subdir() {
+    assert_eq!(
+        utils::split_subdir("https://host/repo.git//modules/nixos"),
+        ("https://host/repo.git", Some("modules/nixos"))
+    );
+    assert_eq!(
+        utils::split_subdir("git@github.com:user/repo.git//modules/"),
+        ("git@github.com:user/repo.git", Some("modules"))
+    );
+    assert_eq!(
+        utils::split_subdir("https://host/repo.git"),
+        ("https://host/repo.git", None)
+    );
+    assert_eq!(
+        utils::split_subdir("file:///srv/repo.git//default.nix"),
+        ("file:///srv/repo.git", Some("default.nix"))
+    );
+}
 
-    Ok(())
+/// Commits a single `modules/test.nix` file with the given content on top of `HEAD`.
+///
+/// # Arguments
+/// - `repo`: The repository to commit to.
+/// - `content`: The content of the committed file.
+/// - `parents`: The parent commits of the new commit.
+///
+/// # Returns
+/// A Result containing the id of the new commit or a git error.
+fn commit_test_file(
+    repo: &gix::Repository,
+    content: &str,
+    parents: Vec<gix::ObjectId>,
+) -> Result<gix::ObjectId, Box<dyn std::error::Error + Send + Sync>> {
+    let signature = gix::actor::SignatureRef {
+        name: "Test".into(),
+        email: "test@example.com".into(),
+        time: gix::date::Time::now_utc(),
+    };
+
+    let blob = repo.write_blob(content)?.detach();
+    let modules = repo.write_object(gix::objs::Tree {
+        entries: vec![gix::objs::tree::Entry {
+            mode: gix::objs::tree::EntryKind::Blob.into(),
+            filename: "test.nix".into(),
+            oid: blob,
+        }],
+    })?;
+    let root = repo.write_object(gix::objs::Tree {
+        entries: vec![gix::objs::tree::Entry {
+            mode: gix::objs::tree::EntryKind::Tree.into(),
+            filename: "modules".into(),
+            oid: modules.detach(),
+        }],
+    })?;
+
+    Ok(repo
+        .commit_as(
+            signature,
+            signature,
+            "HEAD",
+            "commit",
+            root.detach(),
+            parents,
+        )?
+        .detach())
 }
 
-/// Tests error handling for invalid paths and malformed files.
+/// Tests reading options from a local git repository at a given revision
+/// without checking it out.
 #[test]
-fn test_error_handling() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+fn test_collect_options_at_rev() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let temp_dir = TempDir::new()?;
+    let mut repo = gix::init(temp_dir.path())?;
+
+    // Reflog updates require a committer identity
+    let mut config = repo.config_snapshot_mut();
+    config.set_value(&gix::config::tree::Committer::NAME, "Test")?;
+    config.set_value(&gix::config::tree::Committer::EMAIL, "test@example.com")?;
+    config.commit()?;
+
+    let v1 = commit_test_file(
+        &repo,
+        r#"{ options.test.old = lib.mkEnableOption "Old option"; }"#,
+        vec![],
+    )?;
+    repo.tag_reference(
+        "v1.2",
+        v1,
+        gix::refs::transaction::PreviousValue::MustNotExist,
+    )?;
+    commit_test_file(
+        &repo,
+        r#"{ options.test.new = lib.mkEnableOption "New option"; }"#,
+        vec![v1],
+    )?;
 
-    // Test non-existent path
-    let non_existent = temp_dir.path().join("non-existent");
-    let result = collect_options(&non_existent, &[], &HashMap::new(), false, false);
-    assert!(result.is_err(), "Non-existent paths should return an error");
+    let options = collect_options_at_rev(
+        temp_dir.path(),
+        "v1.2",
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    assert_eq!(options.len(), 1);
+    assert_eq!(options[0].name, "options.test.old");
+    assert_eq!(options[0].file_path, "modules/test.nix");
 
-    // Create a file with invalid Nix syntax
-    let invalid_content = r#"
-{
-  options.test = {
-    # Missing closing brace
-    invalid = lib.mkEnableOption "Invalid option"
-  ;
+    let options = collect_options_at_rev(
+        temp_dir.path(),
+        "HEAD",
+        Some(Path::new("modules")),
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    assert_eq!(options.len(), 1);
+    assert_eq!(options[0].name, "options.test.new");
+
+    let options = collect_options_at_rev(
+        temp_dir.path(),
+        &v1.to_string(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    assert_eq!(options[0].name, "options.test.old");
+
+    // The working tree is left untouched
+    assert!(!temp_dir.path().join("modules").exists());
+
+    // Unknown revisions and subdirectories are reported as errors
+    assert!(collect_options_at_rev(
+        temp_dir.path(),
+        "v9",
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new())
+    )
+    .is_err());
+    assert!(collect_options_at_rev(
+        temp_dir.path(),
+        "HEAD",
+        Some(Path::new("missing")),
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new())
+    )
+    .is_err());
+
+    Ok(())
 }
-"#;
-    create_test_file(temp_dir.path(), "invalid.nix", invalid_content)?;
 
-    // File processing should continue even with parse errors
-    let result = collect_options(temp_dir.path(), &[], &HashMap::new(), false, false);
+/// Tests fetching a repository into the persistent cache, updating it
+/// incrementally and reusing it offline.
+#[test]
+fn test_repository_cache() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let remote_dir = TempDir::new()?;
+    let cache_dir = TempDir::new()?;
+    let mut repo = gix::init(remote_dir.path())?;
+
+    let mut config = repo.config_snapshot_mut();
+    config.set_value(&gix::config::tree::Committer::NAME, "Test")?;
+    config.set_value(&gix::config::tree::Committer::EMAIL, "test@example.com")?;
+    config.commit()?;
+
+    let v1 = commit_test_file(
+        &repo,
+        r#"{ options.test.old = lib.mkEnableOption "Old option"; }"#,
+        vec![],
+    )?;
+
+    let url = remote_dir.path().to_string_lossy().to_string();
+    let (cached_path, rev) = cache::fetch_repo(cache_dir.path(), &url, None, 1, false)?;
+    assert_eq!(rev, v1.to_string());
+    assert_eq!(cached_path, cache::repo_cache_path(cache_dir.path(), &url));
+
+    let options = collect_options_at_rev(
+        &cached_path,
+        &rev,
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    assert_eq!(options[0].name, "options.test.old");
+
+    // New commits are fetched into the existing cache
+    let v2 = commit_test_file(
+        &repo,
+        r#"{ options.test.new = lib.mkEnableOption "New option"; }"#,
+        vec![v1],
+    )?;
+    let (_, rev) = cache::fetch_repo(cache_dir.path(), &url, None, 1, false)?;
+    assert_eq!(rev, v2.to_string());
+
+    // Offline mode uses the cached revision even if the remote is gone
+    drop(remote_dir);
+    let (_, rev) = cache::fetch_repo(cache_dir.path(), &url, None, 1, true)?;
+    assert_eq!(rev, v2.to_string());
+
+    // Uncached repositories can't be used offline
     assert!(
-        result.is_ok(),
-        "Processing should continue even with parse errors"
+        cache::fetch_repo(cache_dir.path(), "https://example.com/x.git", None, 1, true).is_err()
     );
 
-    // Create a file with valid Nix syntax alongside the invalid one
-    let valid_content = r#"
+    assert_eq!(cache::clean(cache_dir.path())?, 1);
+    assert!(cache::fetch_repo(cache_dir.path(), &url, None, 1, true).is_err());
+
+    Ok(())
+}
+
+/// Tests that the files of submodules are included with paths relative to the
+/// superproject when recursing into submodules.
+#[test]
+fn test_recurse_submodules() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let sub_dir = TempDir::new()?;
+    let super_dir = TempDir::new()?;
+    let cache_dir = TempDir::new()?;
+
+    let mut sub_repo = gix::init(sub_dir.path())?;
+    let mut config = sub_repo.config_snapshot_mut();
+    config.set_value(&gix::config::tree::Committer::NAME, "Test")?;
+    config.set_value(&gix::config::tree::Committer::EMAIL, "test@example.com")?;
+    config.commit()?;
+
+    let sub_commit = commit_test_file(
+        &sub_repo,
+        r#"{ options.vendored.enable = lib.mkEnableOption "Vendored option"; }"#,
+        vec![],
+    )?;
+
+    // Superproject with `vendor/sub` pointing at the submodule commit
+    let repo = gix::init(super_dir.path())?;
+    let gitmodules = format!(
+        "[submodule \"sub\"]\n\tpath = vendor/sub\n\turl = {}\n",
+        sub_dir.path().display()
+    );
+    let vendor = repo.write_object(gix::objs::Tree {
+        entries: vec![gix::objs::tree::Entry {
+            mode: gix::objs::tree::EntryKind::Commit.into(),
+            filename: "sub".into(),
+            oid: sub_commit,
+        }],
+    })?;
+    let root = repo.write_object(gix::objs::Tree {
+        entries: vec![
+            gix::objs::tree::Entry {
+                mode: gix::objs::tree::EntryKind::Blob.into(),
+                filename: ".gitmodules".into(),
+                oid: repo.write_blob(gitmodules)?.detach(),
+            },
+            gix::objs::tree::Entry {
+                mode: gix::objs::tree::EntryKind::Tree.into(),
+                filename: "vendor".into(),
+                oid: vendor.detach(),
+            },
+        ],
+    })?;
+    let commit = repo.write_object(gix::objs::Commit {
+        tree: root.detach(),
+        parents: Default::default(),
+        author: gix::actor::Signature {
+            name: "Test".into(),
+            email: "test@example.com".into(),
+            time: gix::date::Time::now_utc(),
+        },
+        committer: gix::actor::Signature {
+            name: "Test".into(),
+            email: "test@example.com".into(),
+            time: gix::date::Time::now_utc(),
+        },
+        encoding: None,
+        message: "commit".into(),
+        extra_headers: vec![],
+    })?;
+    let rev = commit.to_string();
+
+    // Submodules are skipped unless enabled
+    let options = collect_options_at_rev(
+        super_dir.path(),
+        &rev,
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    assert!(options.is_empty());
+
+    let submodules = git::SubmoduleOptions {
+        cache_dir: cache_dir.path().to_path_buf(),
+        depth: 1,
+        offline: false,
+    };
+    let options = collect_options_at_rev(
+        super_dir.path(),
+        &rev,
+        None,
+        &CollectOptions {
+            submodules: Some(&submodules),
+            ..CollectOptions::new(&FileFilter::default(), &HashMap::new())
+        },
+    )?;
+    assert_eq!(options.len(), 1);
+    assert_eq!(options[0].name, "options.vendored.enable");
+    assert_eq!(options[0].file_path, "vendor/sub/modules/test.nix");
+
+    // The submodule commit is cached and can be used offline
+    drop(sub_repo);
+    drop(sub_dir);
+    let offline = git::SubmoduleOptions {
+        offline: true,
+        ..submodules
+    };
+    let options = collect_options_at_rev(
+        super_dir.path(),
+        &rev,
+        Some(Path::new("vendor")),
+        &CollectOptions {
+            submodules: Some(&offline),
+            ..CollectOptions::new(&FileFilter::default(), &HashMap::new())
+        },
+    )?;
+    assert_eq!(options.len(), 1);
+
+    Ok(())
+}
+
+/// Tests resolving submodule URLs and building links to their files.
+#[test]
+fn test_submodule_urls() {
+    assert_eq!(
+        git::resolve_url("../lib.git", Some("https://host/owner/super.git")),
+        Some("https://host/owner/lib.git".to_string())
+    );
+    assert_eq!(
+        git::resolve_url("./lib", Some("git@host:owner/super")),
+        Some("git@host:owner/super/lib".to_string())
+    );
+    assert_eq!(git::resolve_url("../lib.git", None), None);
+    assert_eq!(
+        git::resolve_url("https://host/lib.git", None),
+        Some("https://host/lib.git".to_string())
+    );
+
+    assert_eq!(
+        git::browse_url("https://github.com/owner/lib.git", "abc", "mod.nix"),
+        Some("https://github.com/owner/lib/blob/abc/mod.nix".to_string())
+    );
+    assert_eq!(
+        git::browse_url("git@github.com:owner/lib.git", "abc", "a/mod.nix"),
+        Some("https://github.com/owner/lib/blob/abc/a/mod.nix".to_string())
+    );
+    assert_eq!(
+        git::browse_url("ssh://git@host:2222/owner/lib", "abc", "mod.nix"),
+        Some("https://host/owner/lib/blob/abc/mod.nix".to_string())
+    );
+    assert_eq!(git::browse_url("/srv/git/lib.git", "abc", "mod.nix"), None);
+    assert_eq!(
+        git::browse_url("file:///srv/lib.git", "abc", "mod.nix"),
+        None
+    );
+}
+
+/// Tests that only the modules reachable through `imports` from the entry points,
+/// including flake outputs, are documented.
+#[test]
+fn test_entry_imports() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    fs::create_dir_all(dir.join("modules/b"))?;
+
+    create_test_file(
+        dir,
+        "flake.nix",
+        r#"
 {
-  options.test.valid = {
-    enable = lib.mkEnableOption "Valid option";
+  outputs = { self, ... }: {
+    nixosModules = {
+      default = import ./modules;
+      inline = { lib, ... }: {
+        imports = [ ./modules/b ];
+        options.inline.enable = lib.mkEnableOption "Inline module";
+      };
+    };
   };
 }
-"#;
-    create_test_file(temp_dir.path(), "valid.nix", valid_content)?;
+"#,
+    )?;
+    create_test_file(
+        dir,
+        "modules/default.nix",
+        r#"
+{ lib, ... }:
+{
+  imports = [ ./a.nix ./b <nixpkgs/nixos/modules/foo.nix> ];
+  options.root.enable = lib.mkEnableOption "Root module";
+}
+"#,
+    )?;
+    create_test_file(
+        dir,
+        "modules/a.nix",
+        r#"
+{ lib, ... }:
+{
+  imports = [ ./b/default.nix ];
+  options.a.enable = lib.mkEnableOption "Module A";
+}
+"#,
+    )?;
+    create_test_file(
+        dir,
+        "modules/b/default.nix",
+        r#"{ lib, ... }: { options.b.enable = lib.mkEnableOption "Module B"; }"#,
+    )?;
+    create_test_file(
+        dir,
+        "unused.nix",
+        r#"{ lib, ... }: { options.unused.enable = lib.mkEnableOption "Unused"; }"#,
+    )?;
 
-    // We should still find the valid option
-    // even when there's an invalid file in the same directory
-    let options_with_valid = collect_options(temp_dir.path(), &[], &HashMap::new(), false, false)?;
-    assert!(
-        !options_with_valid.is_empty(),
-        "Valid options should be found even when some files have errors"
+    let entries = [imports::parse_entry("modules/default.nix", "")];
+    let (options, graph) = collect_options_from_entries(
+        dir,
+        &entries,
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+
+    let names: Vec<_> = options.iter().map(|o| o.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "options.root.enable",
+            "options.a.enable",
+            "options.b.enable"
+        ]
     );
+    assert_eq!(graph.modules.len(), 3);
+    assert_eq!(graph.edges.len(), 3);
+    assert!(graph
+        .to_dot()
+        .contains("\"modules/a.nix\" -> \"modules/b/default.nix\";"));
+
+    // Flake outputs resolve to the imported file or to the inline module
+    let entries = [
+        imports::parse_entry(".#nixosModules.default", ""),
+        imports::parse_entry(".#nixosModules.inline", ""),
+    ];
+    let (options, graph) = collect_options_from_entries(
+        dir,
+        &entries,
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
 
-    // Test a directory with .nix extension
-    let dir_with_nix_ext = temp_dir.path().join("not-readable.nix");
-    std::fs::create_dir(&dir_with_nix_ext)?;
+    assert_eq!(graph.modules[0].file, "modules/default.nix");
+    assert_eq!(
+        graph.modules[1].to_string(),
+        "flake.nix#nixosModules.inline"
+    );
+    let inline = options
+        .iter()
+        .find(|o| o.name == "options.inline.enable")
+        .expect("inline module option");
+    assert_eq!(inline.file_path, "flake.nix");
+    assert_eq!(options.len(), 4);
+
+    // Excluded directories are not followed
+    let entries = [imports::parse_entry("default.nix", "modules")];
+    let (options, _) = collect_options_from_entries(
+        dir,
+        &entries,
+        None,
+        &CollectOptions::new(
+            &FileFilter {
+                exclude_dirs: vec!["modules/b".to_string()],
+                ..Default::default()
+            },
+            &HashMap::new(),
+        ),
+    )?;
+    assert_eq!(options.len(), 2);
 
-    // Should not error out even with the unreadable "file"
-    let result = collect_options(temp_dir.path(), &[], &HashMap::new(), false, false);
+    // Missing entry points are reported
+    let entries = [imports::parse_entry("missing.nix", "")];
+    assert!(collect_options_from_entries(
+        dir,
+        &entries,
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new())
+    )
+    .is_err());
+
+    Ok(())
+}
+
+/// Tests that the modules exported by a flake are discovered and documented separately.
+#[test]
+fn test_flake_outputs() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    fs::create_dir_all(dir.join("modules"))?;
+
+    create_test_file(
+        dir,
+        "flake.nix",
+        r#"
+{
+  outputs = inputs: flake-parts.lib.mkFlake { inherit inputs; } {
+    flake = {
+      nixosModules = {
+        default = ./modules/nixos.nix;
+        "with-home" = { imports = [ ./modules/home.nix ]; };
+      };
+      homeManagerModules.default = import ./modules/home.nix;
+      packages = { };
+    };
+  };
+}
+"#,
+    )?;
+    create_test_file(
+        dir,
+        "modules/nixos.nix",
+        r#"{ lib, ... }: { options.nixos.enable = lib.mkEnableOption "NixOS"; }"#,
+    )?;
+    create_test_file(
+        dir,
+        "modules/home.nix",
+        r#"{ lib, ... }: { options.home.enable = lib.mkEnableOption "Home"; }"#,
+    )?;
+    create_test_file(
+        dir,
+        "unrelated.nix",
+        r#"{ lib, ... }: { options.unrelated.enable = lib.mkEnableOption "Unrelated"; }"#,
+    )?;
+
+    let root = rnix::Root::parse(&fs::read_to_string(dir.join("flake.nix"))?).syntax();
+    assert_eq!(
+        imports::flake_module_outputs(&root),
+        vec![
+            "nixosModules.default",
+            "nixosModules.with-home",
+            "homeManagerModules.default"
+        ]
+    );
+
+    let options = collect_flake_options(
+        dir,
+        "flake.nix",
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?
+    .expect("flake options");
+    let documented: Vec<_> = options
+        .iter()
+        .map(|o| (o.module.as_deref().unwrap_or_default(), o.name.as_str()))
+        .collect();
+    assert_eq!(
+        documented,
+        vec![
+            ("nixosModules.default", "options.nixos.enable"),
+            ("nixosModules.with-home", "options.home.enable"),
+            ("homeManagerModules.default", "options.home.enable"),
+        ]
+    );
+
+    // Each output becomes a JSON key
+    let json: serde_json::Value = serde_json::from_str(&generate::generate_json(&options)?)?;
+    assert_eq!(
+        json["homeManagerModules.default"][0]["name"],
+        "options.home.enable"
+    );
+    // Local files have no submodule URL, which is left out
+    assert!(json["homeManagerModules.default"][0]
+        .get("source_url")
+        .is_none());
+
+    // Directories without a flake are processed as usual
+    assert!(collect_flake_options(
+        dir,
+        "modules/flake.nix",
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new())
+    )?
+    .is_none());
+
+    Ok(())
+}
+
+/// Tests that flake-parts `perSystem` options are namespaced and that options outside
+/// of the option roots of the module system are skipped.
+#[test]
+fn test_module_system() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use clap::Parser;
+
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "flake-module.nix",
+        r#"
+{ lib, flake-parts-lib, ... }:
+{
+  helpers.unused = lib.mkEnableOption "Not an option";
+  options.flake.enable = lib.mkEnableOption "Top-level option";
+  options.perSystem = flake-parts-lib.mkPerSystemOption ({ ... }: {
+    options.declared.enable = lib.mkEnableOption "Declared per-system option";
+  });
+  perSystem = { config, pkgs, ... }: {
+    options.inline.enable = lib.mkEnableOption "Inline per-system option";
+  };
+}
+"#,
+    )?;
+
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    assert_eq!(options.len(), 4);
+
+    let cli = Cli::parse_from([
+        "program",
+        "--module-system",
+        "flake-parts",
+        "--strip-prefix",
+    ]);
+    let mut names: Vec<_> = filter_options(&options, &cli)
+        .into_iter()
+        .map(|o| o.name)
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            "flake.enable",
+            "perSystem.declared.enable",
+            "perSystem.inline.enable"
+        ]
+    );
+    assert_eq!(document_title(&options, &cli), "flake-parts Module Options");
+
+    // NixOS modules have no `perSystem` options
+    let cli = Cli::parse_from(["program", "--module-system", "nixos"]);
+    let filtered = filter_options(&options, &cli);
+    assert_eq!(filtered.len(), 2);
+    assert_eq!(document_title(&filtered, &cli), "NixOS Module Options");
+
+    // The module system is inferred from flake outputs
+    let cli = Cli::parse_from(["program"]);
+    let mut home_options = filtered.clone();
+    for option in &mut home_options {
+        option.module = Some("homeManagerModules.default".to_string());
+    }
+    assert_eq!(
+        document_title(&home_options, &cli),
+        "Home Manager Module Options"
+    );
+
+    Ok(())
+}
+
+/// Tests that options wrapped in parentheses, `mkMerge`, `mkIf`, `optionalAttrs` and `//`
+/// updates are found, and that conditionally declared options record their condition.
+#[test]
+fn test_wrapped_options() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "wrapped.nix",
+        r#"
+{ lib, pkgs, ... }:
+{
+  options.wrapped = lib.mkMerge [
+    {
+      paren = (lib.mkOption { type = lib.types.str; });
+    }
+    (lib.mkIf pkgs.stdenv.isLinux {
+      linux = lib.mkEnableOption "Linux only";
+      nested = lib.optionalAttrs (pkgs.system == "x86_64-linux") {
+        x86 = lib.mkEnableOption "x86_64 only";
+      };
+    })
+  ];
+
+  options.updated = {
+    base = lib.mkEnableOption "Base option";
+  } // lib.optionalAttrs false {
+    extra = lib.mkEnableOption "Extra option";
+  };
+}
+"#,
+    )?;
+
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    let conditions: HashMap<_, _> = options
+        .iter()
+        .map(|o| (o.name.as_str(), o.condition.as_deref()))
+        .collect();
+
+    assert_eq!(conditions.len(), 5);
+    assert_eq!(conditions["options.wrapped.paren"], None);
+    assert_eq!(
+        conditions["options.wrapped.linux"],
+        Some("pkgs.stdenv.isLinux")
+    );
+    assert_eq!(
+        conditions["options.wrapped.nested.x86"],
+        Some("pkgs.stdenv.isLinux && (pkgs.system == \"x86_64-linux\")")
+    );
+    assert_eq!(conditions["options.updated.base"], None);
+    assert_eq!(conditions["options.updated.extra"], Some("false"));
+
+    let markdown = generate_markdown(
+        &options,
+        "NixOS Module Options",
+        &MarkdownOptions::default(),
+    )?;
+    assert!(markdown.contains("**Declared if:** `pkgs.stdenv.isLinux`"));
+
+    Ok(())
+}
+
+/// Tests that attribute paths are evaluated from string segments, let bindings, function
+/// arguments and rec attribute sets, and that inherited option definitions are expanded.
+#[test]
+fn test_attrpath_evaluation() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "module.nix",
+        r#"
+{ lib, namespace ? "myns", ... }:
+let
+  service = "web-${namespace}";
+  commonOpts = {
+    enable = lib.mkEnableOption "the ${service} service";
+  };
+  port = lib.mkOption { type = lib.types.port; };
+in
+{
+  options.${namespace}.${service} = {
+    "foo-bar" = lib.mkEnableOption "Quoted name";
+    inherit (commonOpts) enable;
+    inherit port;
+  };
+  options.apps = rec {
+    name = "app";
+    ${name} = lib.mkEnableOption "Named by a rec binding";
+  };
+}
+"#,
+    )?;
+
+    let mut names: Vec<_> = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?
+    .into_iter()
+    .map(|o| o.name)
+    .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            "options.apps.app",
+            "options.myns.web-myns.enable",
+            "options.myns.web-myns.foo-bar",
+            "options.myns.web-myns.port",
+        ]
+    );
+
+    // Values given with --replace take precedence over argument defaults
+    let replacements = HashMap::from([("namespace".to_string(), "custom".to_string())]);
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &replacements),
+    )?;
+    let enable = options
+        .iter()
+        .find(|o| o.name == "options.custom.web-custom.enable")
+        .expect("inherited option");
+    assert_eq!(
+        enable.description.as_deref(),
+        Some("Whether to enable the web-custom service.")
+    );
+
+    Ok(())
+}
+
+/// Tests that `mkEnableOption` descriptions are evaluated from interpolated and concatenated
+/// strings and worded like nixpkgs' enable options.
+#[test]
+fn test_enable_option_description() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "enable.nix",
+        r#"
+{ lib, config, ... }:
+let
+  name = "nginx";
+in
+{
+  options.interpolated = lib.mkEnableOption "the ${name} service";
+  options.concatenated = lib.mkEnableOption ("the " + name + " proxy");
+  options.unknown = lib.mkEnableOption "the ${config.service.name} service";
+}
+"#,
+    )?;
+
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    let descriptions: HashMap<_, _> = options
+        .iter()
+        .map(|o| {
+            (
+                o.name.as_str(),
+                o.description.as_deref().unwrap_or_default(),
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        descriptions,
+        HashMap::from([
+            (
+                "options.interpolated",
+                "Whether to enable the nginx service."
+            ),
+            ("options.concatenated", "Whether to enable the nginx proxy."),
+            (
+                "options.unknown",
+                "Whether to enable the ${config.service.name} service."
+            ),
+        ])
+    );
+
+    Ok(())
+}
+
+/// Tests that string literals are decoded like Nix does, including escapes and the
+/// indentation of indented strings.
+#[test]
+fn test_string_decoding() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "strings.nix",
+        r#"
+{ lib, ... }:
+{
+  options.quoted = lib.mkOption {
+    description = "'Quoted' \"text\"\nwith \${escapes}";
+    example = lib.literalExpression "{ name = \"value\"; }";
+  };
+  options.indented = lib.mkOption {
+    description = lib.mdDoc ''
+      Users' settings, with '''quotes''' and ''${literal} interpolation.
+        Nested line''\tafter a tab.
+    '';
+    default = {
+      enable = true;
+    };
+    example = lib.literalExpression ''
+      {
+        enable = false;
+      }
+    '';
+  };
+}
+"#,
+    )?;
+
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    let quoted = options.iter().find(|o| o.name == "options.quoted").unwrap();
+    let indented = options
+        .iter()
+        .find(|o| o.name == "options.indented")
+        .unwrap();
+
+    assert_eq!(
+        quoted.description.as_deref(),
+        Some("'Quoted' \"text\"\nwith ${escapes}")
+    );
+    assert_eq!(quoted.example.as_deref(), Some("{ name = \"value\"; }"));
+
+    assert_eq!(
+        indented.description.as_deref(),
+        Some(
+            "Users' settings, with ''quotes'' and ${literal} interpolation.\n  Nested line\tafter a tab.\n"
+        )
+    );
+    assert_eq!(
+        indented.default_value.as_deref(),
+        Some("{\n  enable = true;\n}")
+    );
+    assert_eq!(indented.example.as_deref(), Some("{\n  enable = false;\n}"));
+
+    Ok(())
+}
+
+/// Tests that doc comments preceding options and at the top of files are documented.
+#[test]
+fn test_doc_comments() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "commented.nix",
+        r#"
+/**
+  Options of the *commented* service.
+*/
+{ lib, ... }:
+{
+  options.commented = {
+    # The port to listen on.
+    # Ports below 1024 require root.
+    port = lib.mkOption { type = lib.types.port; };
+
+    /** Enables the service. */
+    enable = lib.mkOption {
+      description = "Whether to run the service.";
+    };
+
+    # Only used for options without a description
+    host = lib.mkOption {
+      description = "The host to bind to.";
+    };
+
+    # Not a doc comment, separated by a blank line
+
+    plain = lib.mkOption { };
+  };
+}
+"#,
+    )?;
+
+    let mut options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    options.sort_by(|a, b| a.name.cmp(&b.name));
+    let descriptions: Vec<_> = options.iter().map(|o| o.description.as_deref()).collect();
+    assert_eq!(
+        descriptions,
+        vec![
+            Some("Whether to run the service.\n\nEnables the service."),
+            Some("The host to bind to."),
+            None,
+            Some("The port to listen on.\nPorts below 1024 require root."),
+        ]
+    );
+    assert_eq!(
+        options[0].file_doc.as_deref(),
+        Some("Options of the *commented* service.")
+    );
+
+    // Only `/** ... */` comments document a file
+    let hashed = r#"
+# Not a module doc comment
+{ lib, ... }:
+{
+  options.hashed.enable = lib.mkEnableOption "hashed";
+}
+"#;
+    let hashed = utils::process_nix_source(hashed, "hashed.nix", &HashMap::new());
+    assert_eq!(hashed.len(), 1);
+    assert_eq!(hashed[0].file_doc, None);
+
+    // Inline flake modules are documented by the doc comment of the flake
+    let flake = r#"
+/** Modules of the example flake. */
+{
+  outputs = { self, ... }: {
+    nixosModules.inline = { lib, ... }: {
+      options.inline.enable = lib.mkEnableOption "Inline module";
+    };
+  };
+}
+"#;
+    let module = imports::ModuleRef {
+        file: "flake.nix".to_string(),
+        attr: Some("nixosModules.inline".to_string()),
+    };
+    let inline = utils::process_module_source(flake, &module, &HashMap::new());
+    assert_eq!(inline.len(), 1);
+    assert_eq!(
+        inline[0].file_doc.as_deref(),
+        Some("Modules of the example flake.")
+    );
+
+    // Options are listed one after the other by default
+    let markdown = generate_markdown(
+        &options,
+        "NixOS Module Options",
+        &MarkdownOptions::default(),
+    )?;
+    assert!(!markdown.contains("Options of the *commented* service."));
+    assert!(markdown.contains("\n## [`options.commented.enable`]"));
+    let html = generate::generate_html(&options, "NixOS Module Options")?;
+    assert!(!html.contains("Options of the <em>commented</em> service."));
+
+    // The file's doc comment introduces its section when grouping by file
+    let markdown = generate_markdown(
+        &options,
+        "NixOS Module Options",
+        &MarkdownOptions {
+            grouping: Grouping::File,
+            ..Default::default()
+        },
+    )?;
+    assert!(markdown.contains(
+        "## `commented.nix`\n\nOptions of the *commented* service.\n\n### [`options.commented.enable`]"
+    ));
+
+    Ok(())
+}
+
+/// Tests that options are located by line, column and end position, and linked with
+/// line ranges.
+#[test]
+fn test_source_spans() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "spans.nix",
+        r#"{ lib, ... }:
+{
+  options.spans = {
+    multi = lib.mkOption {
+      type = lib.types.str;
+      description = "Spans three lines";
+    };
+    single = lib.mkEnableOption "ünïcode";
+  };
+}
+"#,
+    )?;
+
+    let mut options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    options.sort_by(|a, b| a.name.cmp(&b.name));
+    let spans: Vec<_> = options
+        .iter()
+        .map(|o| {
+            (
+                o.line_number,
+                o.column_number,
+                o.end_line_number,
+                o.end_column_number,
+            )
+        })
+        .collect();
+    assert_eq!(spans, vec![(4, 13, 7, 6), (8, 14, 8, 42)]);
+
+    let markdown = generate_markdown(
+        &options,
+        "NixOS Module Options",
+        &MarkdownOptions::default(),
+    )?;
+    assert!(markdown.contains("[`options.spans.multi`](spans.nix#L4-L7)"));
+    assert!(markdown.contains("[`options.spans.single`](spans.nix#L8)"));
+
+    // Columns are counted in characters
+    let line_index = utils::LineIndex::new("ä\nöx");
+    assert_eq!(
+        line_index.position(5),
+        utils::Position { line: 2, column: 2 }
+    );
+
+    Ok(())
+}
+
+/// Tests that the parse cache reuses the options of unchanged files across runs.
+#[test]
+fn test_parse_cache() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    let cache_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "a.nix",
+        r#"{ options.a = lib.mkEnableOption "A"; }"#,
+    )?;
+    create_test_file(
+        temp_dir.path(),
+        "b.nix",
+        r#"{ options.b = lib.mkEnableOption "B"; }"#,
+    )?;
+
+    let run = |replacements: &HashMap<String, String>| -> Result<_, NixDocError> {
+        let parse_cache = cache::ParseCache::open(cache_dir.path(), temp_dir.path());
+        let mut options = collect_options(
+            temp_dir.path(),
+            None,
+            &CollectOptions {
+                parse_cache: Some(&parse_cache),
+                ..CollectOptions::new(&FileFilter::default(), replacements)
+            },
+        )?;
+        parse_cache.save(true)?;
+        options.sort_by(|a, b| a.name.cmp(&b.name));
+        let names: Vec<_> = options.into_iter().map(|o| o.name).collect();
+        Ok((names, parse_cache.stats()))
+    };
+
+    let no_replacements = HashMap::new();
+    assert_eq!(
+        run(&no_replacements)?,
+        (
+            vec!["options.a".to_string(), "options.b".to_string()],
+            (0, 2)
+        )
+    );
+    assert_eq!(
+        run(&no_replacements)?,
+        (
+            vec!["options.a".to_string(), "options.b".to_string()],
+            (2, 0)
+        )
+    );
+
+    // Only changed files are parsed again
+    create_test_file(
+        temp_dir.path(),
+        "b.nix",
+        r#"{ options.c = lib.mkEnableOption "C"; }"#,
+    )?;
+    assert_eq!(
+        run(&no_replacements)?,
+        (
+            vec!["options.a".to_string(), "options.c".to_string()],
+            (1, 1)
+        )
+    );
+
+    // Runs on part of the directory don't evict the entries of the other files
+    let parse_cache = cache::ParseCache::open(cache_dir.path(), temp_dir.path());
+    collect_options(
+        temp_dir.path(),
+        Some(Path::new("a.nix")),
+        &CollectOptions {
+            parse_cache: Some(&parse_cache),
+            ..CollectOptions::new(&FileFilter::default(), &no_replacements)
+        },
+    )?;
+    parse_cache.save(false)?;
+    assert_eq!(parse_cache.stats(), (1, 0));
+    assert_eq!(run(&no_replacements)?.1, (2, 0));
+
+    // Runs on the whole directory drop the entries of changed files
+    create_test_file(
+        temp_dir.path(),
+        "b.nix",
+        r#"{ options.b = lib.mkEnableOption "B"; }"#,
+    )?;
+    assert_eq!(run(&no_replacements)?.1, (1, 1));
+
+    // Replacements change the parsed options
+    let replacements = HashMap::from([("name".to_string(), "value".to_string())]);
+    assert_eq!(run(&replacements)?.1, (0, 2));
+    assert_eq!(run(&no_replacements)?.1, (0, 2));
+
+    Ok(())
+}
+
+/// Tests that watching reports only changed Nix files outside excluded and hidden paths,
+/// and that an in-memory parse cache reuses unchanged files between runs.
+#[test]
+fn test_watch_changes() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use notify::event::{CreateKind, ModifyKind, RemoveKind};
+    use notify::{Event, EventKind};
+
+    let dir = Path::new("/repo");
+    let files = FileFilter {
+        exclude_dirs: vec!["vendor".to_string()],
+        ..Default::default()
+    }
+    .matcher(dir)?;
+    let event = Event::new(EventKind::Modify(ModifyKind::Any))
+        .add_path(PathBuf::from("/repo/modules/a.nix"))
+        .add_path(PathBuf::from("/repo/modules/.a.nix.swp"))
+        .add_path(PathBuf::from("/repo/modules/.hidden.nix"))
+        .add_path(PathBuf::from("/repo/vendor/b.nix"))
+        .add_path(PathBuf::from("/repo/README.md"));
+    assert_eq!(
+        watch::changed_paths(&event, dir, &files),
+        vec![PathBuf::from("/repo/modules/a.nix")]
+    );
+
+    // Removed files no longer exist but still trigger a run
+    let removed = Event::new(EventKind::Remove(RemoveKind::File))
+        .add_path(PathBuf::from("/repo/modules/gone.nix"));
+    assert_eq!(watch::changed_paths(&removed, dir, &files).len(), 1);
+    let created = Event::new(EventKind::Create(CreateKind::File))
+        .add_path(PathBuf::from("/repo/modules/new.nix"));
+    assert_eq!(watch::changed_paths(&created, dir, &files).len(), 1);
+    let accessed = Event::new(EventKind::Access(notify::event::AccessKind::Any))
+        .add_path(PathBuf::from("/repo/modules/a.nix"));
+    assert!(watch::changed_paths(&accessed, dir, &files).is_empty());
+
+    // Only files changed since the previous run are parsed again
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "a.nix",
+        r#"{ options.a = lib.mkEnableOption "A"; }"#,
+    )?;
+    create_test_file(
+        temp_dir.path(),
+        "b.nix",
+        r#"{ options.b = lib.mkEnableOption "B"; }"#,
+    )?;
+
+    let mut parse_cache = cache::ParseCache::in_memory();
+    let run = |parse_cache: &cache::ParseCache| {
+        collect_options(
+            temp_dir.path(),
+            None,
+            &CollectOptions {
+                parse_cache: Some(parse_cache),
+                ..CollectOptions::new(&FileFilter::default(), &HashMap::new())
+            },
+        )
+        .map(|_| parse_cache.stats())
+    };
+    assert_eq!(run(&parse_cache)?, (0, 2));
+
+    create_test_file(
+        temp_dir.path(),
+        "b.nix",
+        r#"{ options.c = lib.mkEnableOption "C"; }"#,
+    )?;
+    parse_cache.next_run(None);
+    assert_eq!(run(&parse_cache)?, (1, 1));
+
+    // Files that weren't reported as changed aren't read again
+    create_test_file(
+        temp_dir.path(),
+        "a.nix",
+        r#"{ options.d = lib.mkEnableOption "D"; }"#,
+    )?;
+    parse_cache.next_run(Some(&["b.nix".to_string()]));
+    assert_eq!(run(&parse_cache)?, (2, 0));
+
+    // Nothing is written to disk
+    parse_cache.save(true)?;
+
+    Ok(())
+}
+
+/// Tests that the preview server serves a self-contained page with live reload, and the
+/// options as JSON filtered by query parameters on top of the command line filters.
+#[test]
+fn test_serve_responses() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "server.nix",
+        r#"
+{ lib, ... }:
+{
+  options.server.enable = lib.mkEnableOption "the server";
+  options.server.name = lib.mkOption {
+    type = lib.types.str;
+    description = "Name of the server";
+  };
+  options.client.enable = lib.mkEnableOption "the client";
+}
+"#,
+    )?;
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+
+    let cli = Cli::parse_from(["program", "--strip-prefix", "--sort"]);
+    let server = serve::Server::bind(&cli, "127.0.0.1:0")?;
+    assert_eq!(server.respond("/api/generation").body, "0");
+    server.update(options.clone())?;
+
+    let page = server.respond("/");
+    assert_eq!(page.status, 200);
+    assert!(page.content_type.starts_with("text/html"));
+    assert!(page.body.contains("server.name"));
+    assert!(page.body.contains("const generation = \"1\";"));
+    assert!(!page.body.contains("<link") && !page.body.contains(" src="));
+
+    let names = |url: &str| -> Vec<String> {
+        let response = server.respond(url);
+        assert_eq!(response.status, 200);
+        serde_json::from_str::<Vec<OptionDoc>>(&response.body)
+            .unwrap()
+            .into_iter()
+            .map(|o| o.name)
+            .collect()
+    };
+    assert_eq!(
+        names("/api/options"),
+        vec!["client.enable", "server.enable", "server.name"]
+    );
+    assert_eq!(
+        names("/api/options?prefix=options.server&type=bool"),
+        vec!["server.enable"]
+    );
+    assert_eq!(
+        names("/api/options?search=Name+of%20the&has_description"),
+        vec!["server.name"]
+    );
+    assert_eq!(
+        names("/api/options?has_default=true"),
+        vec!["client.enable", "server.enable"]
+    );
+
+    assert_eq!(server.respond("/api/options?bogus=1").status, 400);
+    assert_eq!(server.respond("/api/options?has_default=maybe").status, 400);
+    let invalid = server.respond("/api/options?search=%28unclosed");
+    assert_eq!(invalid.status, 400);
+    assert!(invalid.body.starts_with("Invalid search pattern"));
+    assert!(Cli::try_parse_from(["program", "--search", "(unclosed"]).is_err());
+    assert_eq!(server.respond("/missing").status, 404);
+
+    // Updating the options starts a new generation, reloading open pages
+    server.update(options[..1].to_vec())?;
+    assert_eq!(server.respond("/api/generation").body, "2");
+    assert_eq!(names("/api/options").len(), 1);
+
+    Ok(())
+}
+
+/// Tests that the language server completes option paths inside `config` blocks, and
+/// finds the option under the cursor for hover and go-to-definition.
+#[test]
+fn test_lsp_index() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "module.nix",
+        r#"{ lib, ... }:
+{
+  options.services.web.enable = lib.mkEnableOption "the web server";
+  options.services.web.port = lib.mkOption {
+    type = lib.types.port;
+    default = 80;
+    description = "Port to listen on";
+  };
+  options.services.worker.threads = lib.mkOption { type = lib.types.int; };
+  options.users = lib.genAttrs config.names (name: {
+    shell = lib.mkOption { type = lib.types.str; };
+  });
+}
+"#,
+    )?;
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    let index = lsp::Index::new(temp_dir.path(), [options.clone(), options.clone()].concat());
+    assert_eq!(index.len(), 4);
+
+    let text = r#"{ config, lib, ... }:
+{
+  config = lib.mkIf true {
+    services.web.p
+    services = {
+      worker.threads = 4;
+    };
+    users.alice.shell = "fish";
+  };
+  config.services.w
+  other.services.w
+}
+"#;
+    let labels = |needle: &str| -> Vec<String> {
+        let offset = text.find(needle).unwrap() + needle.len();
+        index
+            .complete(text, offset)
+            .into_iter()
+            .map(|item| item.label)
+            .collect()
+    };
+    assert_eq!(labels("services.web.p"), vec!["port"]);
+    assert_eq!(labels("services.web."), vec!["enable", "port"]);
+    assert_eq!(labels("config.services.w"), vec!["web", "worker"]);
+    assert_eq!(labels("threads = 4;"), vec!["web", "worker"]);
+    assert_eq!(labels("users.alice."), vec!["shell"]);
+    assert!(labels("other.services.w").is_empty());
+    assert!(labels("mkIf t").is_empty());
+
+    // The completion of an option describes it
+    let port = &index.complete(text, text.find("web.p").unwrap() + 5)[0];
+    let port_type = options
+        .iter()
+        .find(|o| o.name == "options.services.web.port")
+        .map(|o| o.nix_type.clone());
+    assert_eq!(port.detail, port_type);
+
+    let offset = text.find("threads").unwrap() + 2;
+    let hover = index.hover(text, offset).ok_or("no hover")?;
+    let lsp_types::HoverContents::Markup(contents) = hover.contents else {
+        return Err("unexpected hover contents".into());
+    };
+    assert!(contents
+        .value
+        .starts_with("`options.services.worker.threads`"));
+    assert!(contents.value.contains("**Declared in:** `module.nix:9`"));
+
+    let location = index
+        .definition(text, text.find("shell").unwrap())
+        .ok_or("no definition")?;
+    assert!(location.uri.as_str().ends_with("/module.nix"));
+    assert_eq!(location.range.start.line, 10);
+    assert_eq!(location.range.start.character, 12);
+
+    // Intermediate attribute sets and values aren't options
+    assert!(index
+        .option_at(text, text.find("worker").unwrap())
+        .is_none());
+    assert!(index.option_at(text, text.find("fish").unwrap()).is_none());
+
+    // Positions count UTF-16 code units
+    let position = |line, character| lsp_types::Position { line, character };
+    assert_eq!(lsp::offset_at("a\n\u{1F600}b\n", position(1, 2)), 6);
+    assert_eq!(lsp::offset_at("a\nb", position(0, 10)), 1);
+    assert_eq!(lsp::offset_at("a\nb", position(5, 0)), 3);
+
+    Ok(())
+}
+
+/// Tests that options are ranked by fuzzy matching their names and descriptions, found by
+/// name with or without the `options.` prefix, and rendered for the terminal.
+#[test]
+fn test_search_options() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "search.nix",
+        r#"{ lib, ... }:
+{
+  options.services.nginx.enable = lib.mkEnableOption "the nginx web server";
+  options.services.nginx.virtualHosts = lib.mkOption {
+    type = lib.types.attrsOf lib.types.str;
+    default = { };
+    description = ''
+      Declarative vhost configuration, served by the web server.
+
+      Each attribute is a host name.
+    '';
+  };
+  options.networking.firewall.allowedTCPPorts = lib.mkOption {
+    type = lib.types.listOf lib.types.port;
+    default = [ ];
+    example = [
+      22
+      80
+    ];
+    description = "Ports opened in the firewall.";
+  };
+}
+"#,
+    )?;
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+
+    let names = |query: &str| -> Vec<&str> {
+        search::rank(&options, query)
+            .into_iter()
+            .map(|o| o.name.as_str())
+            .collect()
+    };
+    assert_eq!(
+        names("ngx"),
+        vec![
+            "options.services.nginx.enable",
+            "options.services.nginx.virtualHosts"
+        ]
+    );
+    assert_eq!(names("vhost"), vec!["options.services.nginx.virtualHosts"]);
+    assert_eq!(
+        names("tcp"),
+        vec!["options.networking.firewall.allowedTCPPorts"]
+    );
+    // Every term must match, in the name or the description
+    assert_eq!(
+        names("nginx served"),
+        vec!["options.services.nginx.virtualHosts"]
+    );
+    assert!(names("zzz").is_empty());
+
+    let option =
+        search::find(&options, "networking.firewall.allowedTCPPorts").ok_or("option not found")?;
+    assert_eq!(
+        search::find(&options, "options.networking.firewall.allowedTCPPorts").map(|o| &o.name),
+        Some(&option.name)
+    );
+    assert!(search::find(&options, "networking.firewall").is_none());
+
+    let virtual_hosts =
+        search::find(&options, "services.nginx.virtualHosts").ok_or("option not found")?;
+    assert_eq!(
+        anstream::adapter::strip_str(&search::render_summary(virtual_hosts, 40)).to_string(),
+        "options.services.nginx.virtualHosts  lib.types.attrsOf lib.types.str = { }
+    Declarative vhost configuration,
+    served by the web server.
+"
+    );
+    assert_eq!(
+        anstream::adapter::strip_str(&search::render_option(option, 80)).to_string(),
+        "options.networking.firewall.allowedTCPPorts
+
+Ports opened in the firewall.
+
+Type:        lib.types.listOf lib.types.port
+Default:     [ ]
+Example:     [
+               22
+               80
+             ]
+Declared in: search.nix:13:49
+"
+    );
+
+    Ok(())
+}
+
+/// Tests that queries combine conditions on names, types, fields and files with `AND`,
+/// `OR` and `NOT`, and that invalid queries are reported with their position.
+#[test]
+fn test_query_filter() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    fs::create_dir_all(temp_dir.path().join("modules/net"))?;
+    create_test_file(
+        temp_dir.path(),
+        "modules/services.nix",
+        r#"{ lib, ... }:
+{
+  options.services.web.enable = lib.mkEnableOption "the web server";
+  options.services.web.hosts = lib.mkOption {
+    type = lib.types.listOf lib.types.str;
+    default = [ ];
+    example = [ "example.org" ];
+  };
+  options.services.web.ports = lib.mkOption {
+    type = lib.types.listOf lib.types.port;
+    description = "Ports to listen on";
+  };
+}
+"#,
+    )?;
+    create_test_file(
+        temp_dir.path(),
+        "modules/net/firewall.nix",
+        r#"{ lib, ... }:
+{
+  options.networking.firewall.enable = lib.mkEnableOption "the firewall";
+}
+"#,
+    )?;
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+
+    let names = |query: &str| -> Vec<String> {
+        let cli = Cli::parse_from(["program", "--query", query]);
+        let mut names: Vec<_> = filter_options(&options, &cli)
+            .into_iter()
+            .map(|o| o.name)
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(
+        names("name:services.* AND type:listOf AND NOT has:example OR file:modules/net/**"),
+        vec![
+            "options.networking.firewall.enable",
+            "options.services.web.ports"
+        ]
+    );
+    assert_eq!(
+        names("name:services.* (type:bool OR has:example)"),
+        vec!["options.services.web.enable", "options.services.web.hosts"]
+    );
+    assert_eq!(
+        names("NOT (has:default OR has:description)"),
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        names(r#"description:"listen on" file:modules/*.nix"#),
+        vec!["options.services.web.ports"]
+    );
+    assert_eq!(
+        names("file:*.nix"),
+        Vec::<String>::new(),
+        "`*` doesn't match directories"
+    );
+    assert_eq!(
+        names("type:PORT or default:false and name:*firewall*"),
+        vec![
+            "options.networking.firewall.enable",
+            "options.services.web.ports"
+        ]
+    );
+
+    let error = |query: &str| match query::Query::parse(query) {
+        Ok(_) => panic!("`{}` should be invalid", query),
+        Err(e) => e.to_string(),
+    };
+    assert_eq!(
+        error("name:a AND has:wat"),
+        "Invalid query: unknown field `wat` for `has:`, expected one of description, \
+         default, example, condition, module at column 16\n  name:a AND has:wat\n                 ^"
+    );
+    assert!(error("type:").starts_with("Invalid query: missing value after `type:` at column 6"));
+    assert!(error("foo:bar").contains("unknown field `foo`"));
+    assert!(error("nginx").contains("expected `field:value`, found `nginx`"));
+    assert!(error("(name:a").contains("unclosed `(` at column 1"));
+    assert!(error("name:a)").contains("unexpected `)` without a matching `(` at column 7"));
+    assert!(error("name:a OR").contains("expected a condition such as"));
+    assert!(error("AND name:a").contains("expected a condition before `AND`"));
+    assert!(error(r#"name:"a"#).contains("unterminated `\"`"));
+    assert!(error("").contains("empty query"));
+    assert!(Cli::try_parse_from(["program", "--query", "name:["]).is_err());
+
+    Ok(())
+}
+
+/// Tests that files are selected by gitignore-style patterns and ignore files,
+/// and that hidden directories are only processed when requested.
+#[test]
+fn test_file_patterns() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    for file in [
+        "default.nix",
+        "build.nix",
+        "modules/a.nix",
+        "modules/tests/t.nix",
+        "modules/hidden/keep.nix",
+        "modules/hidden/drop.nix",
+        "generated/g.nix",
+        ".github/h.nix",
+    ] {
+        let path = dir.join(file);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let name = file.trim_end_matches(".nix").replace(['/', '.'], "_");
+        fs::write(
+            path,
+            format!("{{ options.{name} = lib.mkEnableOption \"{name}\"; }}"),
+        )?;
+    }
+    create_test_file(dir, files::IGNORE_FILE, "# Generated modules\ngenerated/\n")?;
+    create_test_file(dir, ".gitignore", "build.nix\n")?;
+
+    let names = |files: &FileFilter| -> Result<Vec<String>, NixDocError> {
+        let mut names: Vec<String> =
+            collect_options(dir, None, &CollectOptions::new(files, &HashMap::new()))?
+                .into_iter()
+                .map(|option| option.name.trim_start_matches("options.").to_string())
+                .collect();
+        names.sort();
+        Ok(names)
+    };
+
+    // Hidden directories and files listed in .nix-options-docignore are skipped
+    assert_eq!(
+        names(&FileFilter::default())?,
+        [
+            "build",
+            "default",
+            "modules_a",
+            "modules_hidden_drop",
+            "modules_hidden_keep",
+            "modules_tests_t"
+        ]
+    );
+
+    // Exclusions are matched like .gitignore lines, where `!` re-includes files
+    let files = FileFilter {
+        exclude: vec![
+            "**/tests/**".to_string(),
+            "modules/hidden/*".to_string(),
+            "!modules/hidden/keep.nix".to_string(),
+        ],
+        gitignore: true,
+        hidden: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        names(&files)?,
+        ["_github_h", "default", "modules_a", "modules_hidden_keep"]
+    );
+
+    // Inclusions restrict processing to matching files or directories
+    let files = FileFilter {
+        include: vec!["modules".to_string(), "/default.nix".to_string()],
+        exclude: vec!["tests/".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(
+        names(&files)?,
+        [
+            "default",
+            "modules_a",
+            "modules_hidden_drop",
+            "modules_hidden_keep"
+        ]
+    );
+
+    // Invalid patterns are rejected when parsing the command line
+    assert!(Cli::try_parse_from(["nix-options-doc", "--exclude", "modules/[z-a]"]).is_err());
+    let cli = Cli::try_parse_from(["nix-options-doc", "--exclude", "!keep.nix", "--hidden"])?;
+    assert_eq!(cli.util.file_filter().exclude, ["!keep.nix"]);
+    assert!(cli.util.file_filter().hidden);
+
+    Ok(())
+}
+
+/// Tests that Markdown documents can be grouped by file, namespace or a mapping of
+/// sections, with a table of contents linking to each section.
+#[test]
+fn test_markdown_grouping() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "web.nix",
+        r#"
+/** The web server. */
+{ lib, ... }:
+{
+  options.services.web.enable = lib.mkEnableOption "the web server";
+  options.services.web.port = lib.mkOption { type = lib.types.port; };
+}
+"#,
+    )?;
+    create_test_file(
+        temp_dir.path(),
+        "misc.nix",
+        r#"
+{ lib, ... }:
+{
+  options.services.worker.threads = lib.mkOption { type = lib.types.int; };
+  options.debug = lib.mkEnableOption "debugging";
+}
+"#,
+    )?;
+    let mut options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    options.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let generate = |grouping| {
+        generate_markdown(
+            &options,
+            "Options",
+            &MarkdownOptions {
+                grouping,
+                ..Default::default()
+            },
+        )
+    };
+
+    // Files are introduced by their doc comments
+    let markdown = generate(Grouping::File)?;
+    assert!(markdown.contains(
+        "## Contents\n\n- [`misc.nix`](#miscnix) (2 options)\n- [`web.nix`](#webnix) (2 options)\n"
+    ));
+    assert!(markdown
+        .contains("\n## `web.nix`\n\nThe web server.\n\n### [`options.services.web.enable`]"));
+
+    // Namespaces are formed by the leading components of option names
+    let markdown = generate(Grouping::Namespace(2))?;
+    assert!(markdown.contains(
+        "- [Other options](#other-options) (1 option)\n\
+         - [`services.web`](#servicesweb) (2 options)\n\
+         - [`services.worker`](#servicesworker) (1 option)\n"
+    ));
+    assert!(markdown.contains("\n## Other options\n\n### [`options.debug`]"));
+    assert!(markdown.contains("\n## `services.web`\n\nThe web server.\n"));
+    let markdown = generate(Grouping::Namespace(1))?;
+    assert!(markdown.contains("- [`services`](#services) (3 options)\n"));
+
+    // Sections of a mapping are matched by prefix or glob, in order
+    let sections = generate::Section::parse_mapping(
+        r#"[
+            { "title": "Web server", "options": ["services.web"], "description": "Serves pages." },
+            { "title": "Services", "options": ["options.services.*"] },
+            { "title": "Unused", "options": ["programs"] }
+        ]"#,
+    )?;
+    let markdown = generate(Grouping::Sections(sections))?;
+    assert!(markdown.contains(
+        "- [Web server](#web-server) (2 options)\n\
+         - [Services](#services) (1 option)\n\
+         - [Other options](#other-options) (1 option)\n\n## Web server\n\nServes pages.\n"
+    ));
+    assert!(!markdown.contains("Unused"));
+    assert!(matches!(
+        generate::Section::parse_mapping(r#"[{ "title": "A" }]"#),
+        Err(NixDocError::GroupMapping(_))
+    ));
+    let cli = Cli::parse_from([
+        "program",
+        "--group-by",
+        "mapping",
+        "--group-mapping",
+        "/nonexistent/sections.json",
+    ]);
+    assert!(matches!(
+        cli.io.markdown_options(),
+        Err(NixDocError::GroupMapping(_))
+    ));
+    assert!(Cli::try_parse_from(["program", "--group-by", "mapping"]).is_err());
+    assert!(
+        generate::Section::parse_mapping(r#"[{ "title": "A", "options": ["[z-a]"] }]"#).is_err()
+    );
+
+    // Duplicate headings get numbered anchors, as on GitHub
+    let mut options = options.clone();
+    options[0].module = Some("nixosModules.a".to_string());
+    options[1].module = Some("nixosModules.a".to_string());
+    options[2].module = Some("nixosModules.b".to_string());
+    options[3].module = Some("nixosModules.b".to_string());
+    let markdown = generate_markdown(
+        &options,
+        "Options",
+        &MarkdownOptions {
+            grouping: Grouping::Namespace(1),
+            ..Default::default()
+        },
+    )?;
+    assert!(markdown.contains(
+        "- [`nixosModules.a`](#nixosmodulesa) (2 options)\n\
+         \x20 - [Other options](#other-options) (1 option)\n\
+         \x20 - [`services`](#services) (1 option)\n\
+         - [`nixosModules.b`](#nixosmodulesb) (2 options)\n\
+         \x20 - [`services`](#services-1) (2 options)\n"
+    ));
+
+    // Without grouping, there is no table of contents
+    assert!(!generate(Grouping::Default)?.contains("## Contents"));
+
+    Ok(())
+}
+
+/// Tests that the table style summarizes options in a table, escaping pipes and
+/// keeping multi-line defaults on a single row, with details in collapsible blocks.
+#[test]
+fn test_markdown_table() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let content = r#"
+{ lib, ... }:
+{
+  options.services.web.mode = lib.mkOption {
+    type = lib.types.enum [ "a|b" "c" ];
+    default = "a|b";
+    description = ''
+      Mode of the server, e.g. `a|b` or `c`. Further details
+      that are only shown in full.
+    '';
+  };
+  options.services.web.settings = lib.mkOption {
+    type = lib.types.attrs;
+    default = {
+      port = 80;
+      host = "<any>";
+    };
+  };
+}
+"#;
+    let temp_dir = TempDir::new()?;
+    create_test_file(temp_dir.path(), "web.nix", content)?;
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+
+    let settings = MarkdownOptions {
+        grouping: Grouping::Namespace(2),
+        style: MarkdownStyle::Table,
+    };
+    let markdown = generate_markdown(&options, "Options", &settings)?;
+
+    assert!(markdown.contains(
+        "\n## `services.web`\n\n\
+         | Option | Type | Default | Description |\n\
+         |--------|------|---------|-------------|\n"
+    ));
+    assert!(markdown.contains(
+        "| [`options.services.web.mode`](web.nix#L4-L11) | `lib.types.enum [ \"a\\|b\" \"c\" ]` | `\"a\\|b\"` \
+         | Mode of the server, e.g. `a\\|b` or `c`. |\n"
+    ));
+    assert!(markdown.contains(
+        "| `lib.types.attrs` | <code>{<br>&nbsp;&nbsp;port = 80;<br>&nbsp;&nbsp;host = \"&lt;any&gt;\";<br>}</code> |  |\n"
+    ));
+
+    // Full details follow the table, unescaped
+    assert!(markdown.contains(
+        "\n<details>\n<summary><code>options.services.web.mode</code></summary>\n\n\
+         Mode of the server, e.g. `a|b` or `c`. Further details\nthat are only shown in full.\n"
+    ));
+    assert_eq!(markdown.matches("\n</details>\n").count(), 2);
+    assert!(!markdown.contains("\n### "));
+
+    // The table of contents still links to the sections
+    assert!(markdown.contains("- [`services.web`](#servicesweb) (2 options)\n"));
+
+    Ok(())
+}
+
+/// Tests that generated documentation replaces the content between the markers of an
+/// existing file, leaving the rest of the file untouched.
+#[test]
+fn test_inject() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let readme = "# Project\n\n## Options\n\n\
+                  <!-- BEGIN nix-options-doc -->\nstale\n<!-- END nix-options-doc -->\n\n\
+                  ## License\n";
+
+    // The title of the generated document is left out
+    let markdown =
+        "# NixOS Module Options\n\n\n## [`options.a`](a.nix#L1)\n\n**Type:** `boolean`\n";
+    let fragment = inject::fragment(markdown, OutputFormat::Markdown)?;
+    assert_eq!(
+        fragment,
+        "## [`options.a`](a.nix#L1)\n\n**Type:** `boolean`"
+    );
+
+    let updated = inject::inject(readme, &fragment)?;
+    assert_eq!(
+        updated,
+        "# Project\n\n## Options\n\n\
+         <!-- BEGIN nix-options-doc -->\n## [`options.a`](a.nix#L1)\n\n**Type:** `boolean`\n\
+         <!-- END nix-options-doc -->\n\n## License\n"
+    );
+    // Injecting again leaves the file unchanged, so up-to-date files pass --check
+    assert_eq!(inject::inject(&updated, &fragment)?, updated);
+
+    // Only the body of HTML documents is injected
+    let html = generate_doc(
+        &[],
+        OutputFormat::Html,
+        false,
+        "Options",
+        &MarkdownOptions::default(),
+    )?;
+    let fragment = inject::fragment(&html, OutputFormat::Html)?;
+    assert!(fragment.starts_with("    <div class=\"footer\">"));
+    assert!(!fragment.contains("<h1>") && !fragment.contains("</body>"));
+    assert!(inject::fragment("[]", OutputFormat::Json).is_err());
+
+    // Markers must appear once each, in order
+    for content in [
+        "no markers",
+        "<!-- BEGIN nix-options-doc -->",
+        "<!-- END nix-options-doc --><!-- BEGIN nix-options-doc -->",
+        "<!-- BEGIN nix-options-doc --><!-- BEGIN nix-options-doc --><!-- END nix-options-doc -->",
+    ] {
+        assert!(inject::inject(content, "docs").is_err(), "{}", content);
+    }
+
+    // --check can't be combined with --watch, and requires an output file
+    assert!(Cli::try_parse_from([
+        "nix-options-doc",
+        "--out",
+        "README.md",
+        "--inject",
+        "--check",
+        "--watch"
+    ])
+    .is_err());
+    assert!(Cli::try_parse_from(["nix-options-doc", "--check"]).is_err());
+    assert!(Cli::try_parse_from(["nix-options-doc", "--out", "README.md", "--check"]).is_ok());
+
+    Ok(())
+}
+
+/// Tests that files are processed in a stable order and that out of date files are
+/// reported with a unified diff.
+#[test]
+fn test_check() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    fs::create_dir(dir.join("m"))?;
+    for file in ["z.nix", "m/b.nix", "a.nix", "m/a.nix"] {
+        let name = file.trim_end_matches(".nix").replace('/', "_");
+        create_test_file(
+            dir,
+            file,
+            &format!("{{ options.{name} = lib.mkEnableOption \"{name}\"; }}"),
+        )?;
+    }
+
+    // Files are processed in the order of their paths, regardless of the file system
+    let collect = || {
+        collect_options(
+            dir,
+            None,
+            &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+        )
+    };
+    let names: Vec<String> = collect()?.into_iter().map(|option| option.name).collect();
+    assert_eq!(
+        names,
+        ["options.a", "options.m_a", "options.m_b", "options.z"]
+    );
+
+    let markdown = generate_doc(
+        &collect()?,
+        OutputFormat::Markdown,
+        false,
+        "Options",
+        &MarkdownOptions::default(),
+    )?;
+    assert_eq!(check::unified_diff(&markdown, &markdown, "docs.md"), "");
+
+    // Changed lines are shown with their context
+    let current = markdown.replace("Whether to enable m_b.", "Outdated.");
+    let diff = check::unified_diff(&current, &markdown, "docs.md");
+    assert!(diff.starts_with("--- a/docs.md\n+++ b/docs.md\n@@ "));
+    assert!(diff.contains("\n-Outdated.\n+Whether to enable m_b.\n"));
+    assert!(diff.contains("\n ## [`options.m_b`](m/b.nix#L1)\n"));
+    assert!(!diff.contains("options.a`"));
+
+    // Missing files are shown as entirely added, absolute paths aren't prefixed
+    let diff = check::unified_diff("", "a\nb\n", "/tmp/docs.md");
+    assert_eq!(
+        diff,
+        "--- /tmp/docs.md\n+++ /tmp/docs.md\n@@ -0,0 +1,2 @@\n+a\n+b\n"
+    );
+
+    // Colors are only added to changed lines and headers
+    let colored = check::colorize("@@ -1 +1 @@\n-a\n+b\n c\n");
+    assert_eq!(
+        anstream::adapter::strip_str(&colored).to_string(),
+        "@@ -1 +1 @@\n-a\n+b\n c\n"
+    );
+    assert!(colored.ends_with("\n c\n"));
+
+    Ok(())
+}
+
+/// Tests that options generated with `genAttrs`, `mapAttrs` and `listToAttrs` are expanded
+/// per generated name, with a placeholder for names that aren't known statically.
+#[test]
+fn test_generated_options() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+    create_test_file(
+        temp_dir.path(),
+        "generated.nix",
+        r#"
+{ lib, config, ... }:
+let
+  backends = [ "postgres" "sqlite" ];
+in
+{
+  options.backends = lib.genAttrs backends (name: {
+    enable = lib.mkEnableOption "the ${name} backend";
+  });
+  options.ports = lib.mapAttrs (name: port: lib.mkOption {
+    type = lib.types.port;
+    description = "Port of ${name}, ${port} by default";
+  }) { http = "80"; https = "443"; };
+  options.users = lib.listToAttrs (map (user: lib.nameValuePair user (lib.mkOption {
+    description = "Settings of ${user}";
+  })) [ "alice" "bob" ]);
+  options.extra = lib.listToAttrs [
+    { name = "first"; value = lib.mkEnableOption "first"; }
+  ];
+  options.dynamic = lib.genAttrs config.names (name: lib.mkEnableOption "${name}");
+}
+"#,
+    )?;
+
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    let descriptions: HashMap<_, _> = options
+        .iter()
+        .map(|o| {
+            (
+                o.name.as_str(),
+                o.description.as_deref().unwrap_or_default(),
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        descriptions,
+        HashMap::from([
+            (
+                "options.backends.postgres.enable",
+                "Whether to enable the postgres backend."
+            ),
+            (
+                "options.backends.sqlite.enable",
+                "Whether to enable the sqlite backend."
+            ),
+            ("options.ports.http", "Port of http, 80 by default"),
+            ("options.ports.https", "Port of https, 443 by default"),
+            ("options.users.alice", "Settings of alice"),
+            ("options.users.bob", "Settings of bob"),
+            ("options.extra.first", "Whether to enable first."),
+            ("options.dynamic.<name>", "Whether to enable <name>."),
+        ])
+    );
+
+    Ok(())
+}
+
+/// Tests variable replacement functionality in option names and descriptions.
+#[test]
+fn test_variable_replacements() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+
+    // Create a file with variable interpolation
+    let content = r#"
+{
+  options.${namespace}.hardware.bluetooth = {
+    enable = lib.mkEnableOption "Enable ${namespace} bluetooth";
+  };
+  
+  options.${system}.networking = {
+    enable = lib.mkEnableOption "Enable networking for ${system}";
+  };
+}
+"#;
+    create_test_file(temp_dir.path(), "config.nix", content)?;
+
+    // Set up replacements
+    let mut replacements = HashMap::new();
+    replacements.insert("namespace".to_string(), "snowflake".to_string());
+    replacements.insert("system".to_string(), "x86_64-linux".to_string());
+
+    let options = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &replacements),
+    )?;
+
+    // Check if options contain the replaced values
+    let bluetooth_options: Vec<_> = options
+        .iter()
+        .filter(|o| o.name.contains("bluetooth"))
+        .collect();
+
+    let networking_options: Vec<_> = options
+        .iter()
+        .filter(|o| o.name.contains("networking"))
+        .collect();
+
+    if !bluetooth_options.is_empty() {
+        let bluetooth_opt = &bluetooth_options[0];
+        assert!(bluetooth_opt.name.contains("snowflake"));
+        assert!(!bluetooth_opt.name.contains("${namespace}"));
+
+        // Check if description also had replacements
+        if let Some(desc) = &bluetooth_opt.description {
+            assert!(desc.contains("snowflake"));
+            assert!(!desc.contains("${namespace}"));
+        }
+    }
+
+    if !networking_options.is_empty() {
+        let networking_opt = &networking_options[0];
+        assert!(networking_opt.name.contains("x86_64-linux"));
+        assert!(!networking_opt.name.contains("${system}"));
+
+        // Check if description also had replacements
+        if let Some(desc) = &networking_opt.description {
+            assert!(desc.contains("x86_64-linux"));
+            assert!(!desc.contains("${system}"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Tests that replacements for dotted variables apply to interpolations in descriptions.
+#[test]
+fn test_dotted_variable_replacements() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let content = r#"
+{ lib, ... }:
+{
+  options.services.host = {
+    enable = lib.mkEnableOption "the ${cfg.ns} thing";
+    name = lib.mkOption {
+      type = lib.types.str;
+      description = "Host ${cfg.ns} here";
+    };
+  };
+}
+"#;
+    let mut replacements = HashMap::new();
+    replacements.insert("cfg.ns".to_string(), "snow".to_string());
+
+    let options = utils::process_nix_source(content, "host.nix", &replacements);
+    let description = |name: &str| {
+        options
+            .iter()
+            .find(|option| option.name == name)
+            .and_then(|option| option.description.clone())
+    };
+    assert_eq!(
+        description("options.services.host.enable").as_deref(),
+        Some("Whether to enable the snow thing.")
+    );
+    assert_eq!(
+        description("options.services.host.name").as_deref(),
+        Some("Host snow here")
+    );
+
+    Ok(())
+}
+
+/// Tests error handling for invalid paths and malformed files.
+#[test]
+fn test_error_handling() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let temp_dir = TempDir::new()?;
+
+    // Test non-existent path
+    let non_existent = temp_dir.path().join("non-existent");
+    let result = collect_options(
+        &non_existent,
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    );
+    assert!(result.is_err(), "Non-existent paths should return an error");
+
+    // Create a file with invalid Nix syntax
+    let invalid_content = r#"
+{
+  options.test = {
+    # Missing closing brace
+    invalid = lib.mkEnableOption "Invalid option"
+  ;
+}
+"#;
+    create_test_file(temp_dir.path(), "invalid.nix", invalid_content)?;
+
+    // File processing should continue even with parse errors
+    let result = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    );
+    assert!(
+        result.is_ok(),
+        "Processing should continue even with parse errors"
+    );
+
+    // Create a file with valid Nix syntax alongside the invalid one
+    let valid_content = r#"
+{
+  options.test.valid = {
+    enable = lib.mkEnableOption "Valid option";
+  };
+}
+"#;
+    create_test_file(temp_dir.path(), "valid.nix", valid_content)?;
+
+    // We should still find the valid option
+    // even when there's an invalid file in the same directory
+    let options_with_valid = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    )?;
+    assert!(
+        !options_with_valid.is_empty(),
+        "Valid options should be found even when some files have errors"
+    );
+
+    // Test a directory with .nix extension
+    let dir_with_nix_ext = temp_dir.path().join("not-readable.nix");
+    std::fs::create_dir(&dir_with_nix_ext)?;
+
+    // Should not error out even with the unreadable "file"
+    let result = collect_options(
+        temp_dir.path(),
+        None,
+        &CollectOptions::new(&FileFilter::default(), &HashMap::new()),
+    );
     assert!(
         result.is_ok(),
         "Should handle directories with .nix extensions"
@@ -5,11 +5,11 @@
 
 use regex::Regex;
 use std::collections::HashMap;
-use std::fs;
 use textwrap::dedent;
 
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
+use crate::imports::{self, ModuleRef};
 use crate::parser;
 use crate::OptionDoc;
 
@@ -102,157 +102,257 @@ pub fn clean_description(text: &str) -> String {
     convert_admonitions(&cleaned)
 }
 
-/// Extracts the actual content from Nix literalExpression wrappers.
+/// Removes the indentation of the source text of a multi-line expression.
 ///
 /// # Arguments
-/// - `value`: The raw value string potentially containing literalExpression wrappers.
+/// - `text`: The source text of the expression.
+/// - `column`: The 0-based column the expression starts at, i.e. the indentation of its first line.
 ///
 /// # Returns
-/// A string with the literalExpression wrapper removed, exposing just the content.
-pub fn clean_literal_expr(value: &str) -> String {
-    // Remove common wrappers
-    let value = value.trim();
-
-    // Handle lib.literalExpression patterns
-    if value.starts_with("lib.literalExpression") || value.starts_with("literalExpression") {
-        // Simple approach: extract the content between the string delimiters
-
-        // For indented string literals: ''...''
-        if let Some(start_pos) = value.find("''") {
-            let start = start_pos + 2; // Skip past the opening ''
-
-            // Find the closing '' - we assume the last '' in the string
-            // This works because literalExpression takes a single string argument
-            if let Some(end_pos) = value.rfind("''") {
-                if end_pos > start {
-                    return value[start..end_pos].trim().to_string();
-                }
-            }
-        }
-        // For regular quoted strings: "..."
-        else if let Some(start_pos) = value.find('"') {
-            let start = start_pos + 1; // Skip past the opening "
-
-            // Find the closing " - we assume the last " in the string
-            // This is a simplification but works for most common cases
-            if let Some(end_pos) = value.rfind('"') {
-                if end_pos > start {
-                    return value[start..end_pos].trim().to_string();
-                }
-            }
-        }
+/// The expression's text with the indentation common to all lines removed.
+pub fn dedent_expression(text: &str, column: usize) -> String {
+    let indented = format!("{}{}", " ".repeat(column), text);
+    dedent(&indented).trim().to_string()
+}
+
+/// A 1-based position in a source file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    /// The line number
+    pub line: usize,
+
+    /// The column number, counted in characters
+    pub column: usize,
+}
+
+/// Index of the starts of the lines of a source file, for converting offsets into
+/// line and column numbers without scanning the file for each conversion.
+pub struct LineIndex<'a> {
+    /// The source text
+    text: &'a str,
+
+    /// The byte offsets at which each line starts
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    /// Creates the line index of a source file.
+    ///
+    /// # Arguments
+    /// - `text`: The source text of the file.
+    ///
+    /// # Returns
+    /// The index of the starts of the file's lines.
+    pub fn new(text: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(pos, _)| pos + 1))
+            .collect();
+        Self { text, line_starts }
     }
 
-    // If we couldn't extract inner content, return the original
-    value.to_string()
+    /// Returns the source text of the file.
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// Converts a byte offset into a position.
+    ///
+    /// # Arguments
+    /// - `offset`: The byte offset in the source text.
+    ///
+    /// # Returns
+    /// The 1-based line and column of the offset.
+    pub fn position(&self, offset: usize) -> Position {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let line_start = self.line_starts[line - 1];
+        let column = self
+            .text
+            .get(line_start..offset)
+            .map_or(offset - line_start, |text| text.chars().count());
+        Position {
+            line,
+            column: column + 1,
+        }
+    }
 }
 
-/// Custom dedent function that preserves the first line and only dedents subsequent lines.
+/// Computes the path of a file relative to the base directory for use in documentation.
 ///
 /// # Arguments
-/// - `text`: The text to dedent, potentially with inconsistent indentation.
+/// - `file_path`: Path to the file.
+/// - `dir`: The base directory for calculating relative paths.
 ///
 /// # Returns
-/// A string with consistent indentation where the first line is preserved as-is.
-pub fn custom_dedent(text: &str) -> String {
-    // Split by first line break
-    if let Some(pos) = text.find('\n') {
-        let first_line = &text[..pos];
-        let rest = &text[pos..];
-
-        // Dedent only the remaining text
-        format!("{}{}", first_line, dedent(rest))
-    } else {
-        // No line breaks, return as is
-        text.to_string()
+/// The relative path as a string, or the full path if it is not inside `dir`.
+pub fn relative_path(file_path: &Path, dir: &Path) -> String {
+    match file_path.strip_prefix(dir) {
+        Ok(rel_path) => rel_path.to_string_lossy().into_owned(),
+        Err(e) => {
+            log::warn!(
+                "Error getting relative path for {}: {}",
+                file_path.display(),
+                e
+            );
+            file_path.to_string_lossy().into_owned()
+        }
     }
 }
 
-/// Determines if a directory entry represents a hidden directory.
+/// Process the source of a single Nix file to extract option documentation.
 ///
 /// # Arguments
-/// - `entry`: The directory entry to check.
+/// - `content`: The source text of the Nix file.
+/// - `relative_path`: The path of the file relative to the base directory, used in the documentation.
+/// - `replacements`: Variable replacements to apply during parsing.
 ///
 /// # Returns
-/// True if the directory is hidden (starts with a dot), false otherwise.
-fn is_hidden(entry: &walkdir::DirEntry) -> bool {
-    entry.file_name().to_string_lossy().starts_with('.')
+/// A vector of OptionDoc structs representing the options found in the source.
+pub fn process_nix_source(
+    content: &str,
+    relative_path: &str,
+    replacements: &HashMap<String, String>,
+) -> Vec<OptionDoc> {
+    process_source(content, relative_path, None, replacements)
 }
 
-/// Determines if a file should be processed based on extension and exclusion criteria.
+/// Extracts the options of a file, or of the module at an attribute path inside it, tagging
+/// them with the doc comment at the top of the file and reporting its syntax errors.
 ///
 /// # Arguments
-/// - `entry`: The directory entry representing the file to check.
-/// - `exclude_paths`: A list of paths to exclude from processing.
+/// - `content`: The source text of the Nix file.
+/// - `relative_path`: The path of the file relative to the base directory, used in the documentation.
+/// - `attr`: The attribute path of an inline module inside the file, or None for the whole file.
+/// - `replacements`: Variable replacements to apply during parsing.
 ///
 /// # Returns
-/// True if the file should be processed, false if it should be skipped.
-pub fn should_process_file(entry: &walkdir::DirEntry, exclude_paths: &[PathBuf]) -> bool {
-    // Skip excluded paths
-    if exclude_paths
-        .iter()
-        .any(|excl| entry.path().starts_with(excl))
-    {
-        log::debug!("Skipping excluded path: {}", entry.path().display());
-        return false;
-    }
+/// A vector of OptionDoc structs representing the options found in the file or module.
+fn process_source(
+    content: &str,
+    relative_path: &str,
+    attr: Option<&str>,
+    replacements: &HashMap<String, String>,
+) -> Vec<OptionDoc> {
+    let parse = rnix::Root::parse(content);
+    let line_index = LineIndex::new(content);
+    report_parse_errors(&parse, relative_path, &line_index);
+
+    let root = parse.syntax();
+    let file_doc = parser::file_doc_comment(&root);
 
-    // Skip hidden files, non-files, and non-nix files
-    if is_hidden(entry)
-        || !entry.file_type().is_file()
-        || entry.path().extension().is_none_or(|ext| ext != "nix")
-    {
-        return false;
+    let node = match attr {
+        Some(attr) => match imports::find_attr(&root, attr) {
+            Some(node) => node,
+            None => return Vec::new(),
+        },
+        None => root,
+    };
+
+    // Parse the file and get options
+    match parser::visit_node(
+        &node,
+        relative_path,
+        "",
+        &parser::Scope::new(replacements),
+        &line_index,
+    ) {
+        Ok(mut file_options) => {
+            for option in &mut file_options {
+                option.file_doc.clone_from(&file_doc);
+            }
+            file_options
+        }
+        Err(e) => {
+            match attr {
+                Some(attr) => log::error!("Error parsing module {}#{}: {}", relative_path, attr, e),
+                None => log::error!("Error parsing file {}: {}", relative_path, e),
+            }
+            Vec::new()
+        }
     }
+}
+
+/// Logs the syntax errors of a Nix file with their positions.
+///
+/// # Arguments
+/// - `parse`: The result of parsing the file.
+/// - `relative_path`: The path of the file, used in the messages.
+/// - `line_index`: The line index of the file.
+fn report_parse_errors(
+    parse: &rnix::Parse<rnix::Root>,
+    relative_path: &str,
+    line_index: &LineIndex,
+) {
+    use rnix::parser::ParseError;
 
-    true
+    for error in parse.errors() {
+        let offset = match error {
+            ParseError::Unexpected(range)
+            | ParseError::UnexpectedExtra(range)
+            | ParseError::UnexpectedWanted(_, range, _)
+            | ParseError::UnexpectedDoubleBind(range)
+            | ParseError::DuplicatedArgs(range, _) => range.start().into(),
+            _ => line_index.text().len(),
+        };
+        let position = line_index.position(offset);
+        log::warn!(
+            "{}:{}:{}: syntax error: {}",
+            relative_path,
+            position.line,
+            position.column,
+            error
+        );
+    }
 }
 
-/// Process a single Nix file to extract option documentation.
+/// Extracts the options of a module of the import graph from the source of its file.
 ///
 /// # Arguments
-/// - `file_path`: Path to the Nix file to process.
-/// - `dir`: The base directory for calculating relative paths.
-/// - `replacements`: Variable replacements to apply during parsing.
+/// - `content`: The content of the file containing the module.
+/// - `module`: The module, either the whole file or an inline flake module.
+/// - `replacements`: A map of variable replacements for dynamic segments.
 ///
 /// # Returns
-/// A vector of OptionDoc structs representing the options found in the file.
-pub fn process_nix_file(
-    file_path: &Path,
-    dir: &Path,
+/// A vector of OptionDoc structs representing the module's options.
+pub fn process_module_source(
+    content: &str,
+    module: &ModuleRef,
     replacements: &HashMap<String, String>,
 ) -> Vec<OptionDoc> {
-    match fs::read_to_string(file_path) {
-        Ok(content) => {
-            let parse = rnix::Root::parse(&content);
-            let relative_path = match file_path.strip_prefix(dir) {
-                Ok(rel_path) => rel_path.to_string_lossy().into_owned(),
-                Err(e) => {
-                    log::warn!(
-                        "Error getting relative path for {}: {}",
-                        file_path.display(),
-                        e
-                    );
-                    file_path.to_string_lossy().into_owned()
-                }
-            };
-
-            // Parse the file and get options
-            match parser::visit_node(&parse.syntax(), &relative_path, "", replacements, &content) {
-                Ok(file_options) => file_options,
-                Err(e) => {
-                    log::error!("Error parsing file {}: {}", file_path.display(), e);
-                    Vec::new()
-                }
-            }
-        }
-        Err(e) => {
-            log::error!("Error reading file {}: {}", file_path.display(), e);
-            Vec::new()
+    process_source(content, &module.file, module.attr.as_deref(), replacements)
+}
+
+/// Splits a repository URL of the form `<url>//<subdir>` into the URL and subdirectory.
+///
+/// # Arguments
+/// - `path`: The repository URL, optionally suffixed with `//` and a path inside the repository.
+///
+/// # Returns
+/// A tuple of the repository URL and the subdirectory, if one was specified.
+pub fn split_subdir(path: &str) -> (&str, Option<&str>) {
+    // Skip the `//` following the URL scheme (e.g. `https://`)
+    let search_start = path.find("://").map_or(0, |pos| pos + 3);
+
+    match path[search_start..].find("//") {
+        Some(pos) => {
+            let split = search_start + pos;
+            let subdir = path[split + 2..].trim_matches('/');
+            (&path[..split], (!subdir.is_empty()).then_some(subdir))
         }
+        None => (path, None),
     }
 }
 
+/// Parses the regular expression searched for in option names and descriptions.
+///
+/// # Arguments
+/// - `pattern`: The regular expression given with `--search`.
+///
+/// # Returns
+/// The compiled regular expression, or an error describing why it is invalid.
+pub fn parse_search(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| format!("Invalid search pattern: {}", e))
+}
+
 /// Parses a string in the format key=value and returns the separate components.
 ///
 /// # Arguments
@@ -0,0 +1,103 @@
+//! The watch module regenerates documentation when the documented files change.
+//!
+//! It watches a directory recursively and reports batches of changed Nix files,
+//! skipping the same excluded, ignored and hidden files as the initial run.
+
+use crate::error::NixDocError;
+use crate::files::{FileFilter, FileMatcher};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How long to wait for further changes before reporting a batch, since editors
+/// and tools usually touch several files (or the same file several times) at once.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watches a directory for changes to Nix files until the watcher fails.
+///
+/// # Arguments
+/// - `dir`: The directory to watch recursively.
+/// - `files`: Selects the files whose changes are reported.
+/// - `on_change`: Called with the changed files after each batch of changes.
+///
+/// # Returns
+/// An error if the directory couldn't be watched or the watcher stopped.
+pub fn watch<F>(dir: &Path, files: &FileFilter, mut on_change: F) -> Result<(), NixDocError>
+where
+    F: FnMut(&[PathBuf]),
+{
+    // Events carry absolute paths, so they must be matched against the absolute directory
+    let dir = dir.canonicalize()?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(NixDocError::watch_error)?;
+    watcher
+        .watch(&dir, RecursiveMode::Recursive)
+        .map_err(NixDocError::watch_error)?;
+
+    loop {
+        let mut changed = Vec::new();
+        let mut result = receiver.recv().map_err(NixDocError::watch_error)?;
+
+        // Ignore files may have changed since the previous batch
+        let matcher = files.matcher(&dir)?;
+
+        // Gather the events following the first one into a single batch
+        loop {
+            match result {
+                Ok(event) => {
+                    for path in changed_paths(&event, &dir, &matcher) {
+                        if !changed.contains(&path) {
+                            changed.push(path);
+                        }
+                    }
+                }
+                Err(e) => log::warn!("Error while watching for changes: {}", e),
+            }
+            result = match receiver.recv_timeout(DEBOUNCE) {
+                Ok(result) => result,
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(e) => return Err(NixDocError::watch_error(e)),
+            };
+        }
+
+        if !changed.is_empty() {
+            for path in &changed {
+                log::debug!("Changed: {}", path.display());
+            }
+            on_change(&changed);
+        }
+    }
+}
+
+/// Determines which paths of a file system event refer to Nix files that should be
+/// processed, or to ignore files changing which files are processed.
+///
+/// # Arguments
+/// - `event`: The file system event.
+/// - `dir`: The absolute path of the watched directory.
+/// - `files`: Selects the files to process, relative to `dir`.
+///
+/// # Returns
+/// The paths of the created, modified or removed files of the event.
+pub fn changed_paths(event: &Event, dir: &Path, files: &FileMatcher) -> Vec<PathBuf> {
+    if !matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) | EventKind::Any
+    ) {
+        return Vec::new();
+    }
+
+    // Removed files no longer exist, so every path is treated as a file
+    event
+        .paths
+        .iter()
+        .filter(|path| {
+            path.strip_prefix(dir).is_ok_and(|relative_path| {
+                files.is_selected(relative_path, true) || files.is_ignore_file(relative_path)
+            })
+        })
+        .cloned()
+        .collect()
+}